@@ -1,4 +1,4 @@
-use crate::io_device::IODevice;
+use crate::io_device::{IODevice, LongIODevice};
 use crate::memory;
 use std::ops::RangeInclusive;
 
@@ -31,6 +31,12 @@ impl AddressDecoder {
     }
 }
 
+impl Default for AddressDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl IODevice for AddressDecoder {
     fn get(&self, addr: u16) -> u8 {
         match self.get_device(addr) {
@@ -47,12 +53,9 @@ impl IODevice for AddressDecoder {
     }
 
     fn put(&mut self, addr: u16, value: u8) {
-        match self.get_device_mut(addr) {
-            Some(dm) => {
-                let offset = addr - dm.range.start();
-                dm.device.put(offset, value);
-            }
-            None => ()
+        if let Some(dm) = self.get_device_mut(addr) {
+            let offset = addr - dm.range.start();
+            dm.device.put(offset, value);
         }
     }
 
@@ -61,10 +64,63 @@ impl IODevice for AddressDecoder {
     }
 }
 
+struct LongDeviceMapping {
+    range: RangeInclusive<u32>,
+    device: Box<dyn IODevice>,
+}
+
+/// AddressDecoder for the 65816's 24-bit bus. Devices see addresses relative to the start of their range, so
+/// the same RAM and ROM devices work in any bank. Ranges bigger than 64k mirror the device across them.
+pub struct LongAddressDecoder {
+    ranges: Vec<LongDeviceMapping>,
+}
+
+impl LongAddressDecoder {
+    pub fn new() -> Self {
+        Self {
+            ranges: Vec::new(),
+        }
+    }
+
+    pub fn add_device(&mut self, range: RangeInclusive<u32>, device: Box<dyn IODevice>) {
+        self.ranges.push(LongDeviceMapping { range, device })
+    }
+
+    fn get_device(&self, addr: u32) -> Option<&LongDeviceMapping> {
+        self.ranges.iter().find(|dm| dm.range.contains(&addr))
+    }
+
+    fn get_device_mut(&mut self, addr: u32) -> Option<&mut LongDeviceMapping> {
+        self.ranges.iter_mut().find(|dm| dm.range.contains(&addr))
+    }
+}
+
+impl Default for LongAddressDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LongIODevice for LongAddressDecoder {
+    fn get_long(&self, addr: u32) -> u8 {
+        match self.get_device(addr) {
+            Some(dm) => dm.device.get((addr - dm.range.start()) as u16),
+            None => 0
+        }
+    }
+
+    fn put_long(&mut self, addr: u32, value: u8) {
+        if let Some(dm) = self.get_device_mut(addr) {
+            let offset = (addr - dm.range.start()) as u16;
+            dm.device.put(offset, value);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::address_decoder::AddressDecoder;
-    use crate::io_device::IODevice;
+    use crate::address_decoder::{AddressDecoder, LongAddressDecoder};
+    use crate::io_device::{IODevice, LongIODevice};
     use crate::memory::ram::RAM;
 
     #[test]
@@ -146,4 +202,30 @@ mod tests {
         decoder.put_hl(0x40, 0x00, 255);
         assert_eq!(decoder.get(0x4000), 0);
     }
+
+    #[test]
+    fn long_get_put() {
+        let mut decoder = LongAddressDecoder::new();
+        decoder.add_device(
+            0x7E0000..=0x7FFFFF,
+            Box::new(RAM::<0x10000>::new(None)),
+        );
+        decoder.put_long(0x7E1234, 255);
+        assert_eq!(decoder.get_long(0x7E1234), 255);
+        assert_eq!(decoder.get_long(0x001234), 0);
+
+        // A 64k device is mirrored across the 128k it's mapped into
+        assert_eq!(decoder.get_long(0x7F1234), 255);
+        decoder.put_long(0x001234, 255);
+        assert_eq!(decoder.get_long(0x001234), 0);
+    }
+
+    #[test]
+    fn long_from_short() {
+        // Anything with 16-bit addresses sees the same 64k in every bank
+        let mut ram = RAM::<0x10000>::new(None);
+        ram.put_long(0x121234, 255);
+        assert_eq!(ram.get(0x1234), 255);
+        assert_eq!(ram.get_long(0x341234), 255);
+    }
 }
@@ -0,0 +1,52 @@
+use crate::cpu::cpu_6502::{CPU6502, ExecutionMode, ExecutionState};
+use crate::io_device::IODevice;
+use crate::memory::ram::RAM;
+use std::time::Instant;
+
+/// A loop mixing most of the addressing modes, a read-modify-write, a subroutine call and a taken branch:
+///
+/// ```text
+/// $0200  loop: LDA $10,X
+/// $0202        ADC #$01
+/// $0204        STA $0300,X
+/// $0207        INC $20
+/// $0209        JSR sub
+/// $020C        INX
+/// $020D        BNE loop
+/// $020F        JMP loop
+/// $0212  sub:  RTS
+/// ```
+const PROGRAM: [u8; 0x13] = [
+    0xb5, 0x10,
+    0x69, 0x01,
+    0x9d, 0x00, 0x03,
+    0xe6, 0x20,
+    0x20, 0x12, 0x02,
+    0xe8,
+    0xd0, 0xf1,
+    0x4c, 0x00, 0x02,
+    0x60,
+];
+
+/// Run the benchmark program for a number of cycles as fast as the core can go, and report how fast that is
+/// in emulated MHz.
+pub fn run(cycles: usize, mode: ExecutionMode) {
+    let mut ram = RAM::<0x10000>::new(None);
+    for (offset, byte) in PROGRAM.iter().enumerate() {
+        ram.put(0x0200 + offset as u16, *byte);
+    }
+    ram.put(0xFFFC, 0x00);
+    ram.put(0xFFFD, 0x02);
+
+    let mut cpu = CPU6502::with_mode(mode);
+    cpu.reset();
+
+    let start = Instant::now();
+    let (ran, state) = cpu.run_for(&mut ram, cycles);
+    let elapsed = start.elapsed().as_secs_f64();
+
+    if state != ExecutionState::Running {
+        println!("Stopped: {state:?}");
+    }
+    println!("Ran {ran} cycles ({mode:?}) in {elapsed:.3}s: {:.2} emulated MHz", ran as f64 / elapsed / 1_000_000.0);
+}
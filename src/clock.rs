@@ -15,7 +15,7 @@ impl Clock {
     pub fn start<F>(&self, mut callback: F)
     where F: FnMut() -> bool {
         for _ in self.oscillator.iter() {
-            if callback() == false {
+            if !callback() {
                 break;
             }
         };
@@ -1,8 +1,12 @@
 use crate::io_device::IODevice;
 use crate::cpu::status_register::StatusRegister;
+use std::collections::HashSet;
 
-use super::opcodes::{Instruction, InstructionState, find_instruction};
+use super::io_port::{IOPort, VariantBus};
+use super::opcodes::{Instruction, InstructionState, Interrupt, find_instruction, interrupt_sequence};
+use super::variant::Variant;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Registers {
     pub a: u8,
     pub x: u8,
@@ -18,13 +22,93 @@ pub struct Registers {
     pub data: u8,
 }
 
+/// What happened on a call to CPU6502::tick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecutionState {
+    /// A cycle ran and the CPU can carry on.
+    Running,
+    /// The CPU has locked up on a JAM or STP opcode and won't run again until it's reset.
+    Halted { pc: u16, opcode: u8 },
+    /// WAI has stopped the CPU until IRQ or NMI is asserted, and nothing was run. PC is the instruction it will
+    /// carry on from.
+    Waiting { pc: u16 },
+    /// The opcode at PC isn't one we know how to run. It was read, but PC hasn't moved past it.
+    UnknownOpcode { pc: u16, opcode: u8 },
+    /// PC is at a breakpoint and nothing was run. The next tick carries on from it.
+    Breakpoint { pc: u16 },
+    /// RDY is low and the cycle would have read, so the CPU held where it was. The cycle still passed, and the
+    /// next tick tries it again.
+    Stalled { pc: u16 },
+}
+
+/// How CPU6502 runs instructions when it's stepped or run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecutionMode {
+    /// A cycle at a time, making every bus access the hardware makes.
+    CycleAccurate,
+    /// A whole instruction at a time, making only the accesses that matter to the result. Cycle counts are
+    /// still those of the hardware, but everything within an instruction, interrupt lines included, happens at
+    /// once.
+    Functional,
+}
+
+/// What a call to CPU6502::step executed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    /// The opcode that was fetched. For a hardware interrupt this is the opcode it discarded.
+    pub opcode: u8,
+    /// Where the instruction started.
+    pub address: u16,
+    /// How many cycles the step ran for.
+    pub cycles: usize,
+    /// Set when the step ran an interrupt sequence, including BRK.
+    pub interrupt: Option<Interrupt>,
+}
+
+/// The instruction the CPU is partway through, as CPU6502::in_flight reports it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InFlight {
+    /// The opcode that was fetched. For a hardware interrupt this is the opcode it discarded.
+    pub opcode: u8,
+    /// Where the instruction started.
+    pub address: u16,
+    /// How many of its cycles have run, opcode fetch included.
+    pub cycle: usize,
+    /// Set when it's an interrupt sequence, including BRK.
+    pub interrupt: Option<Interrupt>,
+}
+
 pub struct CPU6502 {
     pub registers: Registers,
-    pub instruction: Option<Box<dyn Instruction>>,
+    pub instruction: Option<Instruction>,
     pub cycle: usize,
+    /// The 6510's I/O port. Other variants don't have one, and leave it alone.
+    pub port: IOPort,
+
+    variant: Variant,
+    mode: ExecutionMode,
+    magic: u8,
+    opcode: u8,
+    address: u16,
+    interrupt: Option<Interrupt>,
+
+    irq: bool,
+    nmi: bool,
+    nmi_pending: bool,
+    polled: Option<Interrupt>,
+    ready: bool,
+    overflow_set: bool,
+
+    jammed: bool,
+    waiting: bool,
+    breakpoints: HashSet<u16>,
+    resuming_from: Option<u16>,
 }
 
 impl CPU6502 {
+    /// The magic constant the unstable ANE ($8B) and LXA ($AB) opcodes use unless told otherwise.
+    pub const DEFAULT_MAGIC: u8 = 0xEE;
+
     pub fn new() -> Self {
         Self {
             registers: Registers {
@@ -40,36 +124,1080 @@ impl CPU6502 {
             },
             instruction: None,
             cycle: 0,
+            port: IOPort::new(),
+            variant: Variant::Mos6502,
+            mode: ExecutionMode::CycleAccurate,
+            magic: Self::DEFAULT_MAGIC,
+            opcode: 0,
+            address: 0,
+            interrupt: None,
+            irq: false,
+            nmi: false,
+            nmi_pending: false,
+            polled: None,
+            ready: true,
+            overflow_set: false,
+            jammed: false,
+            waiting: false,
+            breakpoints: HashSet::new(),
+            resuming_from: None,
         }
     }
 
-    pub fn update_buses<T: IODevice>(&mut self, address_bus: &mut T) {
-        (self.registers.adh, self.registers.adl) = (((self.registers.pc & 0xFF00) >> 8) as u8, (self.registers.pc & 0x00FF) as u8);
-        self.registers.data = address_bus.get_hl(self.registers.adh, self.registers.adl);
+    pub fn with_mode(mode: ExecutionMode) -> Self {
+        Self { mode, ..Self::new() }
+    }
+
+    pub fn with_variant(variant: Variant) -> Self {
+        Self { variant, ..Self::new() }
     }
 
-    pub fn next_instruction(&mut self) {
-        self.instruction = find_instruction(self.registers.data);
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn mode(&self) -> ExecutionMode {
+        self.mode
+    }
+
+    /// Switch how the CPU runs from here on. Switching in the middle of an instruction is fine: a functional
+    /// step finishes whatever was in flight a cycle at a time.
+    pub fn set_mode(&mut self, mode: ExecutionMode) {
+        self.mode = mode;
+    }
+
+    /// Set the magic constant ANE ($8B) and LXA ($AB) OR into A before ANDing it. On real chips it depends on the
+    /// individual part and even its temperature; $EE, $EF, $FF and $00 have all been seen.
+    pub fn set_magic(&mut self, magic: u8) {
+        self.magic = magic;
+    }
+
+    /// Start the reset sequence. Over the next seven cycles the stack pointer is moved down by three without
+    /// anything being written, interrupts are disabled, and PC is loaded from the reset vector at $FFFC/$FFFD.
+    pub fn reset(&mut self) {
+        self.instruction = Some(interrupt_sequence(Interrupt::Reset, self.variant));
         self.cycle = 0;
+        (self.opcode, self.address, self.interrupt) = (0, self.registers.pc, Some(Interrupt::Reset));
+        self.nmi_pending = false;
+        self.polled = None;
+        self.jammed = false;
+        self.waiting = false;
+        self.port.reset();
+    }
+
+    /// Whether the CPU is between instructions, so the next tick fetches an opcode. It's also between them while
+    /// it's stopped by JAM, STP or WAI.
+    pub fn at_boundary(&self) -> bool {
+        self.instruction.is_none()
+    }
+
+    /// The instruction the CPU is partway through, or None if it's between instructions.
+    pub fn in_flight(&self) -> Option<InFlight> {
+        self.instruction.as_ref().map(|_| InFlight {
+            opcode: self.opcode,
+            address: self.address,
+            cycle: self.cycle + 1,
+            interrupt: self.interrupt,
+        })
+    }
+
+    /// Stop before fetching the instruction at an address. tick reports the breakpoint once each time it's
+    /// reached, and carries on from it when it's called again.
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
     }
 
-    pub fn tick<T: IODevice>(&mut self, address_bus: &mut T) -> bool {
-        self.update_buses(address_bus);
-        self.registers.pc += 1;
+    /// Drive the IRQ line. IRQ is level triggered: while it's asserted, an interrupt is taken after every
+    /// instruction that polls it with IRQ_DISABLE clear. Instructions poll before their last cycle, so the line
+    /// has to be asserted before then, and CLI, SEI and PLP only change what's polled after the instruction that
+    /// follows them.
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq = asserted;
+    }
 
+    /// Drive the NMI line. NMI is edge triggered: asserting it latches a single interrupt, which is taken after
+    /// the next instruction that polls it however long the line is held. Latched early enough, it takes over a
+    /// BRK or IRQ sequence already under way.
+    pub fn set_nmi(&mut self, asserted: bool) {
+        if asserted && !self.nmi {
+            self.nmi_pending = true;
+        }
+        self.nmi = asserted;
+    }
+
+    /// Drive the RDY line. While it's low the CPU stalls on the next cycle that reads, which is how DMA takes
+    /// the bus. The NMOS parts carry on through cycles that write, so it takes up to three cycles for the CPU to
+    /// stop, but the CMOS 65C02 stops on any cycle.
+    pub fn set_rdy(&mut self, ready: bool) {
+        self.ready = ready;
+    }
+
+    /// Drive the SO (set overflow) line, which sets OVERFLOW on the edge where it's asserted.
+    pub fn set_so(&mut self, asserted: bool) {
+        if asserted && !self.overflow_set {
+            self.registers.status.set_overflow(true);
+        }
+        self.overflow_set = asserted;
+    }
+
+    /// The interrupt the CPU would take if it polled now: NMI if one has been latched, otherwise IRQ if it's
+    /// asserted and not masked.
+    fn poll_interrupts(&self) -> Option<Interrupt> {
+        if self.nmi_pending {
+            Some(Interrupt::Nmi)
+        } else if self.irq && !self.registers.status.irq_disable() {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        }
+    }
+
+    /// The first cycle of every instruction: fetch and decode the opcode at PC. If the last instruction found an
+    /// interrupt when it polled, the opcode is discarded without incrementing PC, and the interrupt sequence runs
+    /// instead. The functional mode has nothing to go on from within the last instruction, so it polls here.
+    pub fn next_instruction<T: IODevice + ?Sized>(&mut self, address_bus: &mut T) -> ExecutionState {
+        [self.registers.adl, self.registers.adh] = self.registers.pc.to_le_bytes();
+        self.registers.data = address_bus.get_hl(self.registers.adh, self.registers.adl);
+
+        let polled = self.polled.take();
+        let interrupt = match self.mode {
+            ExecutionMode::CycleAccurate => polled,
+            ExecutionMode::Functional => self.poll_interrupts(),
+        };
+        if interrupt == Some(Interrupt::Nmi) {
+            self.nmi_pending = false;
+        }
+
+        (self.opcode, self.address) = (self.registers.data, self.registers.pc);
+        self.instruction = match interrupt {
+            Some(interrupt) => Some(interrupt_sequence(interrupt, self.variant)),
+            None => find_instruction(self.registers.data, self.variant, self.magic),
+        };
+        self.interrupt = match (interrupt, self.registers.data) {
+            (None, 0x00) => Some(Interrupt::Brk),
+            _ => interrupt,
+        };
+        self.cycle = 0;
+
+        match (&self.instruction, interrupt) {
+            (None, _) => ExecutionState::UnknownOpcode { pc: self.address, opcode: self.opcode },
+            (Some(_), Some(_)) => ExecutionState::Running,
+            (Some(instruction), None) => {
+                if instruction.finished_on_fetch() {
+                    self.instruction = None;
+                    self.polled = self.poll_interrupts();
+                }
+                self.registers.pc = self.registers.pc.wrapping_add(1);
+                ExecutionState::Running
+            },
+        }
+    }
+
+    /// Run a single clock cycle, which is either an opcode fetch or the next cycle of the instruction in
+    /// flight. Either way it performs exactly one read or write on the bus.
+    pub fn tick<T: IODevice>(&mut self, address_bus: &mut T) -> ExecutionState {
+        self.on_pins(address_bus, |cpu, address_bus| cpu.tick_bus(address_bus))
+    }
+
+    /// Run something against the bus as the variant sees it. Variants with fewer address lines or the 6510's I/O
+    /// port see it through a VariantBus, and the others see it directly.
+    fn on_pins<T, F, R>(&mut self, address_bus: &mut T, run: F) -> R
+    where T: IODevice, F: FnOnce(&mut Self, &mut dyn IODevice) -> R {
+        if self.variant.direct_bus() {
+            return run(self, address_bus);
+        }
+
+        let mut port = self.port;
+        let result = run(self, &mut VariantBus::new(self.variant, address_bus, &mut port));
+        self.port = port;
+        self.registers.adh &= (self.variant.address_mask() >> 8) as u8;
+        result
+    }
+
+    fn tick_bus(&mut self, address_bus: &mut dyn IODevice) -> ExecutionState {
+        if self.jammed {
+            return ExecutionState::Halted { pc: self.address, opcode: self.opcode };
+        }
+        if self.waiting {
+            if !self.nmi_pending && !self.irq {
+                return ExecutionState::Waiting { pc: self.registers.pc };
+            }
+            self.waiting = false;
+            self.polled = self.poll_interrupts();
+        }
+        if !self.ready && !self.writes_next() {
+            return ExecutionState::Stalled { pc: self.registers.pc };
+        }
+
+        if let Some(instruction) = &self.instruction {
+            if instruction.polls_before(self.cycle) {
+                self.polled = self.poll_interrupts();
+            }
+        }
         match &mut self.instruction {
             Some(instruction) => {
+                if self.nmi_pending && instruction.hijack(self.cycle) {
+                    self.nmi_pending = false;
+                }
                 match instruction.cycle(self.cycle, &mut self.registers, address_bus) {
                     InstructionState::Continue => self.cycle += 1,
-                    InstructionState::Finished => self.next_instruction()
+                    InstructionState::Finished => {
+                        self.instruction = None;
+                        self.cycle = 0;
+                    },
+                    InstructionState::Jammed => {
+                        self.instruction = None;
+                        self.cycle = 0;
+                        self.jammed = true;
+                        return ExecutionState::Halted { pc: self.address, opcode: self.opcode };
+                    },
+                    InstructionState::Waiting => {
+                        self.instruction = None;
+                        self.cycle = 0;
+                        self.waiting = true;
+                    },
+                }
+                ExecutionState::Running
+            },
+            None => {
+                let pc = self.registers.pc;
+                if !self.breakpoints.is_empty() && self.resuming_from != Some(pc) && self.breakpoints.contains(&pc) {
+                    self.resuming_from = Some(pc);
+                    return ExecutionState::Breakpoint { pc };
                 }
+                self.resuming_from = None;
+                self.next_instruction(address_bus)
             },
-            _ => {
-                self.next_instruction()
+        }
+    }
+
+    /// Whether the next cycle writes. Opcode fetches always read, and on the CMOS part RDY stops writes too, so
+    /// they never count.
+    fn writes_next(&self) -> bool {
+        match &self.instruction {
+            Some(instruction) if !self.variant.cmos() => instruction.writes_at(self.cycle),
+            _ => false,
+        }
+    }
+
+    /// Run until the end of an instruction. If one is already in flight it's finished, otherwise a new one is
+    /// fetched and run in full. Anything other than running stops the step early, and is returned as the error.
+    /// tick always runs a single cycle, but step runs whole instructions at once in the functional mode.
+    pub fn step<T: IODevice>(&mut self, address_bus: &mut T) -> Result<Step, ExecutionState> {
+        if self.mode == ExecutionMode::Functional && self.instruction.is_none() {
+            return self.step_functional(address_bus);
+        }
+
+        let mut cycles = 0;
+        loop {
+            match self.tick(address_bus) {
+                ExecutionState::Running => cycles += 1,
+                state => return Err(state),
+            }
+            if self.instruction.is_none() {
+                return Ok(Step { opcode: self.opcode, address: self.address, cycles, interrupt: self.interrupt });
             }
+        }
+    }
+
+    fn step_functional<T: IODevice>(&mut self, address_bus: &mut T) -> Result<Step, ExecutionState> {
+        match self.tick(address_bus) {
+            ExecutionState::Running => (),
+            state => return Err(state),
+        }
+
+        let executed = self.instruction.take().map(|mut instruction| {
+            self.on_pins(address_bus, |cpu, address_bus| instruction.execute(&mut cpu.registers, address_bus))
+        });
+        let cycles = match executed {
+            Some(executed) => match executed {
+                (InstructionState::Jammed, _) => {
+                    self.jammed = true;
+                    return Err(ExecutionState::Halted { pc: self.address, opcode: self.opcode });
+                },
+                (InstructionState::Waiting, cycles) => {
+                    self.waiting = true;
+                    1 + cycles
+                },
+                (_, cycles) => 1 + cycles,
+            },
+            None => 1,
         };
+        Ok(Step { opcode: self.opcode, address: self.address, cycles, interrupt: self.interrupt })
+    }
+
+    /// Run the next cycle, or the next instruction in the functional mode, returning the cycles it took.
+    fn advance<T: IODevice>(&mut self, address_bus: &mut T) -> Result<usize, ExecutionState> {
+        match self.mode {
+            ExecutionMode::CycleAccurate => match self.tick(address_bus) {
+                ExecutionState::Running => Ok(1),
+                state => Err(state),
+            },
+            ExecutionMode::Functional => self.step(address_bus).map(|step| step.cycles),
+        }
+    }
+
+    /// Run for a number of cycles, stopping early on anything other than running. Returns the number of cycles
+    /// run along with the state the CPU stopped in. The functional mode only stops between instructions, so it
+    /// can run a few cycles over.
+    pub fn run_for<T: IODevice>(&mut self, address_bus: &mut T, cycles: usize) -> (usize, ExecutionState) {
+        let mut ran = 0;
+        while ran < cycles {
+            match self.advance(address_bus) {
+                Ok(taken) => ran += taken,
+                Err(state) => return (ran, state),
+            }
+        }
+        (ran, ExecutionState::Running)
+    }
+
+    /// Run until the predicate holds at the end of an instruction, stopping early on anything other than
+    /// running. Returns the number of cycles run along with the state the CPU stopped in.
+    pub fn run_until<T, F>(&mut self, address_bus: &mut T, mut predicate: F) -> (usize, ExecutionState)
+    where T: IODevice, F: FnMut(&Self) -> bool {
+        let mut ran = 0;
+        loop {
+            match self.advance(address_bus) {
+                Ok(taken) => ran += taken,
+                Err(state) => return (ran, state),
+            }
+            if self.instruction.is_none() && predicate(self) {
+                return (ran, ExecutionState::Running);
+            }
+        }
+    }
+}
 
-        self.instruction.is_some()
+impl Default for CPU6502 {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::cpu::cpu_6502::{CPU6502, ExecutionMode, ExecutionState, InFlight, Step};
+    use crate::cpu::opcodes::Interrupt;
+    use crate::cpu::status_register::StatusRegister;
+    use crate::cpu::variant::Variant;
+    use crate::io_device::IODevice;
+    use crate::memory::ram::RAM;
+    use std::cell::Cell;
+
+    /// A machine with NOPs from $0200 onwards, a handler at $0300 and each vector pointing somewhere different.
+    fn setup() -> (CPU6502, RAM<0x10000>) {
+        let mut ram = RAM::<0x10000>::new(None);
+        for addr in 0x0200..0x0280 {
+            ram.put(addr, 0xea);
+        }
+        for (vector, target) in [(0xfffa, 0x0300), (0xfffc, 0x0200), (0xfffe, 0x0310)] {
+            ram.put(vector, (target & 0xff) as u8);
+            ram.put(vector + 1, (target >> 8) as u8);
+        }
+        for addr in 0x0300..0x0320 {
+            ram.put(addr, 0xea);
+        }
+
+        let mut cpu = CPU6502::new();
+        cpu.reset();
+        for _ in 0..8 {
+            cpu.tick(&mut ram);
+        }
+        (cpu, ram)
+    }
+
+    #[test]
+    fn reset() {
+        let mut ram = RAM::<0x10000>::new(None);
+        ram.put(0xfffc, 0x34);
+        ram.put(0xfffd, 0x12);
+        ram.put(0x1234, 0xea);
+
+        let mut cpu = CPU6502::new();
+        cpu.registers.stack = 0x00;
+        cpu.reset();
+        for _ in 0..7 {
+            cpu.tick(&mut ram);
+        }
+        assert_eq!(cpu.registers.pc, 0x1234);
+        assert_eq!(cpu.registers.stack, 0xfd);
+        assert!(cpu.registers.status.flag(StatusRegister::IRQ_DISABLE));
+
+        // Nothing is written to the stack
+        assert_eq!(ram.get(0x0100), 0);
+        assert_eq!(ram.get(0x01ff), 0);
+        assert_eq!(ram.get(0x01fe), 0);
+
+        // The eighth cycle fetches the first opcode
+        cpu.tick(&mut ram);
+        assert_eq!(cpu.registers.pc, 0x1235);
+        assert!(cpu.instruction.is_some());
+    }
+
+    #[test]
+    fn in_flight() {
+        let (mut cpu, mut ram) = setup();
+        ram.put(0x0201, 0xad);
+        ram.put(0x0202, 0x34);
+        ram.put(0x0203, 0x12);
+        assert_eq!(cpu.in_flight(), Some(InFlight { opcode: 0xea, address: 0x0200, cycle: 1, interrupt: None }));
+        cpu.tick(&mut ram);
+        assert!(cpu.at_boundary());
+        assert_eq!(cpu.in_flight(), None);
+
+        // LDA $1234 is partway through until its fourth cycle has run
+        for cycle in 1..4 {
+            cpu.tick(&mut ram);
+            assert!(!cpu.at_boundary());
+            assert_eq!(cpu.in_flight(), Some(InFlight { opcode: 0xad, address: 0x0201, cycle, interrupt: None }));
+        }
+        cpu.tick(&mut ram);
+        assert!(cpu.at_boundary());
+
+        // An interrupt sequence reports the opcode it discarded
+        cpu.registers.status.clear_flag(StatusRegister::IRQ_DISABLE);
+        cpu.set_irq(true);
+        cpu.tick(&mut ram);
+        cpu.tick(&mut ram);
+        cpu.tick(&mut ram);
+        let irq = InFlight { opcode: 0xea, address: 0x0205, cycle: 1, interrupt: Some(Interrupt::Irq) };
+        assert_eq!(cpu.in_flight(), Some(irq));
+    }
+
+    #[test]
+    fn irq() {
+        let (mut cpu, mut ram) = setup();
+        cpu.registers.status.clear_flag(StatusRegister::IRQ_DISABLE);
+        cpu.set_irq(true);
+
+        // Finish the NOP in flight; the next opcode fetch is discarded in favour of the interrupt
+        cpu.tick(&mut ram);
+        cpu.tick(&mut ram);
+        for _ in 0..6 {
+            cpu.tick(&mut ram);
+        }
+        assert_eq!(cpu.registers.pc, 0x0310);
+        assert_eq!(cpu.registers.stack, 0xfa);
+        assert_eq!(ram.get(0x01fd), 0x02);
+        assert_eq!(ram.get(0x01fc), 0x01);
+        assert_eq!(ram.get(0x01fb), 0b0010_0000);
+        assert!(cpu.registers.status.flag(StatusRegister::IRQ_DISABLE));
+    }
+
+    #[test]
+    fn irq_masked() {
+        let (mut cpu, mut ram) = setup();
+        cpu.set_irq(true);
+        for _ in 0..20 {
+            cpu.tick(&mut ram);
+        }
+        assert_eq!(cpu.registers.pc, 0x0200 + 11);
+        assert_eq!(cpu.registers.stack, 0xfd);
+    }
+
+    #[test]
+    fn irq_level_triggered() {
+        let (mut cpu, mut ram) = setup();
+        // The handler is a run of NOPs ending in CLI, so a held IRQ is taken again once it re-enables them
+        ram.put(0x0312, 0x58);
+        cpu.registers.status.clear_flag(StatusRegister::IRQ_DISABLE);
+        cpu.set_irq(true);
+
+        for _ in 0..8 {
+            cpu.tick(&mut ram);
+        }
+        assert_eq!(cpu.registers.pc, 0x0310);
+        assert_eq!(cpu.registers.stack, 0xfa);
+
+        // NOP, NOP, CLI, the NOP after it, and the fetch that's replaced by the interrupt
+        for _ in 0..(2 + 2 + 2 + 2 + 1 + 6) {
+            cpu.tick(&mut ram);
+        }
+        assert_eq!(cpu.registers.stack, 0xf7);
+        assert_eq!(cpu.registers.pc, 0x0310);
+    }
+
+    #[test]
+    fn nmi_edge_triggered() {
+        let (mut cpu, mut ram) = setup();
+        cpu.set_nmi(true);
+
+        for _ in 0..8 {
+            cpu.tick(&mut ram);
+        }
+        assert_eq!(cpu.registers.pc, 0x0300);
+        assert_eq!(cpu.registers.stack, 0xfa);
+        assert!(cpu.registers.status.flag(StatusRegister::IRQ_DISABLE));
+
+        // Holding the line doesn't trigger another NMI, but releasing and asserting it again does
+        for _ in 0..10 {
+            cpu.tick(&mut ram);
+        }
+        assert_eq!(cpu.registers.stack, 0xfa);
+        cpu.set_nmi(false);
+        cpu.set_nmi(true);
+
+        // It's too late for the last NOP to have seen it, so the next one runs first
+        for _ in 0..(2 + 7) {
+            cpu.tick(&mut ram);
+        }
+        assert_eq!(cpu.registers.pc, 0x0300);
+        assert_eq!(cpu.registers.stack, 0xf7);
+    }
+
+    #[test]
+    fn step() {
+        let (mut cpu, mut ram) = setup();
+        ram.put(0x0201, 0xad);
+        ram.put(0x0202, 0x34);
+        ram.put(0x0203, 0x12);
+        ram.put(0x1234, 0x42);
+
+        // Finishes the NOP that setup left in flight
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0200, cycles: 1, interrupt: None }));
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xad, address: 0x0201, cycles: 4, interrupt: None }));
+        assert_eq!(cpu.registers.a, 0x42);
+        assert_eq!(cpu.registers.pc, 0x0204);
+
+        ram.put(0x0204, 0x02);
+        assert_eq!(cpu.step(&mut ram), Err(ExecutionState::Halted { pc: 0x0204, opcode: 0x02 }));
+    }
+
+    #[test]
+    fn step_interrupts() {
+        let (mut cpu, mut ram) = setup();
+        assert!(cpu.step(&mut ram).is_ok());
+        cpu.set_nmi(true);
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0201, cycles: 2, interrupt: None }));
+        assert_eq!(
+            cpu.step(&mut ram),
+            Ok(Step { opcode: 0xea, address: 0x0202, cycles: 7, interrupt: Some(Interrupt::Nmi) })
+        );
+
+        ram.put(0x0300, 0x00);
+        assert_eq!(
+            cpu.step(&mut ram),
+            Ok(Step { opcode: 0x00, address: 0x0300, cycles: 7, interrupt: Some(Interrupt::Brk) })
+        );
+
+        cpu.reset();
+        assert_eq!(
+            cpu.step(&mut ram),
+            Ok(Step { opcode: 0x00, address: 0x0310, cycles: 7, interrupt: Some(Interrupt::Reset) })
+        );
+        assert_eq!(cpu.registers.pc, 0x0200);
+    }
+
+    #[test]
+    fn run_for() {
+        let (mut cpu, mut ram) = setup();
+        assert_eq!(cpu.run_for(&mut ram, 10), (10, ExecutionState::Running));
+        assert_eq!(cpu.registers.pc, 0x0206);
+        assert!(cpu.instruction.is_some());
+
+        // Stops when the CPU jams
+        ram.put(0x0206, 0x02);
+        assert_eq!(cpu.run_for(&mut ram, 100), (2, ExecutionState::Halted { pc: 0x0206, opcode: 0x02 }));
+    }
+
+    #[test]
+    fn run_until() {
+        let (mut cpu, mut ram) = setup();
+        ram.put(0x0210, 0xe8);
+        assert_eq!(cpu.run_until(&mut ram, |cpu| cpu.registers.x == 1), (1 + 15 * 2 + 2, ExecutionState::Running));
+        assert_eq!(cpu.registers.pc, 0x0211);
+
+        ram.put(0x0220, 0x02);
+        assert_eq!(
+            cpu.run_until(&mut ram, |_| false),
+            (15 * 2 + 1, ExecutionState::Halted { pc: 0x0220, opcode: 0x02 })
+        );
+    }
+
+    #[test]
+    fn jam() {
+        let (mut cpu, mut ram) = setup();
+        ram.put(0x0201, 0x02);
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0200, cycles: 1, interrupt: None }));
+        assert_eq!(cpu.step(&mut ram), Err(ExecutionState::Halted { pc: 0x0201, opcode: 0x02 }));
+
+        // Interrupts can't get it going again, but reset does
+        cpu.set_nmi(true);
+        assert_eq!(cpu.tick(&mut ram), ExecutionState::Halted { pc: 0x0201, opcode: 0x02 });
+        cpu.reset();
+        assert_eq!(cpu.run_for(&mut ram, 7), (7, ExecutionState::Running));
+        assert_eq!(cpu.registers.pc, 0x0200);
+    }
+
+    #[test]
+    fn stp() {
+        let (_, mut ram) = setup();
+        ram.put(0x0200, 0xdb);
+        let mut cpu = CPU6502::with_variant(Variant::Wdc65C02);
+        cpu.reset();
+        cpu.run_for(&mut ram, 7);
+        assert_eq!(cpu.step(&mut ram), Err(ExecutionState::Halted { pc: 0x0200, opcode: 0xdb }));
+        assert_eq!(cpu.tick(&mut ram), ExecutionState::Halted { pc: 0x0200, opcode: 0xdb });
+    }
+
+    #[test]
+    fn wai() {
+        let (_, mut ram) = setup();
+        ram.put(0x0200, 0xcb);
+        let mut cpu = CPU6502::with_variant(Variant::Wdc65C02);
+        cpu.reset();
+        cpu.run_for(&mut ram, 7);
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xcb, address: 0x0200, cycles: 3, interrupt: None }));
+        assert_eq!(cpu.tick(&mut ram), ExecutionState::Waiting { pc: 0x0201 });
+        assert_eq!(cpu.tick(&mut ram), ExecutionState::Waiting { pc: 0x0201 });
+
+        // With interrupts disabled, IRQ wakes it up without being taken
+        cpu.set_irq(true);
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0201, cycles: 2, interrupt: None }));
+
+        // And with them enabled it's taken straight away
+        let mut cpu = CPU6502::with_variant(Variant::Wdc65C02);
+        cpu.reset();
+        cpu.run_for(&mut ram, 7);
+        cpu.registers.status.clear_flag(StatusRegister::IRQ_DISABLE);
+        cpu.step(&mut ram).unwrap();
+        cpu.set_irq(true);
+        let step = Step { opcode: 0xea, address: 0x0201, cycles: 7, interrupt: Some(Interrupt::Irq) };
+        assert_eq!(cpu.step(&mut ram), Ok(step));
+        assert_eq!(cpu.registers.pc, 0x0310);
+    }
+
+    #[test]
+    fn rdy() {
+        let (_, mut ram) = setup();
+        ram.put(0x0200, 0x85); // STA $10
+        ram.put(0x0201, 0x10);
+        let mut cpu = CPU6502::new();
+        cpu.reset();
+        cpu.run_for(&mut ram, 7);
+        cpu.registers.a = 0x42;
+
+        cpu.set_rdy(false);
+        assert_eq!(cpu.tick(&mut ram), ExecutionState::Stalled { pc: 0x0200 });
+        assert_eq!(cpu.tick(&mut ram), ExecutionState::Stalled { pc: 0x0200 });
+        cpu.set_rdy(true);
+        assert_eq!(cpu.tick(&mut ram), ExecutionState::Running);
+        assert_eq!(cpu.tick(&mut ram), ExecutionState::Running);
+
+        // The write goes ahead with RDY low, and the CPU stops at the read after it
+        cpu.set_rdy(false);
+        assert_eq!(cpu.tick(&mut ram), ExecutionState::Running);
+        assert_eq!(ram.get(0x0010), 0x42);
+        assert_eq!(cpu.tick(&mut ram), ExecutionState::Stalled { pc: 0x0202 });
+        cpu.set_rdy(true);
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0202, cycles: 2, interrupt: None }));
+    }
+
+    /// RAM that counts the accesses made through it.
+    struct CountingBus {
+        ram: RAM<0x10000>,
+        accesses: Cell<usize>,
+    }
+
+    impl IODevice for CountingBus {
+        fn get(&self, addr: u16) -> u8 {
+            self.accesses.set(self.accesses.get() + 1);
+            self.ram.get(addr)
+        }
+
+        fn get_hl(&self, high: u8, low: u8) -> u8 {
+            self.get(u16::from_le_bytes([low, high]))
+        }
+
+        fn put(&mut self, addr: u16, value: u8) {
+            self.accesses.set(self.accesses.get() + 1);
+            self.ram.put(addr, value);
+        }
+
+        fn put_hl(&mut self, high: u8, low: u8, value: u8) {
+            self.put(u16::from_le_bytes([low, high]), value);
+        }
+    }
+
+    #[test]
+    fn rdy_stall_leaves_bus_alone() {
+        let (_, mut ram) = setup();
+        ram.put(0x0200, 0xee); // INC $1234
+        ram.put(0x0201, 0x34);
+        ram.put(0x0202, 0x12);
+        let mut bus = CountingBus { ram, accesses: Cell::new(0) };
+        let mut cpu = CPU6502::new();
+        cpu.reset();
+        cpu.run_for(&mut bus, 7);
+
+        // Every read of the instruction stalls without going near the bus
+        cpu.set_rdy(false);
+        for _ in 0..4 {
+            bus.accesses.set(0);
+            assert_eq!(cpu.tick(&mut bus), ExecutionState::Stalled { pc: cpu.registers.pc });
+            assert_eq!(bus.accesses.get(), 0);
+            cpu.set_rdy(true);
+            cpu.tick(&mut bus);
+            cpu.set_rdy(false);
+        }
+
+        // Leaving the two writes, which each make exactly one access
+        for _ in 0..2 {
+            bus.accesses.set(0);
+            assert_eq!(cpu.tick(&mut bus), ExecutionState::Running);
+            assert_eq!(bus.accesses.get(), 1);
+        }
+    }
+
+    #[test]
+    fn cmos_rdy_stalls_writes() {
+        let (_, mut ram) = setup();
+        ram.put(0x0200, 0x85); // STA $10
+        ram.put(0x0201, 0x10);
+        let mut cpu = CPU6502::with_variant(Variant::Wdc65C02);
+        cpu.reset();
+        cpu.run_for(&mut ram, 7);
+        cpu.registers.a = 0x42;
+
+        cpu.tick(&mut ram);
+        cpu.tick(&mut ram);
+        cpu.set_rdy(false);
+        assert_eq!(cpu.tick(&mut ram), ExecutionState::Stalled { pc: 0x0202 });
+        assert_eq!(ram.get(0x0010), 0x00);
+        cpu.set_rdy(true);
+        assert_eq!(cpu.tick(&mut ram), ExecutionState::Running);
+        assert_eq!(ram.get(0x0010), 0x42);
+    }
+
+    #[test]
+    fn so() {
+        let (mut cpu, mut ram) = setup();
+        cpu.set_so(true);
+        assert!(cpu.registers.status.flag(StatusRegister::OVERFLOW));
+
+        // Holding the line doesn't set it again once it's been cleared
+        ram.put(0x0201, 0xb8); // CLV
+        cpu.step(&mut ram).unwrap();
+        cpu.step(&mut ram).unwrap();
+        assert!(!cpu.registers.status.flag(StatusRegister::OVERFLOW));
+        cpu.set_so(true);
+        assert!(!cpu.registers.status.flag(StatusRegister::OVERFLOW));
+
+        cpu.set_so(false);
+        cpu.set_so(true);
+        assert!(cpu.registers.status.flag(StatusRegister::OVERFLOW));
+    }
+
+    #[test]
+    fn irq_polled_before_last_cycle() {
+        let (mut cpu, mut ram) = setup();
+        ram.put(0x0201, 0xad); // LDA $1000
+        ram.put(0x0202, 0x00);
+        ram.put(0x0203, 0x10);
+        cpu.registers.status.clear_flag(StatusRegister::IRQ_DISABLE);
+        cpu.step(&mut ram).unwrap();
+
+        for _ in 0..3 {
+            cpu.tick(&mut ram);
+        }
+        cpu.set_irq(true);
+        cpu.tick(&mut ram);
+        assert_eq!(cpu.step(&mut ram).unwrap().interrupt, Some(Interrupt::Irq));
+
+        // Asserted any later and the next instruction runs first
+        let (mut cpu, _) = setup();
+        cpu.registers.status.clear_flag(StatusRegister::IRQ_DISABLE);
+        cpu.step(&mut ram).unwrap();
+        for _ in 0..4 {
+            cpu.tick(&mut ram);
+        }
+        cpu.set_irq(true);
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0204, cycles: 2, interrupt: None }));
+        assert_eq!(cpu.step(&mut ram).unwrap().interrupt, Some(Interrupt::Irq));
+    }
+
+    #[test]
+    fn cli_delays_irq() {
+        let (mut cpu, mut ram) = setup();
+        ram.put(0x0201, 0x58); // CLI
+        cpu.set_irq(true);
+        cpu.step(&mut ram).unwrap();
+
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0x58, address: 0x0201, cycles: 2, interrupt: None }));
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0202, cycles: 2, interrupt: None }));
+        assert_eq!(cpu.step(&mut ram).unwrap().interrupt, Some(Interrupt::Irq));
+        assert_eq!(ram.get(0x01fc), 0x03);
+    }
+
+    #[test]
+    fn sei_delays_masking() {
+        let (mut cpu, mut ram) = setup();
+        ram.put(0x0201, 0x78); // SEI
+        cpu.registers.status.clear_flag(StatusRegister::IRQ_DISABLE);
+        cpu.step(&mut ram).unwrap();
+        cpu.set_irq(true);
+
+        // The interrupt is taken straight after SEI, and P is pushed with IRQ_DISABLE already set
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0x78, address: 0x0201, cycles: 2, interrupt: None }));
+        let step = Step { opcode: 0xea, address: 0x0202, cycles: 7, interrupt: Some(Interrupt::Irq) };
+        assert_eq!(cpu.step(&mut ram), Ok(step));
+        assert_eq!(ram.get(0x01fb) & StatusRegister::IRQ_DISABLE.bits(), StatusRegister::IRQ_DISABLE.bits());
+    }
+
+    #[test]
+    fn plp_delays_irq() {
+        let (mut cpu, mut ram) = setup();
+        ram.put(0x0201, 0x28); // PLP
+        ram.put(0x01fe, 0x00);
+        cpu.set_irq(true);
+        cpu.step(&mut ram).unwrap();
+
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0x28, address: 0x0201, cycles: 4, interrupt: None }));
+        assert!(!cpu.registers.status.flag(StatusRegister::IRQ_DISABLE));
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0202, cycles: 2, interrupt: None }));
+        assert_eq!(cpu.step(&mut ram).unwrap().interrupt, Some(Interrupt::Irq));
+    }
+
+    #[test]
+    fn rti_doesnt_delay_irq() {
+        let (mut cpu, mut ram) = setup();
+        ram.put(0x0201, 0x40); // RTI to $0250
+        ram.put(0x01fe, 0x00);
+        ram.put(0x01ff, 0x50);
+        ram.put(0x0100, 0x02);
+        cpu.set_irq(true);
+        cpu.step(&mut ram).unwrap();
+
+        // RTI pulls P before its last cycle, so the interrupt is polled with it already cleared
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0x40, address: 0x0201, cycles: 6, interrupt: None }));
+        let step = Step { opcode: 0xea, address: 0x0250, cycles: 7, interrupt: Some(Interrupt::Irq) };
+        assert_eq!(cpu.step(&mut ram), Ok(step));
+    }
+
+    #[test]
+    fn branch_delays_irq() {
+        let (mut cpu, mut ram) = setup();
+        ram.put(0x0201, 0xd0); // BNE *+2
+        ram.put(0x0202, 0x00);
+        cpu.registers.status.clear_flag(StatusRegister::IRQ_DISABLE);
+        cpu.registers.status.clear_flag(StatusRegister::ZERO);
+        cpu.step(&mut ram).unwrap();
+
+        // A taken branch that stays on the page doesn't poll before its last cycle
+        cpu.tick(&mut ram);
+        cpu.tick(&mut ram);
+        cpu.set_irq(true);
+        cpu.tick(&mut ram);
+        assert!(cpu.instruction.is_none());
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0203, cycles: 2, interrupt: None }));
+        assert_eq!(cpu.step(&mut ram).unwrap().interrupt, Some(Interrupt::Irq));
+
+        // But it does before the one ahead of it
+        let (mut cpu, _) = setup();
+        cpu.registers.status.clear_flag(StatusRegister::IRQ_DISABLE);
+        cpu.registers.status.clear_flag(StatusRegister::ZERO);
+        cpu.step(&mut ram).unwrap();
+        cpu.tick(&mut ram);
+        cpu.set_irq(true);
+        cpu.tick(&mut ram);
+        cpu.tick(&mut ram);
+        let step = Step { opcode: 0xea, address: 0x0203, cycles: 7, interrupt: Some(Interrupt::Irq) };
+        assert_eq!(cpu.step(&mut ram), Ok(step));
+
+        // And one that crosses a page polls before its last cycle as usual
+        let (mut cpu, _) = setup();
+        ram.put(0x0202, 0xfb); // BNE *-3
+        cpu.registers.status.clear_flag(StatusRegister::IRQ_DISABLE);
+        cpu.registers.status.clear_flag(StatusRegister::ZERO);
+        cpu.step(&mut ram).unwrap();
+        for _ in 0..3 {
+            cpu.tick(&mut ram);
+        }
+        cpu.set_irq(true);
+        cpu.tick(&mut ram);
+        let step = Step { opcode: 0x00, address: 0x01fe, cycles: 7, interrupt: Some(Interrupt::Irq) };
+        assert_eq!(cpu.step(&mut ram), Ok(step));
+    }
+
+    #[test]
+    fn nmi_hijacks_brk() {
+        let (mut cpu, mut ram) = setup();
+        ram.put(0x0201, 0x00); // BRK
+        cpu.step(&mut ram).unwrap();
+
+        // Up until P is pushed, an NMI takes the vector over
+        for _ in 0..4 {
+            cpu.tick(&mut ram);
+        }
+        cpu.set_nmi(true);
+        for _ in 0..3 {
+            cpu.tick(&mut ram);
+        }
+        assert!(cpu.instruction.is_none());
+        assert_eq!(cpu.registers.pc, 0x0300);
+        assert_eq!(ram.get(0x01fb) & StatusRegister::BRK_COMMAND.bits(), StatusRegister::BRK_COMMAND.bits());
+
+        // The NMI was used up, so it isn't taken again
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0300, cycles: 2, interrupt: None }));
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0301, cycles: 2, interrupt: None }));
+
+        // Any later and BRK goes to its own vector, with the NMI taken after the first instruction there
+        let (mut cpu, _) = setup();
+        cpu.step(&mut ram).unwrap();
+        for _ in 0..5 {
+            cpu.tick(&mut ram);
+        }
+        cpu.set_nmi(true);
+        cpu.tick(&mut ram);
+        cpu.tick(&mut ram);
+        assert_eq!(cpu.registers.pc, 0x0310);
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0310, cycles: 2, interrupt: None }));
+        assert_eq!(cpu.step(&mut ram).unwrap().interrupt, Some(Interrupt::Nmi));
+    }
+
+    #[test]
+    fn nmi_hijacks_irq() {
+        let (mut cpu, mut ram) = setup();
+        cpu.registers.status.clear_flag(StatusRegister::IRQ_DISABLE);
+        cpu.set_irq(true);
+        cpu.step(&mut ram).unwrap();
+
+        for _ in 0..3 {
+            cpu.tick(&mut ram);
+        }
+        cpu.set_nmi(true);
+        for _ in 0..4 {
+            cpu.tick(&mut ram);
+        }
+        assert!(cpu.instruction.is_none());
+        assert_eq!(cpu.registers.pc, 0x0300);
+        assert_eq!(ram.get(0x01fb) & StatusRegister::BRK_COMMAND.bits(), 0);
+    }
+
+    #[test]
+    fn functional_wai() {
+        let (_, mut ram) = setup();
+        ram.put(0x0200, 0xcb);
+        let mut cpu = CPU6502::with_variant(Variant::Wdc65C02);
+        cpu.set_mode(ExecutionMode::Functional);
+        cpu.reset();
+        cpu.run_for(&mut ram, 7);
+        assert_eq!(cpu.run_for(&mut ram, 100), (3, ExecutionState::Waiting { pc: 0x0201 }));
+        cpu.set_nmi(true);
+        let step = Step { opcode: 0xea, address: 0x0201, cycles: 7, interrupt: Some(Interrupt::Nmi) };
+        assert_eq!(cpu.step(&mut ram), Ok(step));
+    }
+
+    #[test]
+    fn mos6507_address_lines() {
+        let (_, mut ram) = setup();
+        ram.put(0x1ffc, 0x00);
+        ram.put(0x1ffd, 0xf0);
+        // LDA $0200, STA $F280
+        for (offset, byte) in [0xad, 0x00, 0x02, 0x8d, 0x80, 0xf2].iter().enumerate() {
+            ram.put(0x1000 + offset as u16, *byte);
+        }
+        ram.put(0x0200, 0x42);
+
+        for mode in [ExecutionMode::CycleAccurate, ExecutionMode::Functional] {
+            let mut cpu = CPU6502::with_variant(Variant::Mos6507);
+            cpu.set_mode(mode);
+            cpu.reset();
+            cpu.run_for(&mut ram, 7);
+            // PC keeps all 16 bits, but the bus only sees 13 of them
+            assert_eq!(cpu.registers.pc, 0xf000);
+            cpu.step(&mut ram).unwrap();
+            cpu.step(&mut ram).unwrap();
+            assert_eq!(ram.get(0x1280), 0x42, "{mode:?}");
+            assert_eq!(ram.get(0xf280), 0x00, "{mode:?}");
+            ram.put(0x1280, 0x00);
+        }
+    }
+
+    #[test]
+    fn mos6510_io_port() {
+        let (_, mut ram) = setup();
+        // LDA #$2F, STA $00, LDA #$35, STA $01, LDA $01
+        for (offset, byte) in [0xa9, 0x2f, 0x85, 0x00, 0xa9, 0x35, 0x85, 0x01, 0xa5, 0x01].iter().enumerate() {
+            ram.put(0x0200 + offset as u16, *byte);
+        }
+
+        for mode in [ExecutionMode::CycleAccurate, ExecutionMode::Functional] {
+            let mut cpu = CPU6502::with_variant(Variant::Mos6510);
+            cpu.set_mode(mode);
+            cpu.reset();
+            cpu.run_for(&mut ram, 7);
+            assert_eq!(cpu.port.pins(), 0xff);
+            cpu.port.set_inputs(0x00);
+            for _ in 0..5 {
+                cpu.step(&mut ram).unwrap();
+            }
+            assert_eq!((cpu.port.direction, cpu.port.data), (0x2f, 0x35), "{mode:?}");
+            assert_eq!(cpu.port.pins(), 0x25, "{mode:?}");
+            assert_eq!(cpu.registers.a, 0x25, "{mode:?}");
+
+            // Reset makes every pin an input again
+            cpu.reset();
+            assert_eq!(cpu.port.pins(), 0x00, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn breakpoint() {
+        let (mut cpu, mut ram) = setup();
+        cpu.add_breakpoint(0x0203);
+
+        assert_eq!(cpu.run_for(&mut ram, 100), (5, ExecutionState::Breakpoint { pc: 0x0203 }));
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0203, cycles: 2, interrupt: None }));
+
+        // Hit again on the way back round
+        ram.put(0x0204, 0x4c);
+        ram.put(0x0205, 0x03);
+        ram.put(0x0206, 0x02);
+        assert_eq!(cpu.run_until(&mut ram, |_| false), (3, ExecutionState::Breakpoint { pc: 0x0203 }));
+
+        cpu.remove_breakpoint(0x0203);
+        assert_eq!(cpu.run_for(&mut ram, 10), (10, ExecutionState::Running));
+    }
+
+    #[test]
+    fn functional() {
+        let (mut cpu, mut ram) = setup();
+        cpu.set_mode(ExecutionMode::Functional);
+
+        // The NOP setup left in flight is finished off a cycle at a time
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0200, cycles: 1, interrupt: None }));
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xea, address: 0x0201, cycles: 2, interrupt: None }));
+        assert!(cpu.instruction.is_none());
+
+        ram.put(0x0202, 0xad);
+        ram.put(0x0203, 0xff);
+        ram.put(0x0204, 0x12);
+        ram.put(0x12ff, 0x42);
+        assert_eq!(cpu.step(&mut ram), Ok(Step { opcode: 0xad, address: 0x0202, cycles: 4, interrupt: None }));
+        assert_eq!(cpu.registers.a, 0x42);
+
+        cpu.set_nmi(true);
+        assert_eq!(
+            cpu.step(&mut ram),
+            Ok(Step { opcode: 0xea, address: 0x0205, cycles: 7, interrupt: Some(Interrupt::Nmi) })
+        );
+        assert_eq!(cpu.registers.pc, 0x0300);
+        assert_eq!(ram.get(0x01fd), 0x02);
+        assert_eq!(ram.get(0x01fc), 0x05);
+
+        // Stops between instructions, even if that's past the number of cycles asked for
+        assert_eq!(cpu.run_for(&mut ram, 3), (4, ExecutionState::Running));
+        assert_eq!(cpu.registers.pc, 0x0302);
+
+        // Carries on a cycle at a time after switching back
+        cpu.set_mode(ExecutionMode::CycleAccurate);
+        assert_eq!(cpu.run_for(&mut ram, 3), (3, ExecutionState::Running));
+        assert!(cpu.instruction.is_some());
+        assert_eq!(cpu.registers.pc, 0x0304);
+    }
+}
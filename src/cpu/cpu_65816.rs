@@ -0,0 +1,377 @@
+use crate::io_device::LongIODevice;
+use crate::cpu::status_register::StatusRegister;
+
+use super::opcodes::InstructionState;
+use super::opcodes_65816::{execute, interrupt_sequence};
+
+/// The 65816's registers. A, X and Y are 16 bits wide, but while the M or X flag is set (as it always is in
+/// emulation mode) only their low bytes take part in instructions. The high byte of A is kept, as B, and the high
+/// bytes of X and Y are held at zero.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u16,
+    pub x: u16,
+    pub y: u16,
+    pub status: StatusRegister,
+
+    pub pc: u16,
+    pub stack: u16,
+    /// The direct page register, which direct page addressing is relative to
+    pub direct: u16,
+    /// The bank absolute addressing reads and writes data in
+    pub data_bank: u8,
+    /// The bank PC runs in
+    pub program_bank: u8,
+    /// Set when the CPU is emulating a 6502, as it does out of reset
+    pub emulation: bool,
+}
+
+impl Registers {
+    /// Whether A and memory accesses are 8 bits wide.
+    pub fn m8(&self) -> bool {
+        self.emulation || self.status.flag(StatusRegister::MEMORY_SELECT)
+    }
+
+    /// Whether X and Y are 8 bits wide.
+    pub fn x8(&self) -> bool {
+        self.emulation || self.status.flag(StatusRegister::INDEX_SELECT)
+    }
+
+    /// Where PC points, with the program bank.
+    pub fn program_counter(&self) -> u32 {
+        (self.program_bank as u32) << 16 | self.pc as u32
+    }
+}
+
+/// The ways the 65816 can end up running an interrupt sequence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    Brk,
+    Cop,
+    Irq,
+    Nmi,
+    Reset,
+}
+
+impl Interrupt {
+    /// The vector PC is loaded from. Native mode has its own vectors, and a separate one for BRK.
+    pub fn vector(&self, emulation: bool) -> u16 {
+        match (self, emulation) {
+            (Interrupt::Cop, false) => 0xFFE4,
+            (Interrupt::Brk, false) => 0xFFE6,
+            (Interrupt::Nmi, false) => 0xFFEA,
+            (Interrupt::Irq, false) => 0xFFEE,
+            (Interrupt::Cop, true) => 0xFFF4,
+            (Interrupt::Nmi, true) => 0xFFFA,
+            (Interrupt::Reset, _) => 0xFFFC,
+            (Interrupt::Brk | Interrupt::Irq, true) => 0xFFFE,
+        }
+    }
+}
+
+/// What happened on a call to CPU65816::step, when it didn't run an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecutionState {
+    Running,
+    /// STP has stopped the CPU until it's reset.
+    Halted { address: u32 },
+    /// WAI has stopped the CPU until IRQ or NMI is asserted. The address is where it will carry on from.
+    Waiting { address: u32 },
+}
+
+/// What a call to CPU65816::step executed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    /// The opcode that was fetched. For a hardware interrupt this is the opcode it discarded.
+    pub opcode: u8,
+    /// Where the instruction started, with the program bank.
+    pub address: u32,
+    /// How many cycles the step ran for.
+    pub cycles: usize,
+    /// Set when the step ran an interrupt sequence, including BRK and COP.
+    pub interrupt: Option<Interrupt>,
+}
+
+/// The WDC 65C816, run an instruction at a time. It doesn't make the dummy accesses the hardware does, but the
+/// cycle counts are those of the hardware, including the extra cycles for 16-bit registers, a direct page that
+/// isn't page aligned and indexing across a page.
+pub struct CPU65816 {
+    pub registers: Registers,
+
+    reset_pending: bool,
+    irq: bool,
+    nmi: bool,
+    nmi_pending: bool,
+
+    stopped: Option<u32>,
+    waiting: bool,
+}
+
+impl CPU65816 {
+    pub fn new() -> Self {
+        Self {
+            registers: Registers {
+                a: 0,
+                x: 0,
+                y: 0,
+                status: StatusRegister::new(0b00110100),
+                pc: 0,
+                stack: 0x0100,
+                direct: 0,
+                data_bank: 0,
+                program_bank: 0,
+                emulation: true,
+            },
+            reset_pending: false,
+            irq: false,
+            nmi: false,
+            nmi_pending: false,
+            stopped: None,
+            waiting: false,
+        }
+    }
+
+    /// Reset on the next step, which puts the CPU in emulation mode with 8-bit registers, clears the direct page
+    /// and bank registers and D, disables interrupts, and loads PC from the reset vector at $00FFFC/$00FFFD.
+    pub fn reset(&mut self) {
+        self.reset_pending = true;
+        self.nmi_pending = false;
+        self.stopped = None;
+        self.waiting = false;
+    }
+
+    /// Drive the IRQ line, which is level triggered and masked by IRQ_DISABLE, as on the 6502.
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq = asserted;
+    }
+
+    /// Drive the NMI line, which is edge triggered, as on the 6502.
+    pub fn set_nmi(&mut self, asserted: bool) {
+        if asserted && !self.nmi {
+            self.nmi_pending = true;
+        }
+        self.nmi = asserted;
+    }
+
+    /// Run the next instruction, or the interrupt sequence in its place. Each byte of a block move is a step of
+    /// its own, as interrupts can be taken between them. Anything other than running is returned as the error.
+    pub fn step<T: LongIODevice>(&mut self, address_bus: &mut T) -> Result<Step, ExecutionState> {
+        if let Some(address) = self.stopped {
+            return Err(ExecutionState::Halted { address });
+        }
+        if self.waiting {
+            if !self.nmi_pending && !self.irq {
+                return Err(ExecutionState::Waiting { address: self.registers.program_counter() });
+            }
+            self.waiting = false;
+        }
+
+        let address = self.registers.program_counter();
+        let interrupt = if self.reset_pending {
+            self.reset_pending = false;
+            Some(Interrupt::Reset)
+        } else if self.nmi_pending {
+            self.nmi_pending = false;
+            Some(Interrupt::Nmi)
+        } else if self.irq && !self.registers.status.irq_disable() {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        };
+
+        if let Some(interrupt) = interrupt {
+            let (opcode, cycles) = interrupt_sequence(interrupt, &mut self.registers, address_bus);
+            return Ok(Step { opcode, address, cycles, interrupt: Some(interrupt) });
+        }
+
+        let (opcode, state, cycles) = execute(&mut self.registers, address_bus);
+        match state {
+            InstructionState::Jammed => {
+                self.stopped = Some(address);
+                return Err(ExecutionState::Halted { address });
+            },
+            InstructionState::Waiting => self.waiting = true,
+            _ => (),
+        }
+        let interrupt = match opcode {
+            0x00 => Some(Interrupt::Brk),
+            0x02 => Some(Interrupt::Cop),
+            _ => None,
+        };
+        Ok(Step { opcode, address, cycles, interrupt })
+    }
+
+    /// Run for a number of cycles, stopping early on anything other than running. Returns the number of cycles
+    /// run along with the state the CPU stopped in. It only stops between instructions, so it can run a few
+    /// cycles over.
+    pub fn run_for<T: LongIODevice>(&mut self, address_bus: &mut T, cycles: usize) -> (usize, ExecutionState) {
+        let mut ran = 0;
+        while ran < cycles {
+            match self.step(address_bus) {
+                Ok(step) => ran += step.cycles,
+                Err(state) => return (ran, state),
+            }
+        }
+        (ran, ExecutionState::Running)
+    }
+}
+
+impl Default for CPU65816 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::cpu_65816::{CPU65816, ExecutionState, Interrupt, Step};
+    use crate::cpu::status_register::StatusRegister;
+    use crate::io_device::IODevice;
+    use crate::memory::ram::RAM;
+
+    /// A machine with NOPs from $0200 onwards, a handler at $0300 and each vector pointing somewhere different.
+    fn setup() -> (CPU65816, RAM<0x10000>) {
+        let mut ram = RAM::<0x10000>::new(None);
+        for addr in 0x0200..0x0300 {
+            ram.put(addr, 0xEA);
+        }
+        ram.put(0x0300, 0x40); // RTI
+        let vectors = [
+            (0xFFE4, 0x10), (0xFFE6, 0x20), (0xFFEA, 0x30), (0xFFEE, 0x40),
+            (0xFFF4, 0x50), (0xFFFA, 0x60), (0xFFFC, 0x02), (0xFFFE, 0x70),
+        ];
+        for (vector, high) in vectors {
+            ram.put(vector + 1, high);
+        }
+
+        let mut cpu = CPU65816::new();
+        cpu.reset();
+        assert_eq!(cpu.step(&mut ram).unwrap().interrupt, Some(Interrupt::Reset));
+        (cpu, ram)
+    }
+
+    #[test]
+    fn reset() {
+        let (mut cpu, mut ram) = setup();
+        assert_eq!(cpu.registers.pc, 0x0200);
+        assert!(cpu.registers.emulation);
+        assert!(cpu.registers.status.flag(StatusRegister::IRQ_DISABLE));
+        assert_eq!(cpu.registers.stack, 0x01FD);
+
+        // Reset puts everything back the way it was at power on, from native mode with 16-bit registers too
+        cpu.registers.emulation = false;
+        cpu.registers.status = StatusRegister::new(0);
+        cpu.registers.x = 0x1234;
+        cpu.registers.direct = 0x1000;
+        cpu.registers.data_bank = 0x7E;
+        cpu.registers.program_bank = 0x01;
+        cpu.reset();
+        let step = cpu.step(&mut ram).unwrap();
+        assert_eq!(step.cycles, 7);
+        assert_eq!(step.address, 0x010200);
+        assert_eq!(cpu.registers.program_counter(), 0x000200);
+        assert!(cpu.registers.emulation && cpu.registers.m8() && cpu.registers.x8());
+        assert_eq!((cpu.registers.x, cpu.registers.direct, cpu.registers.data_bank), (0x34, 0, 0));
+    }
+
+    #[test]
+    fn interrupts() {
+        let (mut cpu, mut ram) = setup();
+        cpu.registers.status.clear_flag(StatusRegister::IRQ_DISABLE);
+
+        cpu.set_irq(true);
+        let step = cpu.step(&mut ram).unwrap();
+        assert_eq!(step, Step { opcode: 0xEA, address: 0x0200, cycles: 7, interrupt: Some(Interrupt::Irq) });
+        assert_eq!(cpu.registers.pc, 0x7000);
+
+        // IRQ is masked while the handler runs, but NMI isn't
+        cpu.set_nmi(true);
+        let step = cpu.step(&mut ram).unwrap();
+        assert_eq!(step.interrupt, Some(Interrupt::Nmi));
+        assert_eq!(cpu.registers.pc, 0x6000);
+        ram.put(0x6000, 0xEA);
+        cpu.step(&mut ram).unwrap();
+        assert_eq!(cpu.registers.pc, 0x6001);
+    }
+
+    #[test]
+    fn native_interrupts() {
+        let (mut cpu, mut ram) = setup();
+        cpu.registers.emulation = false;
+        cpu.registers.status.clear_flag(StatusRegister::IRQ_DISABLE);
+        cpu.registers.program_bank = 0x01;
+
+        cpu.set_irq(true);
+        let step = cpu.step(&mut ram).unwrap();
+        assert_eq!(step.cycles, 8);
+        assert_eq!(cpu.registers.program_counter(), 0x004000);
+        assert_eq!(ram.get(0x01FD), 0x01);
+
+        cpu.set_nmi(true);
+        cpu.step(&mut ram).unwrap();
+        assert_eq!(cpu.registers.program_counter(), 0x003000);
+
+        ram.put(0x3000, 0x02); // COP
+        let step = cpu.step(&mut ram).unwrap();
+        assert_eq!(step.interrupt, Some(Interrupt::Cop));
+        assert_eq!(cpu.registers.pc, 0x1000);
+
+        ram.put(0x1000, 0x00); // BRK
+        let step = cpu.step(&mut ram).unwrap();
+        assert_eq!(step.interrupt, Some(Interrupt::Brk));
+        assert_eq!(cpu.registers.pc, 0x2000);
+    }
+
+    #[test]
+    fn wai() {
+        let (mut cpu, mut ram) = setup();
+        ram.put(0x0200, 0xCB); // WAI
+
+        assert_eq!(cpu.step(&mut ram).unwrap().cycles, 3);
+        assert_eq!(cpu.step(&mut ram), Err(ExecutionState::Waiting { address: 0x0201 }));
+        assert_eq!(cpu.run_for(&mut ram, 100), (0, ExecutionState::Waiting { address: 0x0201 }));
+
+        // With IRQs disabled, WAI carries on with the next instruction instead of taking the interrupt
+        cpu.set_irq(true);
+        let step = cpu.step(&mut ram).unwrap();
+        assert_eq!((step.address, step.interrupt), (0x0201, None));
+    }
+
+    #[test]
+    fn stp() {
+        let (mut cpu, mut ram) = setup();
+        ram.put(0x0201, 0xDB); // STP
+
+        assert_eq!(cpu.run_for(&mut ram, 100), (2, ExecutionState::Halted { address: 0x0201 }));
+        cpu.set_nmi(true);
+        assert_eq!(cpu.step(&mut ram), Err(ExecutionState::Halted { address: 0x0201 }));
+
+        cpu.reset();
+        assert_eq!(cpu.step(&mut ram).unwrap().interrupt, Some(Interrupt::Reset));
+        assert_eq!(cpu.registers.pc, 0x0200);
+    }
+
+    #[test]
+    fn interrupted_block_move() {
+        let (mut cpu, mut ram) = setup();
+        ram.put(0x0200, 0x54); // MVN $00,$00
+        ram.put(0x0201, 0x00);
+        ram.put(0x0202, 0x00);
+        ram.put(0x1000, 0x11);
+        ram.put(0x1001, 0x22);
+        cpu.registers.emulation = false;
+        cpu.registers.status = StatusRegister::new(0);
+        (cpu.registers.a, cpu.registers.x, cpu.registers.y) = (0x0001, 0x1000, 0x2000);
+
+        // The interrupt returns to the move, which picks up where it left off
+        cpu.step(&mut ram).unwrap();
+        cpu.set_nmi(true);
+        assert_eq!(cpu.step(&mut ram).unwrap().interrupt, Some(Interrupt::Nmi));
+        ram.put(0x3000, 0x40); // RTI
+        cpu.step(&mut ram).unwrap();
+        assert_eq!(cpu.registers.pc, 0x0200);
+        cpu.step(&mut ram).unwrap();
+        assert_eq!(cpu.registers.pc, 0x0203);
+        assert_eq!((ram.get(0x2000), ram.get(0x2001)), (0x11, 0x22));
+    }
+}
@@ -0,0 +1,163 @@
+use crate::io_device::IODevice;
+use crate::memory;
+
+use super::variant::Variant;
+
+/// The 6510's on-chip I/O port. Each bit of the data direction register makes the matching pin an output
+/// driven from the data register when it's set, or an input when it's clear. On the C64 the pins select which
+/// ROMs are banked in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IOPort {
+    pub direction: u8,
+    pub data: u8,
+    inputs: u8,
+}
+
+impl IOPort {
+    pub const DIRECTION_ADDRESS: u16 = 0x0000;
+    pub const DATA_ADDRESS: u16 = 0x0001;
+
+    /// A port fresh out of reset, with every pin an input. Nothing is driving the inputs, so the pull-ups hold
+    /// them high.
+    pub fn new() -> Self {
+        Self { direction: 0, data: 0, inputs: 0xFF }
+    }
+
+    /// Reset turns every pin back into an input.
+    pub fn reset(&mut self) {
+        self.direction = 0;
+        self.data = 0;
+    }
+
+    /// Drive the pins from outside. Only the ones set as inputs take any notice.
+    pub fn set_inputs(&mut self, inputs: u8) {
+        self.inputs = inputs;
+    }
+
+    /// The level of every pin: the data register where it's an output, and whatever drives it where it's an input.
+    pub fn pins(&self) -> u8 {
+        (self.data & self.direction) | (self.inputs & !self.direction)
+    }
+
+    fn get(&self, addr: u16) -> u8 {
+        match addr {
+            Self::DIRECTION_ADDRESS => self.direction,
+            _ => self.pins(),
+        }
+    }
+
+    fn put(&mut self, addr: u16, value: u8) {
+        match addr {
+            Self::DIRECTION_ADDRESS => self.direction = value,
+            _ => self.data = value,
+        }
+    }
+}
+
+impl Default for IOPort {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The bus as a variant sees it through its pins. Addresses are masked down to the address lines it has, and
+/// on the 6510 the I/O port answers reads from $0000 and $0001. Writes there still reach the bus as well, the
+/// way they reach the RAM underneath on the C64.
+pub struct VariantBus<'a> {
+    bus: &'a mut dyn IODevice,
+    mask: u16,
+    port: Option<&'a mut IOPort>,
+}
+
+impl<'a> VariantBus<'a> {
+    pub fn new(variant: Variant, bus: &'a mut dyn IODevice, port: &'a mut IOPort) -> Self {
+        Self { bus, mask: variant.address_mask(), port: variant.io_port().then_some(port) }
+    }
+}
+
+impl IODevice for VariantBus<'_> {
+    fn get(&self, addr: u16) -> u8 {
+        let addr = addr & self.mask;
+        match &self.port {
+            Some(port) if addr <= IOPort::DATA_ADDRESS => port.get(addr),
+            _ => self.bus.get(addr),
+        }
+    }
+
+    fn get_hl(&self, high: u8, low: u8) -> u8 {
+        self.get(memory::hl_to_addr(high, low))
+    }
+
+    fn put(&mut self, addr: u16, value: u8) {
+        let addr = addr & self.mask;
+        if let Some(port) = &mut self.port {
+            if addr <= IOPort::DATA_ADDRESS {
+                port.put(addr, value);
+            }
+        }
+        self.bus.put(addr, value);
+    }
+
+    fn put_hl(&mut self, high: u8, low: u8, value: u8) {
+        self.put(memory::hl_to_addr(high, low), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::io_port::{IOPort, VariantBus};
+    use crate::cpu::variant::Variant;
+    use crate::io_device::IODevice;
+    use crate::memory::ram::RAM;
+
+    #[test]
+    fn pins() {
+        let mut port = IOPort::new();
+        assert_eq!(port.pins(), 0xFF);
+
+        // Only the outputs follow the data register, and only the inputs follow what's driving them
+        port.direction = 0x0F;
+        port.data = 0x05;
+        assert_eq!(port.pins(), 0xF5);
+        port.set_inputs(0x3F);
+        assert_eq!(port.pins(), 0x35);
+
+        port.reset();
+        assert_eq!(port.pins(), 0x3F);
+    }
+
+    #[test]
+    fn port_in_front_of_bus() {
+        let mut ram = RAM::<0x10000>::new(None);
+        ram.put(0x0000, 0x99);
+        ram.put(0x0001, 0x99);
+        let mut port = IOPort::new();
+
+        let mut bus = VariantBus::new(Variant::Mos6510, &mut ram, &mut port);
+        assert_eq!(bus.get(0x0000), 0x00);
+        assert_eq!(bus.get(0x0001), 0xFF);
+        bus.put(0x0000, 0x2F);
+        bus.put_hl(0x00, 0x01, 0x35);
+        assert_eq!(bus.get(0x0001), 0xF5);
+        assert_eq!(bus.get(0x0002), 0x00);
+
+        assert_eq!((port.direction, port.data), (0x2F, 0x35));
+        assert_eq!((ram.get(0x0000), ram.get(0x0001)), (0x2F, 0x35));
+    }
+
+    #[test]
+    fn address_mask() {
+        let mut ram = RAM::<0x10000>::new(None);
+        let mut port = IOPort::new();
+
+        let mut bus = VariantBus::new(Variant::Mos6507, &mut ram, &mut port);
+        bus.put(0xF001, 0x42);
+        assert_eq!(bus.get(0x1001), 0x42);
+        assert_eq!(bus.get_hl(0x30, 0x01), 0x42);
+
+        // The 6507 has no port, so $0001 is just memory
+        assert_eq!(bus.get(0x0001), 0x00);
+        assert_eq!(ram.get(0x1001), 0x42);
+        assert_eq!(ram.get(0xF001), 0x00);
+    }
+}
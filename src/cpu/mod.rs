@@ -1,3 +1,8 @@
 pub mod cpu_6502;
+pub mod cpu_65816;
+pub mod io_port;
 pub mod status_register;
+pub mod opcode_info;
 pub mod opcodes;
+pub mod opcodes_65816;
+pub mod variant;
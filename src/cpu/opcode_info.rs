@@ -0,0 +1,679 @@
+use super::status_register::StatusRegister;
+use super::variant::Variant;
+
+/*
+ * What each opcode is, for anything that needs to know without running it: debuggers, disassemblers and
+ * traces. The NMOS variants all share one table, and the 65C02 has its own, since the same opcode can be a
+ * different instruction on each. The tests run every opcode through the CPU and check it against these tables,
+ * so they're kept honest.
+ *
+ * Resources I've been using:
+ * - https://www.masswerk.at/6502/6502_instruction_set.html
+ * - http://www.oxyron.de/html/opcodes02.html
+ * - http://6502.org/tutorials/65c02opcodes.html
+ */
+
+/// How an instruction finds its operand, which also decides how it's written in assembly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    /// ($xxxx), only used by JMP
+    Indirect,
+    /// ($xx,X)
+    IndexedIndirect,
+    /// ($xx),Y
+    IndirectIndexed,
+    /// ($xx), on the 65C02
+    ZeroPageIndirect,
+    /// ($xxxx,X), only used by the 65C02's JMP
+    AbsoluteIndexedIndirect,
+    Relative,
+    /// $xx,$yy, the zero page address and branch offset of the 65C02's BBR and BBS
+    ZeroPageRelative,
+}
+
+impl AddressingMode {
+    /// The number of bytes an instruction takes up in this mode, opcode included.
+    pub const fn length(&self) -> u8 {
+        match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => 1,
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect
+            | AddressingMode::AbsoluteIndexedIndirect
+            | AddressingMode::ZeroPageRelative => 3,
+            _ => 2,
+        }
+    }
+}
+
+/// Everything there is to know about an opcode short of running it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    /// Bytes, opcode included
+    pub length: u8,
+    /// Cycles, opcode fetch included. Conditional branches take one more when they're taken, and JAM, STP and
+    /// WAI count the cycles before the CPU stops.
+    pub cycles: u8,
+    /// Takes one more cycle when indexing crosses a page, or when a branch is taken to another page
+    pub page_cross: bool,
+    /// Takes one more cycle in decimal mode, as the 65C02's ADC and SBC do
+    pub decimal_cycle: bool,
+    /// The flags that make any difference to what the instruction does
+    pub flags_read: u8,
+    /// The flags the instruction can change
+    pub flags_written: u8,
+    /// Whether the opcode is documented, rather than something the decoder happens to make of it
+    pub legal: bool,
+}
+
+impl OpcodeInfo {
+    const fn new(mnemonic: &'static str) -> Self {
+        Self {
+            mnemonic,
+            mode: AddressingMode::Implied,
+            length: 1,
+            cycles: 2,
+            page_cross: false,
+            decimal_cycle: false,
+            flags_read: 0,
+            flags_written: 0,
+            legal: true,
+        }
+    }
+
+    const fn reads(mut self, flags: u8) -> Self {
+        self.flags_read = flags;
+        self
+    }
+
+    const fn writes(mut self, flags: u8) -> Self {
+        self.flags_written = flags;
+        self
+    }
+
+    const fn undocumented(mut self) -> Self {
+        self.legal = false;
+        self
+    }
+
+    const fn mode(mut self, mode: AddressingMode, cycles: u8) -> Self {
+        self.mode = mode;
+        self.length = mode.length();
+        self.cycles = cycles;
+        self
+    }
+
+    const fn crossing(mut self) -> Self {
+        self.page_cross = true;
+        self
+    }
+
+    const fn decimal(mut self) -> Self {
+        self.decimal_cycle = true;
+        self
+    }
+}
+
+const C: u8 = StatusRegister::CARRY.bits();
+const Z: u8 = StatusRegister::ZERO.bits();
+const I: u8 = StatusRegister::IRQ_DISABLE.bits();
+const D: u8 = StatusRegister::DECIMAL_MODE.bits();
+const V: u8 = StatusRegister::OVERFLOW.bits();
+const N: u8 = StatusRegister::NEGATIVE.bits();
+/// Every flag P holds, as PHP and BRK push and PLP and RTI pull
+const ALL: u8 = N | V | D | I | Z | C;
+
+use AddressingMode::*;
+
+/// The opcode tables, indexed by the opcode.
+pub static NMOS_OPCODES: [OpcodeInfo; 256] = nmos_table();
+pub static CMOS_OPCODES: [OpcodeInfo; 256] = cmos_table();
+pub static BINARY_OPCODES: [OpcodeInfo; 256] = binary_table();
+
+/// Look an opcode up as a variant decodes it.
+pub fn lookup(opcode: u8, variant: Variant) -> OpcodeInfo {
+    let table = match variant {
+        Variant::Wdc65C02 => &CMOS_OPCODES,
+        _ if !variant.decimal_mode() => &BINARY_OPCODES,
+        _ => &NMOS_OPCODES,
+    };
+    table[opcode as usize]
+}
+
+/// The documented opcodes, which every variant shares.
+const fn documented_table() -> [OpcodeInfo; 256] {
+    let mut table = [OpcodeInfo::new("???"); 256];
+
+    // ADC
+    let adc = OpcodeInfo::new("ADC").reads(C | D).writes(N | V | Z | C);
+    table[0x69] = adc.mode(Immediate, 2);
+    table[0x65] = adc.mode(ZeroPage, 3);
+    table[0x75] = adc.mode(ZeroPageX, 4);
+    table[0x6d] = adc.mode(Absolute, 4);
+    table[0x7d] = adc.mode(AbsoluteX, 4).crossing();
+    table[0x79] = adc.mode(AbsoluteY, 4).crossing();
+    table[0x61] = adc.mode(IndexedIndirect, 6);
+    table[0x71] = adc.mode(IndirectIndexed, 5).crossing();
+
+    // AND
+    let and = OpcodeInfo::new("AND").writes(N | Z);
+    table[0x29] = and.mode(Immediate, 2);
+    table[0x25] = and.mode(ZeroPage, 3);
+    table[0x35] = and.mode(ZeroPageX, 4);
+    table[0x2d] = and.mode(Absolute, 4);
+    table[0x3d] = and.mode(AbsoluteX, 4).crossing();
+    table[0x39] = and.mode(AbsoluteY, 4).crossing();
+    table[0x21] = and.mode(IndexedIndirect, 6);
+    table[0x31] = and.mode(IndirectIndexed, 5).crossing();
+
+    // ASL
+    let asl = OpcodeInfo::new("ASL").writes(N | Z | C);
+    table[0x0a] = asl.mode(Accumulator, 2);
+    table[0x06] = asl.mode(ZeroPage, 5);
+    table[0x16] = asl.mode(ZeroPageX, 6);
+    table[0x0e] = asl.mode(Absolute, 6);
+    table[0x1e] = asl.mode(AbsoluteX, 7);
+
+    // Branches
+    table[0x90] = OpcodeInfo::new("BCC").reads(C).mode(Relative, 2).crossing();
+    table[0xb0] = OpcodeInfo::new("BCS").reads(C).mode(Relative, 2).crossing();
+    table[0xd0] = OpcodeInfo::new("BNE").reads(Z).mode(Relative, 2).crossing();
+    table[0xf0] = OpcodeInfo::new("BEQ").reads(Z).mode(Relative, 2).crossing();
+    table[0x10] = OpcodeInfo::new("BPL").reads(N).mode(Relative, 2).crossing();
+    table[0x30] = OpcodeInfo::new("BMI").reads(N).mode(Relative, 2).crossing();
+    table[0x50] = OpcodeInfo::new("BVC").reads(V).mode(Relative, 2).crossing();
+    table[0x70] = OpcodeInfo::new("BVS").reads(V).mode(Relative, 2).crossing();
+
+    // BIT
+    let bit = OpcodeInfo::new("BIT").writes(N | V | Z);
+    table[0x24] = bit.mode(ZeroPage, 3);
+    table[0x2c] = bit.mode(Absolute, 4);
+
+    // Flag instructions
+    table[0x18] = OpcodeInfo::new("CLC").writes(C);
+    table[0xd8] = OpcodeInfo::new("CLD").writes(D);
+    table[0x58] = OpcodeInfo::new("CLI").writes(I);
+    table[0xb8] = OpcodeInfo::new("CLV").writes(V);
+    table[0x38] = OpcodeInfo::new("SEC").writes(C);
+    table[0xf8] = OpcodeInfo::new("SED").writes(D);
+    table[0x78] = OpcodeInfo::new("SEI").writes(I);
+
+    // CMP
+    let cmp = OpcodeInfo::new("CMP").writes(N | Z | C);
+    table[0xc9] = cmp.mode(Immediate, 2);
+    table[0xc5] = cmp.mode(ZeroPage, 3);
+    table[0xd5] = cmp.mode(ZeroPageX, 4);
+    table[0xcd] = cmp.mode(Absolute, 4);
+    table[0xdd] = cmp.mode(AbsoluteX, 4).crossing();
+    table[0xd9] = cmp.mode(AbsoluteY, 4).crossing();
+    table[0xc1] = cmp.mode(IndexedIndirect, 6);
+    table[0xd1] = cmp.mode(IndirectIndexed, 5).crossing();
+
+    // CPX
+    let cpx = OpcodeInfo::new("CPX").writes(N | Z | C);
+    table[0xe0] = cpx.mode(Immediate, 2);
+    table[0xe4] = cpx.mode(ZeroPage, 3);
+    table[0xec] = cpx.mode(Absolute, 4);
+
+    // CPY
+    let cpy = OpcodeInfo::new("CPY").writes(N | Z | C);
+    table[0xc0] = cpy.mode(Immediate, 2);
+    table[0xc4] = cpy.mode(ZeroPage, 3);
+    table[0xcc] = cpy.mode(Absolute, 4);
+
+    // DEC
+    let dec = OpcodeInfo::new("DEC").writes(N | Z);
+    table[0xc6] = dec.mode(ZeroPage, 5);
+    table[0xd6] = dec.mode(ZeroPageX, 6);
+    table[0xce] = dec.mode(Absolute, 6);
+    table[0xde] = dec.mode(AbsoluteX, 7);
+    table[0xca] = OpcodeInfo::new("DEX").writes(N | Z);
+    table[0x88] = OpcodeInfo::new("DEY").writes(N | Z);
+
+    // EOR
+    let eor = OpcodeInfo::new("EOR").writes(N | Z);
+    table[0x49] = eor.mode(Immediate, 2);
+    table[0x45] = eor.mode(ZeroPage, 3);
+    table[0x55] = eor.mode(ZeroPageX, 4);
+    table[0x4d] = eor.mode(Absolute, 4);
+    table[0x5d] = eor.mode(AbsoluteX, 4).crossing();
+    table[0x59] = eor.mode(AbsoluteY, 4).crossing();
+    table[0x41] = eor.mode(IndexedIndirect, 6);
+    table[0x51] = eor.mode(IndirectIndexed, 5).crossing();
+
+    // INC
+    let inc = OpcodeInfo::new("INC").writes(N | Z);
+    table[0xe6] = inc.mode(ZeroPage, 5);
+    table[0xf6] = inc.mode(ZeroPageX, 6);
+    table[0xee] = inc.mode(Absolute, 6);
+    table[0xfe] = inc.mode(AbsoluteX, 7);
+    table[0xe8] = OpcodeInfo::new("INX").writes(N | Z);
+    table[0xc8] = OpcodeInfo::new("INY").writes(N | Z);
+
+    // JMP
+    table[0x4c] = OpcodeInfo::new("JMP").mode(Absolute, 3);
+    table[0x6c] = OpcodeInfo::new("JMP").mode(Indirect, 5);
+
+    // Subroutines and interrupts
+    table[0x20] = OpcodeInfo::new("JSR").mode(Absolute, 6);
+    table[0x60] = OpcodeInfo::new("RTS").mode(Implied, 6);
+    table[0x40] = OpcodeInfo::new("RTI").writes(ALL).mode(Implied, 6);
+    table[0x00] = OpcodeInfo::new("BRK").reads(ALL).writes(I).mode(Implied, 7);
+
+    // LDA
+    let lda = OpcodeInfo::new("LDA").writes(N | Z);
+    table[0xa9] = lda.mode(Immediate, 2);
+    table[0xa5] = lda.mode(ZeroPage, 3);
+    table[0xb5] = lda.mode(ZeroPageX, 4);
+    table[0xad] = lda.mode(Absolute, 4);
+    table[0xbd] = lda.mode(AbsoluteX, 4).crossing();
+    table[0xb9] = lda.mode(AbsoluteY, 4).crossing();
+    table[0xa1] = lda.mode(IndexedIndirect, 6);
+    table[0xb1] = lda.mode(IndirectIndexed, 5).crossing();
+
+    // LDX
+    let ldx = OpcodeInfo::new("LDX").writes(N | Z);
+    table[0xa2] = ldx.mode(Immediate, 2);
+    table[0xa6] = ldx.mode(ZeroPage, 3);
+    table[0xb6] = ldx.mode(ZeroPageY, 4);
+    table[0xae] = ldx.mode(Absolute, 4);
+    table[0xbe] = ldx.mode(AbsoluteY, 4).crossing();
+
+    // LDY
+    let ldy = OpcodeInfo::new("LDY").writes(N | Z);
+    table[0xa0] = ldy.mode(Immediate, 2);
+    table[0xa4] = ldy.mode(ZeroPage, 3);
+    table[0xb4] = ldy.mode(ZeroPageX, 4);
+    table[0xac] = ldy.mode(Absolute, 4);
+    table[0xbc] = ldy.mode(AbsoluteX, 4).crossing();
+
+    // LSR
+    let lsr = OpcodeInfo::new("LSR").writes(N | Z | C);
+    table[0x4a] = lsr.mode(Accumulator, 2);
+    table[0x46] = lsr.mode(ZeroPage, 5);
+    table[0x56] = lsr.mode(ZeroPageX, 6);
+    table[0x4e] = lsr.mode(Absolute, 6);
+    table[0x5e] = lsr.mode(AbsoluteX, 7);
+
+    // NOP
+    table[0xea] = OpcodeInfo::new("NOP");
+
+    // ORA
+    let ora = OpcodeInfo::new("ORA").writes(N | Z);
+    table[0x09] = ora.mode(Immediate, 2);
+    table[0x05] = ora.mode(ZeroPage, 3);
+    table[0x15] = ora.mode(ZeroPageX, 4);
+    table[0x0d] = ora.mode(Absolute, 4);
+    table[0x1d] = ora.mode(AbsoluteX, 4).crossing();
+    table[0x19] = ora.mode(AbsoluteY, 4).crossing();
+    table[0x01] = ora.mode(IndexedIndirect, 6);
+    table[0x11] = ora.mode(IndirectIndexed, 5).crossing();
+
+    // ROL
+    let rol = OpcodeInfo::new("ROL").reads(C).writes(N | Z | C);
+    table[0x2a] = rol.mode(Accumulator, 2);
+    table[0x26] = rol.mode(ZeroPage, 5);
+    table[0x36] = rol.mode(ZeroPageX, 6);
+    table[0x2e] = rol.mode(Absolute, 6);
+    table[0x3e] = rol.mode(AbsoluteX, 7);
+
+    // ROR
+    let ror = OpcodeInfo::new("ROR").reads(C).writes(N | Z | C);
+    table[0x6a] = ror.mode(Accumulator, 2);
+    table[0x66] = ror.mode(ZeroPage, 5);
+    table[0x76] = ror.mode(ZeroPageX, 6);
+    table[0x6e] = ror.mode(Absolute, 6);
+    table[0x7e] = ror.mode(AbsoluteX, 7);
+
+    // SBC
+    let sbc = OpcodeInfo::new("SBC").reads(C | D).writes(N | V | Z | C);
+    table[0xe9] = sbc.mode(Immediate, 2);
+    table[0xe5] = sbc.mode(ZeroPage, 3);
+    table[0xf5] = sbc.mode(ZeroPageX, 4);
+    table[0xed] = sbc.mode(Absolute, 4);
+    table[0xfd] = sbc.mode(AbsoluteX, 4).crossing();
+    table[0xf9] = sbc.mode(AbsoluteY, 4).crossing();
+    table[0xe1] = sbc.mode(IndexedIndirect, 6);
+    table[0xf1] = sbc.mode(IndirectIndexed, 5).crossing();
+
+    // STA
+    let sta = OpcodeInfo::new("STA");
+    table[0x85] = sta.mode(ZeroPage, 3);
+    table[0x95] = sta.mode(ZeroPageX, 4);
+    table[0x8d] = sta.mode(Absolute, 4);
+    table[0x9d] = sta.mode(AbsoluteX, 5);
+    table[0x99] = sta.mode(AbsoluteY, 5);
+    table[0x81] = sta.mode(IndexedIndirect, 6);
+    table[0x91] = sta.mode(IndirectIndexed, 6);
+
+    // STX
+    let stx = OpcodeInfo::new("STX");
+    table[0x86] = stx.mode(ZeroPage, 3);
+    table[0x96] = stx.mode(ZeroPageY, 4);
+    table[0x8e] = stx.mode(Absolute, 4);
+
+    // STY
+    let sty = OpcodeInfo::new("STY");
+    table[0x84] = sty.mode(ZeroPage, 3);
+    table[0x94] = sty.mode(ZeroPageX, 4);
+    table[0x8c] = sty.mode(Absolute, 4);
+
+    // Register transfers
+    table[0xaa] = OpcodeInfo::new("TAX").writes(N | Z);
+    table[0xa8] = OpcodeInfo::new("TAY").writes(N | Z);
+    table[0x8a] = OpcodeInfo::new("TXA").writes(N | Z);
+    table[0x98] = OpcodeInfo::new("TYA").writes(N | Z);
+    table[0xba] = OpcodeInfo::new("TSX").writes(N | Z);
+    table[0x9a] = OpcodeInfo::new("TXS");
+
+    // Stack
+    table[0x48] = OpcodeInfo::new("PHA").mode(Implied, 3);
+    table[0x08] = OpcodeInfo::new("PHP").reads(ALL).mode(Implied, 3);
+    table[0x68] = OpcodeInfo::new("PLA").writes(N | Z).mode(Implied, 4);
+    table[0x28] = OpcodeInfo::new("PLP").writes(ALL).mode(Implied, 4);
+
+    table
+}
+
+/// The NMOS part, with the names the undocumented opcodes usually go by.
+const fn nmos_table() -> [OpcodeInfo; 256] {
+    let mut table = documented_table();
+
+    // JAM
+    let mut jams = [0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2].as_slice();
+    while let [opcode, rest @ ..] = jams {
+        table[*opcode] = OpcodeInfo::new("JAM").undocumented();
+        jams = rest;
+    }
+
+    // Undocumented
+    // SLO
+    let slo = OpcodeInfo::new("SLO").writes(N | Z | C).undocumented();
+    table[0x07] = slo.mode(ZeroPage, 5);
+    table[0x17] = slo.mode(ZeroPageX, 6);
+    table[0x0f] = slo.mode(Absolute, 6);
+    table[0x1f] = slo.mode(AbsoluteX, 7);
+    table[0x1b] = slo.mode(AbsoluteY, 7);
+    table[0x03] = slo.mode(IndexedIndirect, 8);
+    table[0x13] = slo.mode(IndirectIndexed, 8);
+
+    // RLA
+    let rla = OpcodeInfo::new("RLA").reads(C).writes(N | Z | C).undocumented();
+    table[0x27] = rla.mode(ZeroPage, 5);
+    table[0x37] = rla.mode(ZeroPageX, 6);
+    table[0x2f] = rla.mode(Absolute, 6);
+    table[0x3f] = rla.mode(AbsoluteX, 7);
+    table[0x3b] = rla.mode(AbsoluteY, 7);
+    table[0x23] = rla.mode(IndexedIndirect, 8);
+    table[0x33] = rla.mode(IndirectIndexed, 8);
+
+    // SRE
+    let sre = OpcodeInfo::new("SRE").writes(N | Z | C).undocumented();
+    table[0x47] = sre.mode(ZeroPage, 5);
+    table[0x57] = sre.mode(ZeroPageX, 6);
+    table[0x4f] = sre.mode(Absolute, 6);
+    table[0x5f] = sre.mode(AbsoluteX, 7);
+    table[0x5b] = sre.mode(AbsoluteY, 7);
+    table[0x43] = sre.mode(IndexedIndirect, 8);
+    table[0x53] = sre.mode(IndirectIndexed, 8);
+
+    // RRA
+    let rra = OpcodeInfo::new("RRA").reads(C | D).writes(N | V | Z | C).undocumented();
+    table[0x67] = rra.mode(ZeroPage, 5);
+    table[0x77] = rra.mode(ZeroPageX, 6);
+    table[0x6f] = rra.mode(Absolute, 6);
+    table[0x7f] = rra.mode(AbsoluteX, 7);
+    table[0x7b] = rra.mode(AbsoluteY, 7);
+    table[0x63] = rra.mode(IndexedIndirect, 8);
+    table[0x73] = rra.mode(IndirectIndexed, 8);
+
+    // SAX
+    let sax = OpcodeInfo::new("SAX").undocumented();
+    table[0x87] = sax.mode(ZeroPage, 3);
+    table[0x97] = sax.mode(ZeroPageY, 4);
+    table[0x8f] = sax.mode(Absolute, 4);
+    table[0x83] = sax.mode(IndexedIndirect, 6);
+
+    // LAX
+    let lax = OpcodeInfo::new("LAX").writes(N | Z).undocumented();
+    table[0xa7] = lax.mode(ZeroPage, 3);
+    table[0xb7] = lax.mode(ZeroPageY, 4);
+    table[0xaf] = lax.mode(Absolute, 4);
+    table[0xbf] = lax.mode(AbsoluteY, 4).crossing();
+    table[0xa3] = lax.mode(IndexedIndirect, 6);
+    table[0xb3] = lax.mode(IndirectIndexed, 5).crossing();
+
+    // DCP
+    let dcp = OpcodeInfo::new("DCP").writes(N | Z | C).undocumented();
+    table[0xc7] = dcp.mode(ZeroPage, 5);
+    table[0xd7] = dcp.mode(ZeroPageX, 6);
+    table[0xcf] = dcp.mode(Absolute, 6);
+    table[0xdf] = dcp.mode(AbsoluteX, 7);
+    table[0xdb] = dcp.mode(AbsoluteY, 7);
+    table[0xc3] = dcp.mode(IndexedIndirect, 8);
+    table[0xd3] = dcp.mode(IndirectIndexed, 8);
+
+    // ISC
+    let isc = OpcodeInfo::new("ISC").reads(C | D).writes(N | V | Z | C).undocumented();
+    table[0xe7] = isc.mode(ZeroPage, 5);
+    table[0xf7] = isc.mode(ZeroPageX, 6);
+    table[0xef] = isc.mode(Absolute, 6);
+    table[0xff] = isc.mode(AbsoluteX, 7);
+    table[0xfb] = isc.mode(AbsoluteY, 7);
+    table[0xe3] = isc.mode(IndexedIndirect, 8);
+    table[0xf3] = isc.mode(IndirectIndexed, 8);
+
+    // Immediate
+    table[0x0b] = OpcodeInfo::new("ANC").writes(N | Z | C).undocumented().mode(Immediate, 2);
+    table[0x2b] = OpcodeInfo::new("ANC").writes(N | Z | C).undocumented().mode(Immediate, 2);
+    table[0x4b] = OpcodeInfo::new("ALR").writes(N | Z | C).undocumented().mode(Immediate, 2);
+    table[0x6b] = OpcodeInfo::new("ARR").reads(C | D).writes(N | V | Z | C).undocumented().mode(Immediate, 2);
+    table[0xcb] = OpcodeInfo::new("SBX").writes(N | Z | C).undocumented().mode(Immediate, 2);
+    table[0xeb] = OpcodeInfo::new("SBC").reads(C | D).writes(N | V | Z | C).undocumented().mode(Immediate, 2);
+
+    // Unstable
+    table[0x8b] = OpcodeInfo::new("ANE").writes(N | Z).undocumented().mode(Immediate, 2);
+    table[0xab] = OpcodeInfo::new("LXA").writes(N | Z).undocumented().mode(Immediate, 2);
+    table[0xbb] = OpcodeInfo::new("LAS").writes(N | Z).undocumented().mode(AbsoluteY, 4).crossing();
+    table[0x93] = OpcodeInfo::new("SHA").undocumented().mode(IndirectIndexed, 6);
+    table[0x9f] = OpcodeInfo::new("SHA").undocumented().mode(AbsoluteY, 5);
+    table[0x9e] = OpcodeInfo::new("SHX").undocumented().mode(AbsoluteY, 5);
+    table[0x9c] = OpcodeInfo::new("SHY").undocumented().mode(AbsoluteX, 5);
+    table[0x9b] = OpcodeInfo::new("TAS").undocumented().mode(AbsoluteY, 5);
+
+    // NOPs, which go through the motions of their addressing mode
+    let nop = OpcodeInfo::new("NOP").undocumented();
+    let mut implied = [0x1a, 0x3a, 0x5a, 0x7a, 0xda, 0xfa].as_slice();
+    while let [opcode, rest @ ..] = implied {
+        table[*opcode] = nop;
+        implied = rest;
+    }
+    let mut immediates = [0x80, 0x82, 0x89, 0xc2, 0xe2].as_slice();
+    while let [opcode, rest @ ..] = immediates {
+        table[*opcode] = nop.mode(Immediate, 2);
+        immediates = rest;
+    }
+    table[0x04] = nop.mode(ZeroPage, 3);
+    table[0x44] = nop.mode(ZeroPage, 3);
+    table[0x64] = nop.mode(ZeroPage, 3);
+    let mut zero_page_x = [0x14, 0x34, 0x54, 0x74, 0xd4, 0xf4].as_slice();
+    while let [opcode, rest @ ..] = zero_page_x {
+        table[*opcode] = nop.mode(ZeroPageX, 4);
+        zero_page_x = rest;
+    }
+    table[0x0c] = nop.mode(Absolute, 4);
+    let mut absolute_x = [0x1c, 0x3c, 0x5c, 0x7c, 0xdc, 0xfc].as_slice();
+    while let [opcode, rest @ ..] = absolute_x {
+        table[*opcode] = nop.mode(AbsoluteX, 4).crossing();
+        absolute_x = rest;
+    }
+
+    table
+}
+
+/// The NMOS part without decimal mode, as in the 2A03, where only PHP and BRK, which push it, pay any attention
+/// to D.
+const fn binary_table() -> [OpcodeInfo; 256] {
+    let mut table = nmos_table();
+
+    let mut opcode = 0;
+    while opcode < 0x100 {
+        if table[opcode].flags_read != ALL {
+            table[opcode] = table[opcode].reads(table[opcode].flags_read & !D);
+        }
+        opcode += 1;
+    }
+
+    table
+}
+
+/// The 65C02, which adds its own instructions and makes every other opcode a NOP.
+const fn cmos_table() -> [OpcodeInfo; 256] {
+    let mut table = documented_table();
+
+    // ADC and SBC, which take a cycle longer in decimal mode
+    let mut decimal = [0x69, 0x65, 0x75, 0x6d, 0x7d, 0x79, 0x61, 0x71, 0xe9, 0xe5, 0xf5, 0xed, 0xfd, 0xf9, 0xe1, 0xf1]
+        .as_slice();
+    while let [opcode, rest @ ..] = decimal {
+        table[*opcode] = table[*opcode].decimal();
+        decimal = rest;
+    }
+
+    // BRK clears D as well as setting I
+    table[0x00] = table[0x00].writes(I | D);
+
+    // JMP ($xxxx) takes a cycle longer, now that it carries into the high byte of the pointer
+    table[0x6c] = table[0x6c].mode(Indirect, 6);
+
+    // Shifts and rotates, which only fix the address up when the index crosses a page
+    table[0x1e] = table[0x1e].mode(AbsoluteX, 6).crossing();
+    table[0x5e] = table[0x5e].mode(AbsoluteX, 6).crossing();
+    table[0x3e] = table[0x3e].mode(AbsoluteX, 6).crossing();
+    table[0x7e] = table[0x7e].mode(AbsoluteX, 6).crossing();
+
+    // (zp)
+    table[0x12] = OpcodeInfo::new("ORA").writes(N | Z).mode(ZeroPageIndirect, 5);
+    table[0x32] = OpcodeInfo::new("AND").writes(N | Z).mode(ZeroPageIndirect, 5);
+    table[0x52] = OpcodeInfo::new("EOR").writes(N | Z).mode(ZeroPageIndirect, 5);
+    table[0x72] = OpcodeInfo::new("ADC").reads(C | D).writes(N | V | Z | C).mode(ZeroPageIndirect, 5).decimal();
+    table[0x92] = OpcodeInfo::new("STA").mode(ZeroPageIndirect, 5);
+    table[0xb2] = OpcodeInfo::new("LDA").writes(N | Z).mode(ZeroPageIndirect, 5);
+    table[0xd2] = OpcodeInfo::new("CMP").writes(N | Z | C).mode(ZeroPageIndirect, 5);
+    table[0xf2] = OpcodeInfo::new("SBC").reads(C | D).writes(N | V | Z | C).mode(ZeroPageIndirect, 5).decimal();
+
+    // BIT, which only sets Z when it's immediate
+    table[0x89] = OpcodeInfo::new("BIT").writes(Z).mode(Immediate, 2);
+    table[0x34] = OpcodeInfo::new("BIT").writes(N | V | Z).mode(ZeroPageX, 4);
+    table[0x3c] = OpcodeInfo::new("BIT").writes(N | V | Z).mode(AbsoluteX, 4).crossing();
+
+    // INC A, DEC A
+    table[0x1a] = OpcodeInfo::new("INC").writes(N | Z).mode(Accumulator, 2);
+    table[0x3a] = OpcodeInfo::new("DEC").writes(N | Z).mode(Accumulator, 2);
+
+    // BRA, which is always taken
+    table[0x80] = OpcodeInfo::new("BRA").mode(Relative, 3).crossing();
+
+    // JMP
+    table[0x7c] = OpcodeInfo::new("JMP").mode(AbsoluteIndexedIndirect, 6);
+
+    // Stack
+    table[0xda] = OpcodeInfo::new("PHX").mode(Implied, 3);
+    table[0x5a] = OpcodeInfo::new("PHY").mode(Implied, 3);
+    table[0xfa] = OpcodeInfo::new("PLX").writes(N | Z).mode(Implied, 4);
+    table[0x7a] = OpcodeInfo::new("PLY").writes(N | Z).mode(Implied, 4);
+
+    // STZ
+    let stz = OpcodeInfo::new("STZ");
+    table[0x64] = stz.mode(ZeroPage, 3);
+    table[0x74] = stz.mode(ZeroPageX, 4);
+    table[0x9c] = stz.mode(Absolute, 4);
+    table[0x9e] = stz.mode(AbsoluteX, 5);
+
+    // TSB, TRB
+    table[0x04] = OpcodeInfo::new("TSB").writes(Z).mode(ZeroPage, 5);
+    table[0x0c] = OpcodeInfo::new("TSB").writes(Z).mode(Absolute, 6);
+    table[0x14] = OpcodeInfo::new("TRB").writes(Z).mode(ZeroPage, 5);
+    table[0x1c] = OpcodeInfo::new("TRB").writes(Z).mode(Absolute, 6);
+
+    // RMB, SMB, BBR, BBS, with the bit they work on in the mnemonic
+    let rmb = ["RMB0", "RMB1", "RMB2", "RMB3", "RMB4", "RMB5", "RMB6", "RMB7"];
+    let smb = ["SMB0", "SMB1", "SMB2", "SMB3", "SMB4", "SMB5", "SMB6", "SMB7"];
+    let bbr = ["BBR0", "BBR1", "BBR2", "BBR3", "BBR4", "BBR5", "BBR6", "BBR7"];
+    let bbs = ["BBS0", "BBS1", "BBS2", "BBS3", "BBS4", "BBS5", "BBS6", "BBS7"];
+    let mut bit = 0;
+    while bit < 8 {
+        table[0x07 + bit * 0x10] = OpcodeInfo::new(rmb[bit]).mode(ZeroPage, 5);
+        table[0x87 + bit * 0x10] = OpcodeInfo::new(smb[bit]).mode(ZeroPage, 5);
+        table[0x0f + bit * 0x10] = OpcodeInfo::new(bbr[bit]).mode(ZeroPageRelative, 5).crossing();
+        table[0x8f + bit * 0x10] = OpcodeInfo::new(bbs[bit]).mode(ZeroPageRelative, 5).crossing();
+        bit += 1;
+    }
+
+    // WAI, STP
+    table[0xcb] = OpcodeInfo::new("WAI").mode(Implied, 3);
+    table[0xdb] = OpcodeInfo::new("STP").mode(Implied, 3);
+
+    // NOPs. The rest of columns 3 and B are done with on the opcode fetch, and the others read an operand the
+    // way the NMOS part's NOPs in the same place do.
+    let nop = OpcodeInfo::new("NOP").undocumented();
+    let mut opcode = 0;
+    while opcode < 0x100 {
+        if opcode & 0x07 == 0x03 && opcode != 0xcb && opcode != 0xdb {
+            table[opcode] = nop.mode(Implied, 1);
+        }
+        opcode += 1;
+    }
+    let mut immediates = [0x02, 0x22, 0x42, 0x62, 0x82, 0xc2, 0xe2].as_slice();
+    while let [opcode, rest @ ..] = immediates {
+        table[*opcode] = nop.mode(Immediate, 2);
+        immediates = rest;
+    }
+    table[0x44] = nop.mode(ZeroPage, 3);
+    table[0x54] = nop.mode(ZeroPageX, 4);
+    table[0xd4] = nop.mode(ZeroPageX, 4);
+    table[0xf4] = nop.mode(ZeroPageX, 4);
+    table[0xdc] = nop.mode(Absolute, 4);
+    table[0xfc] = nop.mode(Absolute, 4);
+    table[0x5c] = nop.mode(Absolute, 8);
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::opcode_info::{lookup, AddressingMode, CMOS_OPCODES, NMOS_OPCODES};
+    use crate::cpu::status_register::StatusRegister;
+    use crate::cpu::variant::Variant;
+
+    #[test]
+    fn legal_opcodes() {
+        assert_eq!(NMOS_OPCODES.iter().filter(|info| info.legal).count(), 151);
+        assert_eq!(CMOS_OPCODES.iter().filter(|info| info.legal).count(), 212);
+        assert!(NMOS_OPCODES.iter().chain(CMOS_OPCODES.iter()).all(|info| info.mnemonic != "???"));
+    }
+
+    #[test]
+    fn lookup_by_variant() {
+        let slo = lookup(0x07, Variant::Mos6510);
+        assert_eq!((slo.mnemonic, slo.mode, slo.length, slo.legal), ("SLO", AddressingMode::ZeroPage, 2, false));
+        let rmb = lookup(0x07, Variant::Wdc65C02);
+        assert_eq!((rmb.mnemonic, rmb.mode, rmb.length, rmb.legal), ("RMB0", AddressingMode::ZeroPage, 2, true));
+
+        // The 2A03's ADC doesn't care about D
+        let carry_decimal = StatusRegister::CARRY | StatusRegister::DECIMAL_MODE;
+        assert_eq!(lookup(0x69, Variant::Mos6502).flags_read, carry_decimal.bits());
+        assert_eq!(lookup(0x69, Variant::Ricoh2A03).flags_read, StatusRegister::CARRY.bits());
+    }
+}
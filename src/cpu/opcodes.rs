@@ -1,257 +1,4752 @@
 use crate::io_device::IODevice;
 
 use super::cpu_6502::Registers;
-use super::status_register::StatusRegister;
+use super::opcode_info;
+use super::status_register::{Flags, StatusRegister};
+use super::variant::Variant;
 
-#[derive(Clone, Copy)]
-pub enum InstructionState {
-    Continue,
-    Finished
+/// The ways the CPU can end up running the interrupt sequence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    Brk,
+    Irq,
+    Nmi,
+    Reset,
+}
+
+impl Interrupt {
+    pub const NMI_VECTOR: u16 = 0xFFFA;
+    pub const RESET_VECTOR: u16 = 0xFFFC;
+    pub const IRQ_VECTOR: u16 = 0xFFFE;
+
+    pub fn vector(&self) -> u16 {
+        match self {
+            Interrupt::Nmi => Self::NMI_VECTOR,
+            Interrupt::Reset => Self::RESET_VECTOR,
+            Interrupt::Brk | Interrupt::Irq => Self::IRQ_VECTOR,
+        }
+    }
 }
 
-pub trait Instruction {
-    fn cycle(&mut self, step: usize, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstructionState {
+    Continue,
+    Finished,
+    /// JAM or STP has stopped the CPU until it's reset
+    Jammed,
+    /// WAI has stopped the CPU until an interrupt line is asserted
+    Waiting,
 }
 
-/**
- * I'm trying to make these operations cycle-accurate, but currently that involves injecting an extra
- * cycle for no good reason every now and then. Reasoning about 6502 pipelining gives me a headache.
- * There's a lot that needs to be reexamined and tightened up in here.
+/*
+ * Every cycle performs exactly one bus access, the same one the hardware does, including the reads whose
+ * results are thrown away and the extra write of read-modify-write instructions. Memory-mapped devices can
+ * see every one of them. The opcode fetch is the CPU's first cycle; each step here is one of the cycles after
+ * it, and the step that performs the instruction's last access returns Finished.
+ *
+ * Rather than writing a struct per opcode, each addressing mode's cycles are written once and handed the
+ * operation to perform on the value they fetch (or the value to store). Opcodes decode through a table built
+ * at compile time into an Operation naming both, and everything an instruction works out between cycles
+ * lives in the Instruction alongside it, so running an instruction never allocates.
  *
  * Resources I've been using:
  * - http://archive.6502.org/datasheets/synertek_programming_manual.pdf
  * - https://www.masswerk.at/6502/6502_instruction_set.html
- **/
+ * - http://www.atarihq.com/danb/files/64doc.txt
+ */
 
-// ADC
-// absolute
-// TODO: handle decimal mode
-struct ADC0x6D {
-    adh: u8,
-    adl: u8,
+type ImpliedOp = fn(&mut Registers);
+type ReadOp = fn(&mut Registers, u8);
+type WriteOp = fn(&mut Registers) -> u8;
+type ModifyOp = fn(&mut Registers, u8) -> u8;
+type MagicOp = fn(&mut Registers, u8, u8);
+type StoreHighOp = fn(&mut Registers, u8) -> u8;
+
+/// What an instruction does with the memory location its addressing mode resolves to.
+#[derive(Clone, Copy)]
+enum Access {
+    Read(ReadOp),
+    Write(WriteOp),
+    Modify(ModifyOp),
+    /// The unstable stores (SHA, SHX, SHY and TAS), which AND the value with the high byte of the base address
+    /// plus one. If indexing crosses a page the value also replaces the high byte of the address it's stored to.
+    StoreHigh(StoreHighOp),
+}
+
+use Access::{Modify, Read, StoreHigh, Write};
+
+impl Access {
+    /// Whether the cycle at a step of access_cycle writes.
+    fn writes_at(&self, step: usize, cmos: bool) -> bool {
+        match self {
+            Read(_) => false,
+            Write(_) | StoreHigh(_) => step == 0,
+            Modify(_) => step == 2 || (step == 1 && !cmos),
+        }
+    }
 }
-impl ADC0x6D {
-    fn new() -> Option<Box<dyn Instruction>> { Some(Box::new(Self { adh: 0, adl: 0 })) }
+
+#[derive(Clone, Copy)]
+enum Index {
+    X,
+    Y,
 }
-impl Instruction for ADC0x6D {
-    fn cycle(&mut self, cycle: usize, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
-        match cycle {
-            0 => {
-                self.adl = reg.data;
-                InstructionState::Continue
+
+impl Index {
+    fn value(&self, reg: &Registers) -> u8 {
+        match self {
+            Index::X => reg.x,
+            Index::Y => reg.y,
+        }
+    }
+}
+
+/// When absolute indexed addressing spends a cycle fixing up the high byte of the address before the access.
+#[derive(Clone, Copy)]
+enum Fixup {
+    WhenCrossing,
+    Always,
+}
+
+impl Fixup {
+    /// Reads only need to fix the address up when the index crosses a page, everything else always does.
+    const fn default_for(access: Access) -> Self {
+        match access {
+            Read(_) => Fixup::WhenCrossing,
+            _ => Fixup::Always,
+        }
+    }
+
+    fn cycles(&self, address: u16, uncarried: u16) -> usize {
+        match self {
+            Fixup::WhenCrossing if address == uncarried => 0,
+            _ => 1,
+        }
+    }
+}
+
+/// What decides whether a relative branch is taken.
+#[derive(Clone, Copy)]
+enum Branch {
+    /// The flag is in the given state
+    Flag(Flags, bool),
+    /// BRA, on the 65C02
+    Always,
+}
+
+impl Branch {
+    fn taken(&self, reg: &Registers) -> bool {
+        match self {
+            Branch::Flag(flag, set) => reg.status.flag(*flag) == *set,
+            Branch::Always => true,
+        }
+    }
+}
+
+/// What an opcode decodes to: the cycles it runs through, and what it does along the way.
+#[derive(Clone, Copy)]
+enum Operation {
+    Implied(ImpliedOp),
+    Accumulator(ModifyOp),
+    Immediate(ReadOp),
+    /// Immediate instructions that OR A with the CPU's magic constant (ANE and LXA)
+    ImmediateMagic(MagicOp),
+    ZeroPage(Access),
+    ZeroPageIndexed(Access, Index),
+    Absolute(Access),
+    AbsoluteIndexed(Access, Index, Fixup),
+    IndexedIndirect(Access),
+    IndirectIndexed(Access),
+    /// (zp), on the 65C02
+    ZeroPageIndirect(Access),
+    Relative(Branch),
+    /// BBR and BBS: branch when a bit of a zero page location is in the given state
+    BranchBit(u8, bool),
+    Push(WriteOp),
+    Pull(ReadOp),
+    Jsr,
+    Rts,
+    Rti,
+    JmpAbsolute,
+    JmpIndirect,
+    JmpIndexedIndirect,
+    Interrupt(Interrupt),
+    Jam,
+    Stop,
+    Wait,
+    /// The 65C02's single byte NOPs, which are done with by the end of the opcode fetch
+    NopSingleCycle,
+    /// The 65C02's eight cycle $5C NOP
+    Nop5C,
+}
+
+/// An instruction in flight: the operation its opcode decoded to, and whatever it has worked out so far.
+#[derive(Clone, Copy)]
+pub struct Instruction {
+    operation: Operation,
+    variant: Variant,
+    magic: u8,
+    /// Set for the 65C02's ADC and SBC, which spend an extra cycle in decimal mode
+    decimal_cycle: bool,
+    adjusting: bool,
+    /// Set when an NMI has taken over a BRK or IRQ sequence
+    hijacked: bool,
+    pointer: u8,
+    adl: u8,
+    adh: u8,
+    address: u16,
+    uncarried: u16,
+    value: u8,
+}
+
+impl Instruction {
+    fn new(operation: Operation, variant: Variant, magic: u8) -> Self {
+        Self {
+            operation,
+            variant,
+            magic,
+            decimal_cycle: false,
+            adjusting: false,
+            hijacked: false,
+            pointer: 0,
+            adl: 0,
+            adh: 0,
+            address: 0,
+            uncarried: 0,
+            value: 0,
+        }
+    }
+
+    /// Whether the instruction was over as soon as its opcode was fetched, as the 65C02's single byte NOPs are.
+    pub fn finished_on_fetch(&self) -> bool {
+        matches!(self.operation, Operation::NopSingleCycle)
+    }
+
+    /// Whether the CPU polls the interrupt lines before the cycle at a step. What it finds before the last cycle
+    /// decides whether an interrupt is taken instead of the next opcode. A taken branch that stays on its page
+    /// doesn't poll before its last cycle, so what it found before the one ahead of it stands, and interrupt
+    /// sequences don't poll at all, so at least one instruction of a handler always runs.
+    pub fn polls_before(&self, step: usize) -> bool {
+        match self.operation {
+            Operation::Interrupt(_) => false,
+            Operation::Relative(_) => step != 1,
+            _ => true,
+        }
+    }
+
+    /// Whether the cycle at a step writes, worked out from the operation and what the instruction has found so far
+    /// rather than by running it, so the bus isn't touched.
+    pub fn writes_at(&self, step: usize) -> bool {
+        if self.adjusting {
+            return false;
+        }
+        let cmos = self.variant.cmos();
+        let access_writes = |access: Access, first: usize| step >= first && access.writes_at(step - first, cmos);
+        match self.operation {
+            Operation::ZeroPage(access) => access_writes(access, 1),
+            Operation::ZeroPageIndexed(access, _) | Operation::Absolute(access) => access_writes(access, 2),
+            Operation::AbsoluteIndexed(access, _, fixup) => {
+                access_writes(access, 2 + fixup.cycles(self.address, self.uncarried))
             },
-            1 => {
-                self.adh = reg.data;
-                InstructionState::Continue
+            Operation::IndexedIndirect(access) | Operation::IndirectIndexed(access) => access_writes(access, 4),
+            Operation::ZeroPageIndirect(access) => access_writes(access, 3),
+            Operation::Push(_) => step == 1,
+            Operation::Jsr => matches!(step, 2 | 3),
+            Operation::Interrupt(Interrupt::Reset) => false,
+            Operation::Interrupt(_) => matches!(step, 1..=3),
+            _ => false,
+        }
+    }
+
+    /// Have an NMI take over a BRK or IRQ sequence, which it can do up until P is pushed. The sequence carries on
+    /// as it was, B flag and all, but PC is loaded from the NMI vector. Returns whether it took over.
+    pub fn hijack(&mut self, step: usize) -> bool {
+        let hijackable = matches!(self.operation, Operation::Interrupt(Interrupt::Brk | Interrupt::Irq));
+        if hijackable && !self.hijacked && step <= 3 {
+            self.hijacked = true;
+            return true;
+        }
+        false
+    }
+
+    /// Run the cycle of the instruction at a step, counted from zero after the opcode fetch.
+    pub fn cycle(&mut self, step: usize, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+        // The 65C02 spends a cycle after decimal ADC and SBC repeating its last read
+        if self.adjusting {
+            repeat_read(reg, address_bus);
+            return InstructionState::Finished;
+        }
+
+        let state = self.operation_cycle(step, reg, address_bus);
+        let decimal = reg.status.decimal_mode();
+        if self.decimal_cycle && decimal && matches!(state, InstructionState::Finished) {
+            self.adjusting = true;
+            return InstructionState::Continue;
+        }
+        state
+    }
+
+    fn operation_cycle(
+        &mut self,
+        step: usize,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
+        match self.operation {
+            Operation::Implied(op) => self.implied(op, reg, address_bus),
+            Operation::Accumulator(op) => self.accumulator(op, reg, address_bus),
+            Operation::Immediate(op) => self.immediate(op, reg, address_bus),
+            Operation::ImmediateMagic(op) => self.immediate_magic(op, reg, address_bus),
+            Operation::ZeroPage(access) => self.zero_page(step, access, reg, address_bus),
+            Operation::ZeroPageIndexed(access, index) => self.zero_page_indexed(step, access, index, reg, address_bus),
+            Operation::Absolute(access) => self.absolute(step, access, reg, address_bus),
+            Operation::AbsoluteIndexed(access, index, fixup) => {
+                self.absolute_indexed(step, access, index, fixup, reg, address_bus)
             },
-            2 => {
-                (reg.adh, reg.adl) = (self.adh, self.adl);
-                reg.data = address_bus.get_hl(reg.adh, reg.adl);
+            Operation::IndexedIndirect(access) => self.indexed_indirect(step, access, reg, address_bus),
+            Operation::IndirectIndexed(access) => self.indirect_indexed(step, access, reg, address_bus),
+            Operation::ZeroPageIndirect(access) => self.zero_page_indirect(step, access, reg, address_bus),
+            Operation::Relative(branch) => self.relative(step, branch, reg, address_bus),
+            Operation::BranchBit(bit, set) => self.branch_bit(step, bit, set, reg, address_bus),
+            Operation::Push(op) => self.push_stack(step, op, reg, address_bus),
+            Operation::Pull(op) => self.pull_stack(step, op, reg, address_bus),
+            Operation::Jsr => self.jsr(step, reg, address_bus),
+            Operation::Rts => self.rts(step, reg, address_bus),
+            Operation::Rti => self.rti(step, reg, address_bus),
+            Operation::JmpAbsolute => self.jmp_absolute(step, reg, address_bus),
+            Operation::JmpIndirect => self.jmp_indirect(step, reg, address_bus),
+            Operation::JmpIndexedIndirect => self.jmp_indexed_indirect(step, reg, address_bus),
+            Operation::Interrupt(interrupt) => self.interrupt_sequence(step, interrupt, reg, address_bus),
+            Operation::Jam => self.jam(reg, address_bus),
+            Operation::Stop => self.stop(step, reg, address_bus),
+            Operation::Wait => self.wait(step, reg, address_bus),
+            Operation::NopSingleCycle => InstructionState::Finished,
+            Operation::Nop5C => self.nop_5c(step, reg, address_bus),
+        }
+    }
+}
 
-                let total: u16 = reg.a as u16 + reg.data as u16 + if reg.status.flag(StatusRegister::CARRY) { 1 } else { 0 };
-                reg.a = total as u8;
+/// Put an address on the bus and read from it.
+fn read(address: u16, reg: &mut Registers, address_bus: &mut dyn IODevice) -> u8 {
+    [reg.adl, reg.adh] = address.to_le_bytes();
+    reg.data = address_bus.get_hl(reg.adh, reg.adl);
+    reg.data
+}
 
-                if total > 255 {
-                    reg.status.set_flag(StatusRegister::CARRY);
-                } else {
-                    reg.status.clear_flag(StatusRegister::CARRY);
-                }
+/// Put an address and a value on the bus and write it.
+fn write(address: u16, value: u8, reg: &mut Registers, address_bus: &mut dyn IODevice) {
+    [reg.adl, reg.adh] = address.to_le_bytes();
+    reg.data = value;
+    address_bus.put_hl(reg.adh, reg.adl, reg.data);
+}
 
-                if reg.a == 0 {
-                    reg.status.set_flag(StatusRegister::ZERO);
-                } else {
-                    reg.status.clear_flag(StatusRegister::ZERO);
-                }
+/// Read from the address that's already on the bus again. Where the NMOS part makes a dummy read from a half
+/// worked out address, the 65C02 does this instead.
+fn repeat_read(reg: &mut Registers, address_bus: &mut dyn IODevice) {
+    read(u16::from_le_bytes([reg.adl, reg.adh]), reg, address_bus);
+}
 
-                if reg.a & 0b10000000 == 0b10000000 {
-                    reg.status.set_flag(StatusRegister::NEGATIVE);
-                } else {
-                    reg.status.clear_flag(StatusRegister::NEGATIVE);
-                }
+/// Read the next byte of the instruction stream.
+fn fetch(reg: &mut Registers, address_bus: &mut dyn IODevice) -> u8 {
+    let value = read(reg.pc, reg, address_bus);
+    reg.pc = reg.pc.wrapping_add(1);
+    value
+}
+
+fn stack_address(reg: &Registers) -> u16 {
+    u16::from_le_bytes([reg.stack, 0x01])
+}
+
+/// Write a byte to the stack page and move the stack pointer down, wrapping within page one.
+fn push(value: u8, reg: &mut Registers, address_bus: &mut dyn IODevice) {
+    write(stack_address(reg), value, reg, address_bus);
+    reg.stack = reg.stack.wrapping_sub(1);
+}
+
+/// Move the stack pointer up, wrapping within page one, and read the byte it points to.
+fn pull(reg: &mut Registers, address_bus: &mut dyn IODevice) -> u8 {
+    reg.stack = reg.stack.wrapping_add(1);
+    read(stack_address(reg), reg, address_bus)
+}
 
-                if (reg.a & 0b10000000) != ((total as u8) & 0b10000000) {
-                    reg.status.set_flag(StatusRegister::OVERFLOW);
+impl Instruction {
+    /// The cycles shared by every memory addressing mode once the effective address is known. Reads take
+    /// one cycle, writes take one cycle, and read-modify-write instructions read, write the unmodified value
+    /// back (or on the 65C02, read it again), then write the result.
+    fn access_cycle(
+        &mut self,
+        step: usize,
+        access: Access,
+        address: u16,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
+        match (access, step) {
+            (Read(op), _) => {
+                let value = read(address, reg, address_bus);
+                op(reg, value);
+                InstructionState::Finished
+            },
+            (Write(op), _) => {
+                let value = op(reg);
+                write(address, value, reg, address_bus);
+                InstructionState::Finished
+            },
+            (Modify(_), 0) => {
+                self.value = read(address, reg, address_bus);
+                InstructionState::Continue
+            },
+            (Modify(_), 1) => {
+                if self.variant.cmos() {
+                    read(address, reg, address_bus);
                 } else {
-                    reg.status.clear_flag(StatusRegister::OVERFLOW);
+                    write(address, self.value, reg, address_bus);
                 }
-
-                reg.pc -= 1;
                 InstructionState::Continue
             },
-            3 => InstructionState::Finished,
-            _ => panic!("unexpected cycle for ADC0x6D: {cycle}")
+            (Modify(op), _) => {
+                let result = op(reg, self.value);
+                write(address, result, reg, address_bus);
+                InstructionState::Finished
+            },
+            (StoreHigh(op), _) => {
+                store_high(op, address, reg, address_bus);
+                InstructionState::Finished
+            },
         }
     }
+
+    /// The dummy read an indexed mode makes while it works out the address. The NMOS part reads from the address
+    /// it has so far, the 65C02 repeats its last read.
+    fn dummy_read(&self, address: u16, reg: &mut Registers, address_bus: &mut dyn IODevice) {
+        if self.variant.cmos() {
+            repeat_read(reg, address_bus);
+        } else {
+            read(address, reg, address_bus);
+        }
+    }
+}
+
+/// An unstable store that didn't cross a page, so the high byte of the base address is that of the address.
+fn store_high(op: StoreHighOp, address: u16, reg: &mut Registers, address_bus: &mut dyn IODevice) {
+    let value = op(reg, ((address >> 8) as u8).wrapping_add(1));
+    write(address, value, reg, address_bus);
 }
 
+/// An unstable store whose index crossed a page. The high byte of the base address plus one is the high byte of
+/// the address, and the value replaces it.
+fn store_high_crossed(op: StoreHighOp, address: u16, reg: &mut Registers, address_bus: &mut dyn IODevice) {
+    let [adl, adh] = address.to_le_bytes();
+    let value = op(reg, adh);
+    write(u16::from_le_bytes([adl, value]), value, reg, address_bus);
+}
 
-// CLC
-// implied
-struct CLC0x18;
-impl CLC0x18 {
-    fn new() -> Option<Box<dyn Instruction>> { Some(Box::new(Self {})) }
+/// Add an index to a base address, returning the effective address along with the address the CPU puts on
+/// the bus before it has carried into the high byte.
+fn index_address(base: u16, index: u8) -> (u16, u16) {
+    let [adl, adh] = base.to_le_bytes();
+    (base.wrapping_add(index as u16), u16::from_le_bytes([adl.wrapping_add(index), adh]))
 }
-impl Instruction for CLC0x18 {
-    fn cycle(&mut self, cycle: usize, reg: &mut Registers, _address_bus: &mut dyn IODevice) -> InstructionState {
+
+impl Instruction {
+    // Implied
+    // Single byte instructions that only touch registers. The byte after the opcode is read and ignored.
+    fn implied(&mut self, op: ImpliedOp, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+        read(reg.pc, reg, address_bus);
+        op(reg);
+        InstructionState::Finished
+    }
+
+    // Accumulator
+    // Shift and rotate instructions operating on A instead of memory
+    fn accumulator(&mut self, op: ModifyOp, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+        read(reg.pc, reg, address_bus);
+        reg.a = op(reg, reg.a);
+        InstructionState::Finished
+    }
+
+    // Immediate
+    // The operand is the byte following the opcode
+    fn immediate(&mut self, op: ReadOp, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+        let value = fetch(reg, address_bus);
+        op(reg, value);
+        InstructionState::Finished
+    }
+
+    // ANE, LXA
+    // immediate
+    fn immediate_magic(
+        &mut self,
+        op: MagicOp,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
+        let value = fetch(reg, address_bus);
+        op(reg, value, self.magic);
+        InstructionState::Finished
+    }
+
+    // PHA, PHP
+    // The byte after the opcode is read and ignored before the push
+    fn push_stack(
+        &mut self,
+        cycle: usize,
+        op: WriteOp,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
         match cycle {
             0 => {
-                reg.pc -= 1;
+                read(reg.pc, reg, address_bus);
                 InstructionState::Continue
             },
-            1 => {
-                reg.status.clear_flag(StatusRegister::CARRY);
+            _ => {
+                let value = op(reg);
+                push(value, reg, address_bus);
                 InstructionState::Finished
             },
-            _ => panic!("unexpected cycle for CLC0x18: {cycle}")
         }
     }
-}
 
-// CLD
-// implied
-struct CLD0xD8;
-impl CLD0xD8 {
-    fn new() -> Option<Box<dyn Instruction>> { Some(Box::new(Self {})) }
-}
-impl Instruction for CLD0xD8 {
-    fn cycle(&mut self, cycle: usize, reg: &mut Registers, _address_bus: &mut dyn IODevice) -> InstructionState {
+    // PLA, PLP
+    // The stack is read once before the stack pointer is incremented and the value is pulled
+    fn pull_stack(
+        &mut self,
+        cycle: usize,
+        op: ReadOp,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
         match cycle {
             0 => {
-                reg.pc -= 1;
+                read(reg.pc, reg, address_bus);
                 InstructionState::Continue
             },
             1 => {
-                reg.status.clear_flag(StatusRegister::DECIMAL_MODE);
+                read(stack_address(reg), reg, address_bus);
+                InstructionState::Continue
+            },
+            _ => {
+                let value = pull(reg, address_bus);
+                op(reg, value);
                 InstructionState::Finished
             },
-            _ => panic!("unexpected cycle for CLD0xD8: {cycle}")
         }
     }
-}
 
-// LDA
-// immediate
-struct LDA0xA9 {
-    data: u8,
-}
-impl LDA0xA9 {
-    fn new() -> Option<Box<dyn Instruction>> { Some(Box::new(Self { data: 0 })) }
-}
-impl Instruction for LDA0xA9 {
-    fn cycle(&mut self, cycle: usize, reg: &mut Registers, _address_bus: &mut dyn IODevice) -> InstructionState {
+    // JAM
+    // implied
+    // Locks the NMOS part up until it's reset
+    fn jam(&mut self, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+        read(reg.pc, reg, address_bus);
+        InstructionState::Jammed
+    }
+
+    // Zero page
+    fn zero_page(
+        &mut self,
+        cycle: usize,
+        access: Access,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
+        match cycle {
+            0 => {
+                self.adl = fetch(reg, address_bus);
+                InstructionState::Continue
+            },
+            _ => self.access_cycle(cycle - 1, access, self.adl as u16, reg, address_bus),
+        }
+    }
+
+    // Zero page,X and zero page,Y
+    // The unindexed address is read while the index is added. The add doesn't carry, so the effective address
+    // never leaves page zero.
+    fn zero_page_indexed(
+        &mut self,
+        cycle: usize,
+        access: Access,
+        index: Index,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
         match cycle {
             0 => {
-                self.data = reg.data;
+                self.adl = fetch(reg, address_bus);
                 InstructionState::Continue
             },
             1 => {
-                reg.a = self.data;
-                if reg.a == 0 {
-                    reg.status.set_flag(StatusRegister::ZERO);
-                } else {
-                    reg.status.clear_flag(StatusRegister::ZERO);
-                }
+                self.dummy_read(self.adl as u16, reg, address_bus);
+                self.adl = self.adl.wrapping_add(index.value(reg));
+                InstructionState::Continue
+            },
+            _ => self.access_cycle(cycle - 2, access, self.adl as u16, reg, address_bus),
+        }
+    }
 
-                if reg.a & 0b10000000 == 0b10000000 {
-                    reg.status.set_flag(StatusRegister::NEGATIVE);
-                } else {
-                    reg.status.clear_flag(StatusRegister::NEGATIVE);
-                }
+    // Absolute
+    fn absolute(
+        &mut self,
+        cycle: usize,
+        access: Access,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
+        match cycle {
+            0 => {
+                self.adl = fetch(reg, address_bus);
+                InstructionState::Continue
+            },
+            1 => {
+                self.adh = fetch(reg, address_bus);
+                InstructionState::Continue
+            },
+            _ => {
+                let address = u16::from_le_bytes([self.adl, self.adh]);
+                self.access_cycle(cycle - 2, access, address, reg, address_bus)
+            },
+        }
+    }
 
+    // Absolute,X and absolute,Y
+    // The CPU first reads from the address before the carry into the high byte is applied. Reads that don't cross
+    // a page are already at the right address and finish there; otherwise it's a dummy read and the access happens
+    // on the next cycle, which costs page crossing reads one extra cycle and stores and read-modify-write
+    // instructions one every time. The 65C02's shifts and rotates only pay it when they cross a page.
+    fn absolute_indexed(
+        &mut self,
+        cycle: usize,
+        access: Access,
+        index: Index,
+        fixup: Fixup,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
+        match (cycle, access) {
+            (0, _) => {
+                self.adl = fetch(reg, address_bus);
+                InstructionState::Continue
+            },
+            (1, _) => {
+                let adh = fetch(reg, address_bus);
+                (self.address, self.uncarried) = index_address(u16::from_le_bytes([self.adl, adh]), index.value(reg));
+                InstructionState::Continue
+            },
+            (2, _) if fixup.cycles(self.address, self.uncarried) == 1 => {
+                self.dummy_read(self.uncarried, reg, address_bus);
+                InstructionState::Continue
+            },
+            (3, StoreHigh(op)) if self.uncarried != self.address => {
+                store_high_crossed(op, self.address, reg, address_bus);
                 InstructionState::Finished
             },
-            _ => panic!("unexpected cycle for LDA0xA9: {cycle}")
+            _ => {
+                let step = cycle - 2 - fixup.cycles(self.address, self.uncarried);
+                self.access_cycle(step, access, self.address, reg, address_bus)
+            },
         }
     }
-}
 
-// absolute
-struct LDA0xAD {
-    adh: u8,
-    adl: u8,
-}
-impl LDA0xAD {
-    fn new() -> Option<Box<dyn Instruction>> { Some(Box::new(Self { adh: 0, adl: 0 })) }
-}
-impl Instruction for LDA0xAD {
-    fn cycle(&mut self, cycle: usize, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+    // (Indirect,X)
+    // The pointer is read while X is added to it, and the addition wraps within page zero, as does reading the
+    // address it points to
+    fn indexed_indirect(
+        &mut self,
+        cycle: usize,
+        access: Access,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
         match cycle {
             0 => {
-                self.adl = reg.data;
+                self.pointer = fetch(reg, address_bus);
                 InstructionState::Continue
             },
             1 => {
-                self.adh = reg.data;
+                self.dummy_read(self.pointer as u16, reg, address_bus);
+                self.pointer = self.pointer.wrapping_add(reg.x);
                 InstructionState::Continue
             },
             2 => {
-                (reg.adh, reg.adl) = (self.adh, self.adl);
-                reg.data = address_bus.get_hl(reg.adh, reg.adl);
-                reg.a = reg.data;
+                self.adl = read(self.pointer as u16, reg, address_bus);
+                InstructionState::Continue
+            },
+            3 => {
+                let adh = read(self.pointer.wrapping_add(1) as u16, reg, address_bus);
+                self.address = u16::from_le_bytes([self.adl, adh]);
+                InstructionState::Continue
+            },
+            _ => self.access_cycle(cycle - 4, access, self.address, reg, address_bus),
+        }
+    }
 
-                if reg.a == 0 {
-                    reg.status.set_flag(StatusRegister::ZERO);
-                } else {
-                    reg.status.clear_flag(StatusRegister::ZERO);
-                }
+    // (Indirect),Y
+    // The address is read from page zero (wrapping within it) and Y is added to it, with the same extra cycle for
+    // page crossing reads and for every store as absolute indexed addressing.
+    fn indirect_indexed(
+        &mut self,
+        cycle: usize,
+        access: Access,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
+        match (cycle, access) {
+            (0, _) => {
+                self.pointer = fetch(reg, address_bus);
+                InstructionState::Continue
+            },
+            (1, _) => {
+                self.adl = read(self.pointer as u16, reg, address_bus);
+                InstructionState::Continue
+            },
+            (2, _) => {
+                let adh = read(self.pointer.wrapping_add(1) as u16, reg, address_bus);
+                (self.address, self.uncarried) = index_address(u16::from_le_bytes([self.adl, adh]), reg.y);
+                InstructionState::Continue
+            },
+            (3, Read(_)) if self.uncarried == self.address => {
+                self.access_cycle(0, access, self.address, reg, address_bus)
+            },
+            (3, _) => {
+                self.dummy_read(self.uncarried, reg, address_bus);
+                InstructionState::Continue
+            },
+            (4, StoreHigh(op)) if self.uncarried != self.address => {
+                store_high_crossed(op, self.address, reg, address_bus);
+                InstructionState::Finished
+            },
+            _ => self.access_cycle(cycle - 4, access, self.address, reg, address_bus),
+        }
+    }
+
+    // Relative
+    // Branches add a signed offset to the address of the following instruction when their condition holds. A
+    // taken branch spends a cycle reading the next opcode while the offset is added to PCL, and if that crosses a
+    // page, another reading from the target before PCH is fixed up.
+    fn relative(
+        &mut self,
+        cycle: usize,
+        branch: Branch,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
+        match cycle {
+            0 => {
+                let offset = fetch(reg, address_bus);
+                self.branch(offset, branch.taken(reg), reg)
+            },
+            _ => self.branch_cycle(cycle - 1, reg, address_bus),
+        }
+    }
+
+    /// Work out where a branch goes once its offset has been fetched, finishing if it isn't taken.
+    fn branch(&mut self, offset: u8, taken: bool, reg: &mut Registers) -> InstructionState {
+        if !taken {
+            return InstructionState::Finished;
+        }
+        self.address = reg.pc.wrapping_add(offset as i8 as u16);
+        InstructionState::Continue
+    }
 
-                if reg.a & 0b10000000 == 0b10000000 {
-                    reg.status.set_flag(StatusRegister::NEGATIVE);
+    /// The cycles a taken branch spends moving PC to the target.
+    fn branch_cycle(&mut self, step: usize, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+        match step {
+            0 => {
+                read(reg.pc, reg, address_bus);
+                let [target_low, target_high] = self.address.to_le_bytes();
+                let [_, pch] = reg.pc.to_le_bytes();
+                reg.pc = u16::from_le_bytes([target_low, pch]);
+                if target_high == pch {
+                    InstructionState::Finished
                 } else {
-                    reg.status.clear_flag(StatusRegister::NEGATIVE);
+                    InstructionState::Continue
                 }
+            },
+            _ => {
+                read(reg.pc, reg, address_bus);
+                reg.pc = self.address;
+                InstructionState::Finished
+            },
+        }
+    }
 
-                reg.pc -= 1;
+    // BBR, BBS
+    // zero page, relative
+    // The zero page location is read, then read again while the bit is tested, before the offset is fetched and
+    // the branch goes the same way as any other
+    fn branch_bit(
+        &mut self,
+        cycle: usize,
+        bit: u8,
+        set: bool,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
+        match cycle {
+            0 => {
+                self.adl = fetch(reg, address_bus);
                 InstructionState::Continue
             },
-            3 => InstructionState::Finished,
-            _ => panic!("unexpected cycle for LDA0xAD: {cycle}")
+            1 => {
+                self.value = read(self.adl as u16, reg, address_bus);
+                InstructionState::Continue
+            },
+            2 => {
+                repeat_read(reg, address_bus);
+                InstructionState::Continue
+            },
+            3 => {
+                let offset = fetch(reg, address_bus);
+                self.branch(offset, (self.value & (1 << bit) != 0) == set, reg)
+            },
+            _ => self.branch_cycle(cycle - 4, reg, address_bus),
         }
     }
-}
 
-// STA
-// absolute
-struct STA0x8D {
-    adh: u8,
-    adl: u8,
-}
-impl STA0x8D {
-    fn new() -> Option<Box<dyn Instruction>> { Some(Box::new(Self { adh: 0, adl: 0 })) }
-}
-impl Instruction for STA0x8D {
-    fn cycle(&mut self, cycle: usize, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+    // (Zero page)
+    // The 65C02's indirect addressing without an index
+    fn zero_page_indirect(
+        &mut self,
+        cycle: usize,
+        access: Access,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
         match cycle {
             0 => {
-                self.adl = reg.data;
+                self.pointer = fetch(reg, address_bus);
                 InstructionState::Continue
             },
             1 => {
-                self.adh = reg.data;
+                self.adl = read(self.pointer as u16, reg, address_bus);
                 InstructionState::Continue
             },
             2 => {
-                (reg.adh, reg.adl) = (self.adh, self.adl);
-                reg.data = reg.a;
-                address_bus.put_hl(reg.adh, reg.adl, reg.data);
-                reg.pc -= 1;
+                let adh = read(self.pointer.wrapping_add(1) as u16, reg, address_bus);
+                self.address = u16::from_le_bytes([self.adl, adh]);
                 InstructionState::Continue
             },
-            3 => InstructionState::Finished,
-            _ => panic!("unexpected cycle for STA0x8D: {cycle}")
+            _ => self.access_cycle(cycle - 3, access, self.address, reg, address_bus),
+        }
+    }
+
+    // JSR
+    // absolute
+    // The return address pushed is that of the last byte of the JSR, so PC is pushed before the high byte of the
+    // target is read
+    fn jsr(&mut self, cycle: usize, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+        match cycle {
+            0 => {
+                self.adl = fetch(reg, address_bus);
+                InstructionState::Continue
+            },
+            1 => {
+                read(stack_address(reg), reg, address_bus);
+                InstructionState::Continue
+            },
+            2 => {
+                push((reg.pc >> 8) as u8, reg, address_bus);
+                InstructionState::Continue
+            },
+            3 => {
+                push(reg.pc as u8, reg, address_bus);
+                InstructionState::Continue
+            },
+            _ => {
+                let adh = read(reg.pc, reg, address_bus);
+                reg.pc = u16::from_le_bytes([self.adl, adh]);
+                InstructionState::Finished
+            },
+        }
+    }
+
+    // RTS
+    // implied
+    // Pulls the address of the last byte of the JSR, and spends a cycle stepping past it
+    fn rts(&mut self, cycle: usize, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+        match cycle {
+            0 => {
+                read(reg.pc, reg, address_bus);
+                InstructionState::Continue
+            },
+            1 => {
+                read(stack_address(reg), reg, address_bus);
+                InstructionState::Continue
+            },
+            2 => {
+                self.adl = pull(reg, address_bus);
+                InstructionState::Continue
+            },
+            3 => {
+                let pch = pull(reg, address_bus);
+                reg.pc = u16::from_le_bytes([self.adl, pch]);
+                InstructionState::Continue
+            },
+            _ => {
+                fetch(reg, address_bus);
+                InstructionState::Finished
+            },
         }
     }
-}
 
-pub fn find_instruction(opcode: u8) -> Option<Box<dyn Instruction>> {
-    match opcode {
-        0x18 => CLC0x18::new(),
-        0x60 => None, // RTS, not sure how to implment this yet, so just exit when we hit it
-        0x6d => ADC0x6D::new(),
-        0x8d => STA0x8D::new(),
-        0xa9 => LDA0xA9::new(),
-        0xad => LDA0xAD::new(),
-        0xd8 => CLD0xD8::new(),
-        _ => None
+    // RTI
+    // implied
+    // Unlike RTS, the pulled address is the next instruction to execute
+    fn rti(&mut self, cycle: usize, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+        match cycle {
+            0 => {
+                read(reg.pc, reg, address_bus);
+                InstructionState::Continue
+            },
+            1 => {
+                read(stack_address(reg), reg, address_bus);
+                InstructionState::Continue
+            },
+            2 => {
+                let value = pull(reg, address_bus);
+                reg.status = StatusRegister::from_pull(value);
+                InstructionState::Continue
+            },
+            3 => {
+                self.adl = pull(reg, address_bus);
+                InstructionState::Continue
+            },
+            _ => {
+                let pch = pull(reg, address_bus);
+                reg.pc = u16::from_le_bytes([self.adl, pch]);
+                InstructionState::Finished
+            },
+        }
+    }
+
+    // BRK, IRQ, NMI and RESET
+    // All four share the same seven cycle sequence: PC and P are pushed, interrupts are disabled, and PC is loaded
+    // from the vector. BRK skips the byte following its opcode and pushes P with the B flag set. Hardware
+    // interrupts replace an opcode fetch without incrementing PC, so they push the address of the instruction they
+    // interrupted. Reset goes through the motions of the pushes with reads instead of writes, and as nothing was
+    // fetched before it, it spends an extra cycle reading from PC first.
+    fn interrupt_sequence(
+        &mut self,
+        cycle: usize,
+        interrupt: Interrupt,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
+        let cycle = match (interrupt, cycle) {
+            (Interrupt::Reset, 0) => {
+                read(reg.pc, reg, address_bus);
+                return InstructionState::Continue;
+            },
+            (Interrupt::Reset, _) => cycle - 1,
+            _ => cycle,
+        };
+
+        let push = |value: u8, reg: &mut Registers, address_bus: &mut dyn IODevice| {
+            if interrupt == Interrupt::Reset {
+                read(stack_address(reg), reg, address_bus);
+                reg.stack = reg.stack.wrapping_sub(1);
+            } else {
+                push(value, reg, address_bus);
+            }
+        };
+
+        match cycle {
+            0 => {
+                read(reg.pc, reg, address_bus);
+                if interrupt == Interrupt::Brk {
+                    reg.pc = reg.pc.wrapping_add(1);
+                }
+                InstructionState::Continue
+            },
+            1 => {
+                push((reg.pc >> 8) as u8, reg, address_bus);
+                InstructionState::Continue
+            },
+            2 => {
+                push(reg.pc as u8, reg, address_bus);
+                InstructionState::Continue
+            },
+            3 => {
+                let status = match interrupt {
+                    Interrupt::Brk => reg.status.for_push(true),
+                    _ => reg.status.for_push(false),
+                };
+                push(status, reg, address_bus);
+                reg.status.set_irq_disable(true);
+                if self.variant.cmos() {
+                    reg.status.set_decimal_mode(false);
+                }
+                InstructionState::Continue
+            },
+            4 => {
+                self.adl = read(self.vector(interrupt), reg, address_bus);
+                InstructionState::Continue
+            },
+            _ => {
+                let pch = read(self.vector(interrupt).wrapping_add(1), reg, address_bus);
+                reg.pc = u16::from_le_bytes([self.adl, pch]);
+                InstructionState::Finished
+            },
+        }
+    }
+
+    fn vector(&self, interrupt: Interrupt) -> u16 {
+        match self.hijacked {
+            true => Interrupt::NMI_VECTOR,
+            false => interrupt.vector(),
+        }
+    }
+
+    // JMP
+    // absolute
+    fn jmp_absolute(&mut self, cycle: usize, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+        match cycle {
+            0 => {
+                self.adl = fetch(reg, address_bus);
+                InstructionState::Continue
+            },
+            _ => {
+                let adh = fetch(reg, address_bus);
+                reg.pc = u16::from_le_bytes([self.adl, adh]);
+                InstructionState::Finished
+            },
+        }
+    }
+
+    // indirect
+    // The NMOS part doesn't carry into the high byte when fetching the target, so JMP ($xxFF) reads its high
+    // byte from $xx00. The 65C02 spends an extra cycle getting it right.
+    fn jmp_indirect(&mut self, cycle: usize, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+        let cycle = match (self.variant.cmos(), cycle) {
+            (true, 2) => {
+                repeat_read(reg, address_bus);
+                return InstructionState::Continue;
+            },
+            (true, 3..) => cycle - 1,
+            _ => cycle,
+        };
+
+        match cycle {
+            0 => {
+                self.adl = fetch(reg, address_bus);
+                InstructionState::Continue
+            },
+            1 => {
+                self.adh = fetch(reg, address_bus);
+                InstructionState::Continue
+            },
+            2 => {
+                self.value = read(u16::from_le_bytes([self.adl, self.adh]), reg, address_bus);
+                InstructionState::Continue
+            },
+            _ => {
+                let pointer = jmp_indirect_high(u16::from_le_bytes([self.adl, self.adh]), self.variant);
+                let pch = read(pointer, reg, address_bus);
+                reg.pc = u16::from_le_bytes([self.value, pch]);
+                InstructionState::Finished
+            },
+        }
+    }
+
+    // absolute indexed indirect
+    // The 65C02's JMP ($xxxx,X), which adds X while it repeats the read of the high byte
+    fn jmp_indexed_indirect(
+        &mut self,
+        cycle: usize,
+        reg: &mut Registers,
+        address_bus: &mut dyn IODevice,
+    ) -> InstructionState {
+        match cycle {
+            0 => {
+                self.adl = fetch(reg, address_bus);
+                InstructionState::Continue
+            },
+            1 => {
+                let adh = fetch(reg, address_bus);
+                self.address = u16::from_le_bytes([self.adl, adh]).wrapping_add(reg.x as u16);
+                InstructionState::Continue
+            },
+            2 => {
+                repeat_read(reg, address_bus);
+                InstructionState::Continue
+            },
+            3 => {
+                self.value = read(self.address, reg, address_bus);
+                InstructionState::Continue
+            },
+            _ => {
+                let pch = read(self.address.wrapping_add(1), reg, address_bus);
+                reg.pc = u16::from_le_bytes([self.value, pch]);
+                InstructionState::Finished
+            },
+        }
+    }
+
+    // STP
+    // implied
+    // Stops the 65C02 until it's reset
+    fn stop(&mut self, cycle: usize, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+        read(reg.pc, reg, address_bus);
+        match cycle {
+            0 => InstructionState::Continue,
+            _ => InstructionState::Jammed,
+        }
+    }
+
+    // WAI
+    // implied
+    // Stops the 65C02 until an interrupt line is asserted. If interrupts are disabled when it is, the CPU just
+    // carries on from the next instruction.
+    fn wait(&mut self, cycle: usize, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+        read(reg.pc, reg, address_bus);
+        match cycle {
+            0 => InstructionState::Continue,
+            _ => InstructionState::Waiting,
+        }
+    }
+
+    // NOP
+    // The 65C02's $5C fetches a two byte operand, then spends five cycles reading from $FF and its low byte
+    fn nop_5c(&mut self, cycle: usize, reg: &mut Registers, address_bus: &mut dyn IODevice) -> InstructionState {
+        match cycle {
+            0 => {
+                self.adl = fetch(reg, address_bus);
+                InstructionState::Continue
+            },
+            1 => {
+                fetch(reg, address_bus);
+                InstructionState::Continue
+            },
+            2..=5 => {
+                read(u16::from_le_bytes([self.adl, 0xFF]), reg, address_bus);
+                InstructionState::Continue
+            },
+            _ => {
+                read(u16::from_le_bytes([self.adl, 0xFF]), reg, address_bus);
+                InstructionState::Finished
+            },
+        }
+    }
+}
+
+/// The accesses a memory addressing mode makes once the effective address is known, for the functional core.
+/// Read-modify-write instructions skip writing back the unmodified value. Returns the cycles they would take.
+fn execute_access(access: Access, address: u16, reg: &mut Registers, address_bus: &mut dyn IODevice) -> usize {
+    match access {
+        Read(op) => {
+            let value = read(address, reg, address_bus);
+            op(reg, value);
+            1
+        },
+        Write(op) => {
+            let value = op(reg);
+            write(address, value, reg, address_bus);
+            1
+        },
+        Modify(op) => {
+            let value = read(address, reg, address_bus);
+            let result = op(reg, value);
+            write(address, result, reg, address_bus);
+            3
+        },
+        StoreHigh(op) => {
+            store_high(op, address, reg, address_bus);
+            1
+        },
+    }
+}
+
+/// execute_access for the indexed modes, which need to know if the index crossed a page.
+fn execute_indexed_access(
+    access: Access,
+    address: u16,
+    uncarried: u16,
+    reg: &mut Registers,
+    address_bus: &mut dyn IODevice,
+) -> usize {
+    match access {
+        StoreHigh(op) if address != uncarried => {
+            store_high_crossed(op, address, reg, address_bus);
+            1
+        },
+        _ => execute_access(access, address, reg, address_bus),
+    }
+}
+
+/// Read the two byte operand of an absolute instruction.
+fn fetch_address(reg: &mut Registers, address_bus: &mut dyn IODevice) -> u16 {
+    let adl = fetch(reg, address_bus);
+    let adh = fetch(reg, address_bus);
+    u16::from_le_bytes([adl, adh])
+}
+
+/// Move PC to where a branch goes if it's taken, returning the cycles that takes: one, plus one more if the target
+/// is on another page.
+fn execute_branch(offset: u8, taken: bool, reg: &mut Registers) -> usize {
+    if !taken {
+        return 0;
+    }
+    let target = reg.pc.wrapping_add(offset as i8 as u16);
+    let crossed = target >> 8 != reg.pc >> 8;
+    reg.pc = target;
+    if crossed { 2 } else { 1 }
+}
+
+/// Where JMP ($xxxx) reads the high byte of its target from: the byte after the low one, or on variants with the
+/// bug, the byte after it without carrying into the high byte of the pointer.
+fn jmp_indirect_high(pointer: u16, variant: Variant) -> u16 {
+    let [low, high] = pointer.to_le_bytes();
+    match variant.jmp_indirect_wraps() {
+        true => u16::from_le_bytes([low.wrapping_add(1), high]),
+        false => pointer.wrapping_add(1),
+    }
+}
+
+/// Read an address from page zero, wrapping within it.
+fn read_pointer(pointer: u8, reg: &mut Registers, address_bus: &mut dyn IODevice) -> u16 {
+    let adl = read(pointer as u16, reg, address_bus);
+    let adh = read(pointer.wrapping_add(1) as u16, reg, address_bus);
+    u16::from_le_bytes([adl, adh])
+}
+
+impl Instruction {
+    /// Run the whole instruction in one go, for the functional core. It leaves the registers and memory exactly as
+    /// running it a cycle at a time would, but only makes the accesses that matter to the result, so none of the
+    /// dummy reads and writes. Returns the state it finished in along with the number of cycles it would have
+    /// taken after the opcode fetch.
+    pub fn execute(&mut self, reg: &mut Registers, address_bus: &mut dyn IODevice) -> (InstructionState, usize) {
+        let cycles = match self.operation {
+            Operation::Implied(op) => {
+                op(reg);
+                1
+            },
+            Operation::Accumulator(op) => {
+                reg.a = op(reg, reg.a);
+                1
+            },
+            Operation::Immediate(op) => {
+                let value = fetch(reg, address_bus);
+                op(reg, value);
+                1
+            },
+            Operation::ImmediateMagic(op) => {
+                let value = fetch(reg, address_bus);
+                op(reg, value, self.magic);
+                1
+            },
+            Operation::ZeroPage(access) => {
+                let address = fetch(reg, address_bus) as u16;
+                1 + execute_access(access, address, reg, address_bus)
+            },
+            Operation::ZeroPageIndexed(access, index) => {
+                let address = fetch(reg, address_bus).wrapping_add(index.value(reg)) as u16;
+                2 + execute_access(access, address, reg, address_bus)
+            },
+            Operation::Absolute(access) => {
+                let address = fetch_address(reg, address_bus);
+                2 + execute_access(access, address, reg, address_bus)
+            },
+            Operation::AbsoluteIndexed(access, index, fixup) => {
+                let (address, uncarried) = index_address(fetch_address(reg, address_bus), index.value(reg));
+                2 + fixup.cycles(address, uncarried)
+                    + execute_indexed_access(access, address, uncarried, reg, address_bus)
+            },
+            Operation::IndexedIndirect(access) => {
+                let pointer = fetch(reg, address_bus).wrapping_add(reg.x);
+                let address = read_pointer(pointer, reg, address_bus);
+                4 + execute_access(access, address, reg, address_bus)
+            },
+            Operation::IndirectIndexed(access) => {
+                let pointer = fetch(reg, address_bus);
+                let (address, uncarried) = index_address(read_pointer(pointer, reg, address_bus), reg.y);
+                3 + Fixup::default_for(access).cycles(address, uncarried)
+                    + execute_indexed_access(access, address, uncarried, reg, address_bus)
+            },
+            Operation::ZeroPageIndirect(access) => {
+                let pointer = fetch(reg, address_bus);
+                let address = read_pointer(pointer, reg, address_bus);
+                3 + execute_access(access, address, reg, address_bus)
+            },
+            Operation::Relative(branch) => {
+                let offset = fetch(reg, address_bus);
+                1 + execute_branch(offset, branch.taken(reg), reg)
+            },
+            Operation::BranchBit(bit, set) => {
+                let value = read(fetch(reg, address_bus) as u16, reg, address_bus);
+                let offset = fetch(reg, address_bus);
+                4 + execute_branch(offset, (value & (1 << bit) != 0) == set, reg)
+            },
+            Operation::Push(op) => {
+                let value = op(reg);
+                push(value, reg, address_bus);
+                2
+            },
+            Operation::Pull(op) => {
+                let value = pull(reg, address_bus);
+                op(reg, value);
+                3
+            },
+            Operation::Jsr => {
+                let adl = fetch(reg, address_bus);
+                push((reg.pc >> 8) as u8, reg, address_bus);
+                push(reg.pc as u8, reg, address_bus);
+                let adh = read(reg.pc, reg, address_bus);
+                reg.pc = u16::from_le_bytes([adl, adh]);
+                5
+            },
+            Operation::Rts => {
+                let pcl = pull(reg, address_bus);
+                let pch = pull(reg, address_bus);
+                reg.pc = u16::from_le_bytes([pcl, pch]).wrapping_add(1);
+                5
+            },
+            Operation::Rti => {
+                let value = pull(reg, address_bus);
+                reg.status = StatusRegister::from_pull(value);
+                let pcl = pull(reg, address_bus);
+                let pch = pull(reg, address_bus);
+                reg.pc = u16::from_le_bytes([pcl, pch]);
+                5
+            },
+            Operation::JmpAbsolute => {
+                reg.pc = fetch_address(reg, address_bus);
+                2
+            },
+            Operation::JmpIndirect => {
+                let pointer = fetch_address(reg, address_bus);
+                let pcl = read(pointer, reg, address_bus);
+                let pch = read(jmp_indirect_high(pointer, self.variant), reg, address_bus);
+                reg.pc = u16::from_le_bytes([pcl, pch]);
+                if self.variant.cmos() { 5 } else { 4 }
+            },
+            Operation::JmpIndexedIndirect => {
+                let address = fetch_address(reg, address_bus).wrapping_add(reg.x as u16);
+                let pcl = read(address, reg, address_bus);
+                let pch = read(address.wrapping_add(1), reg, address_bus);
+                reg.pc = u16::from_le_bytes([pcl, pch]);
+                5
+            },
+            Operation::Interrupt(interrupt) => {
+                if interrupt == Interrupt::Brk {
+                    reg.pc = reg.pc.wrapping_add(1);
+                }
+                if interrupt == Interrupt::Reset {
+                    reg.stack = reg.stack.wrapping_sub(3);
+                } else {
+                    let status = match interrupt {
+                        Interrupt::Brk => reg.status.for_push(true),
+                        _ => reg.status.for_push(false),
+                    };
+                    push((reg.pc >> 8) as u8, reg, address_bus);
+                    push(reg.pc as u8, reg, address_bus);
+                    push(status, reg, address_bus);
+                }
+                reg.status.set_irq_disable(true);
+                if self.variant.cmos() {
+                    reg.status.set_decimal_mode(false);
+                }
+                let pcl = read(interrupt.vector(), reg, address_bus);
+                let pch = read(interrupt.vector().wrapping_add(1), reg, address_bus);
+                reg.pc = u16::from_le_bytes([pcl, pch]);
+                if interrupt == Interrupt::Reset { 7 } else { 6 }
+            },
+            Operation::Jam => return (InstructionState::Jammed, 1),
+            Operation::Stop => return (InstructionState::Jammed, 2),
+            Operation::Wait => return (InstructionState::Waiting, 2),
+            Operation::NopSingleCycle => 0,
+            Operation::Nop5C => {
+                fetch_address(reg, address_bus);
+                7
+            },
+        };
+        match self.decimal_cycle && reg.status.decimal_mode() {
+            true => (InstructionState::Finished, cycles + 1),
+            false => (InstructionState::Finished, cycles),
+        }
+    }
+}
+
+// Operations
+
+fn adc(reg: &mut Registers, value: u8) {
+    if reg.status.decimal_mode() {
+        adc_decimal(reg, value);
+    } else {
+        adc_binary(reg, value);
+    }
+}
+
+fn adc_binary(reg: &mut Registers, value: u8) {
+    let carry = if reg.status.carry() { 1 } else { 0 };
+    let total: u16 = reg.a as u16 + value as u16 + carry;
+    let result = total as u8;
+
+    reg.status.set_carry_overflow(reg.a, value, total);
+    reg.status.set_zero_negative(result);
+    reg.a = result;
+}
+
+/// Decimal mode addition the way the NMOS part does it: the low digit is adjusted first and carried into
+/// the high digit, N and V are taken from the sum before the high digit is adjusted, and Z is taken from
+/// the binary sum. Invalid BCD digits fall out of the same steps.
+/// See http://www.6502.org/tutorials/decimal_mode.html
+fn adc_decimal(reg: &mut Registers, value: u8) {
+    let carry = if reg.status.carry() { 1 } else { 0 };
+    let (a, value) = (reg.a as u16, value as u16);
+
+    let mut low = (a & 0x0F) + (value & 0x0F) + carry;
+    if low >= 0x0A {
+        low = ((low + 0x06) & 0x0F) + 0x10;
+    }
+
+    let mut total = (a & 0xF0) + (value & 0xF0) + low;
+    let binary = (a + value + carry) as u8;
+
+    reg.status.set_zero(binary == 0);
+    reg.status.set_negative(total & 0x80 != 0);
+    reg.status.set_overflow((a ^ total) & (value ^ total) & 0x80 != 0);
+
+    if total >= 0xA0 {
+        total += 0x60;
+    }
+
+    reg.status.set_carry(total >= 0x100);
+    reg.a = total as u8;
+}
+
+fn sbc(reg: &mut Registers, value: u8) {
+    if reg.status.decimal_mode() {
+        sbc_decimal(reg, value);
+    } else {
+        sbc_binary(reg, value);
+    }
+}
+
+fn sbc_binary(reg: &mut Registers, value: u8) {
+    adc_binary(reg, !value);
+}
+
+/// Decimal mode subtraction on the NMOS part only adjusts the result. Every flag is the same as it would
+/// be for a binary subtraction.
+fn sbc_decimal(reg: &mut Registers, value: u8) {
+    let borrow = if reg.status.carry() { 0 } else { 1 };
+    let (a, value) = (reg.a as i16, value as i16);
+
+    let mut low = (a & 0x0F) - (value & 0x0F) - borrow;
+    if low < 0 {
+        low = ((low - 0x06) & 0x0F) - 0x10;
+    }
+
+    let mut total = (a & 0xF0) - (value & 0xF0) + low;
+    if total < 0 {
+        total -= 0x60;
+    }
+
+    adc_binary(reg, !(value as u8));
+    reg.a = total as u8;
+}
+
+fn and(reg: &mut Registers, value: u8) {
+    reg.a &= value;
+    reg.status.set_zero_negative(reg.a);
+}
+
+fn ora(reg: &mut Registers, value: u8) {
+    reg.a |= value;
+    reg.status.set_zero_negative(reg.a);
+}
+
+fn eor(reg: &mut Registers, value: u8) {
+    reg.a ^= value;
+    reg.status.set_zero_negative(reg.a);
+}
+
+fn bit(reg: &mut Registers, value: u8) {
+    reg.status.set_bit_test(reg.a, value);
+}
+
+fn cmp(reg: &mut Registers, value: u8) {
+    reg.status.set_compare(reg.a, value);
+}
+
+fn cpx(reg: &mut Registers, value: u8) {
+    reg.status.set_compare(reg.x, value);
+}
+
+fn cpy(reg: &mut Registers, value: u8) {
+    reg.status.set_compare(reg.y, value);
+}
+
+fn lda(reg: &mut Registers, value: u8) {
+    reg.a = value;
+    reg.status.set_zero_negative(value);
+}
+
+fn ldx(reg: &mut Registers, value: u8) {
+    reg.x = value;
+    reg.status.set_zero_negative(value);
+}
+
+fn ldy(reg: &mut Registers, value: u8) {
+    reg.y = value;
+    reg.status.set_zero_negative(value);
+}
+
+fn sta(reg: &mut Registers) -> u8 {
+    reg.a
+}
+
+fn stx(reg: &mut Registers) -> u8 {
+    reg.x
+}
+
+fn sty(reg: &mut Registers) -> u8 {
+    reg.y
+}
+
+fn asl(reg: &mut Registers, value: u8) -> u8 {
+    let result = value << 1;
+    reg.status.set_carry(value & 0b10000000 != 0);
+    reg.status.set_zero_negative(result);
+    result
+}
+
+fn lsr(reg: &mut Registers, value: u8) -> u8 {
+    let result = value >> 1;
+    reg.status.set_carry(value & 0b00000001 != 0);
+    reg.status.set_zero_negative(result);
+    result
+}
+
+fn rol(reg: &mut Registers, value: u8) -> u8 {
+    let carry = if reg.status.carry() { 0b00000001 } else { 0 };
+    let result = (value << 1) | carry;
+    reg.status.set_carry(value & 0b10000000 != 0);
+    reg.status.set_zero_negative(result);
+    result
+}
+
+fn ror(reg: &mut Registers, value: u8) -> u8 {
+    let carry = if reg.status.carry() { 0b10000000 } else { 0 };
+    let result = (value >> 1) | carry;
+    reg.status.set_carry(value & 0b00000001 != 0);
+    reg.status.set_zero_negative(result);
+    result
+}
+
+fn inc(reg: &mut Registers, value: u8) -> u8 {
+    let result = value.wrapping_add(1);
+    reg.status.set_zero_negative(result);
+    result
+}
+
+fn dec(reg: &mut Registers, value: u8) -> u8 {
+    let result = value.wrapping_sub(1);
+    reg.status.set_zero_negative(result);
+    result
+}
+
+fn inx(reg: &mut Registers) {
+    reg.x = reg.x.wrapping_add(1);
+    reg.status.set_zero_negative(reg.x);
+}
+
+fn iny(reg: &mut Registers) {
+    reg.y = reg.y.wrapping_add(1);
+    reg.status.set_zero_negative(reg.y);
+}
+
+fn dex(reg: &mut Registers) {
+    reg.x = reg.x.wrapping_sub(1);
+    reg.status.set_zero_negative(reg.x);
+}
+
+fn dey(reg: &mut Registers) {
+    reg.y = reg.y.wrapping_sub(1);
+    reg.status.set_zero_negative(reg.y);
+}
+
+fn tax(reg: &mut Registers) {
+    reg.x = reg.a;
+    reg.status.set_zero_negative(reg.x);
+}
+
+fn tay(reg: &mut Registers) {
+    reg.y = reg.a;
+    reg.status.set_zero_negative(reg.y);
+}
+
+fn txa(reg: &mut Registers) {
+    reg.a = reg.x;
+    reg.status.set_zero_negative(reg.a);
+}
+
+fn tya(reg: &mut Registers) {
+    reg.a = reg.y;
+    reg.status.set_zero_negative(reg.a);
+}
+
+fn clc(reg: &mut Registers) {
+    reg.status.set_carry(false);
+}
+
+fn cld(reg: &mut Registers) {
+    reg.status.set_decimal_mode(false);
+}
+
+fn cli(reg: &mut Registers) {
+    reg.status.set_irq_disable(false);
+}
+
+fn clv(reg: &mut Registers) {
+    reg.status.set_overflow(false);
+}
+
+fn sec(reg: &mut Registers) {
+    reg.status.set_carry(true);
+}
+
+fn sed(reg: &mut Registers) {
+    reg.status.set_decimal_mode(true);
+}
+
+fn sei(reg: &mut Registers) {
+    reg.status.set_irq_disable(true);
+}
+
+fn tsx(reg: &mut Registers) {
+    reg.x = reg.stack;
+    reg.status.set_zero_negative(reg.x);
+}
+
+fn txs(reg: &mut Registers) {
+    reg.stack = reg.x;
+}
+
+fn pha(reg: &mut Registers) -> u8 {
+    reg.a
+}
+
+fn php(reg: &mut Registers) -> u8 {
+    reg.status.for_push(true)
+}
+
+fn pla(reg: &mut Registers, value: u8) {
+    reg.a = value;
+    reg.status.set_zero_negative(value);
+}
+
+fn plp(reg: &mut Registers, value: u8) {
+    reg.status = StatusRegister::from_pull(value);
+}
+
+fn nop(_reg: &mut Registers) {}
+
+fn nop_read(_reg: &mut Registers, _value: u8) {}
+
+// Undocumented operations
+// Most of the undocumented opcodes are two documented operations the decoder ends up running at once. See
+// https://www.masswerk.at/nowgobang/2021/6502-illegal-opcodes and http://www.oxyron.de/html/opcodes02.html
+
+fn slo(reg: &mut Registers, value: u8) -> u8 {
+    let result = asl(reg, value);
+    ora(reg, result);
+    result
+}
+
+fn rla(reg: &mut Registers, value: u8) -> u8 {
+    let result = rol(reg, value);
+    and(reg, result);
+    result
+}
+
+fn sre(reg: &mut Registers, value: u8) -> u8 {
+    let result = lsr(reg, value);
+    eor(reg, result);
+    result
+}
+
+fn rra(reg: &mut Registers, value: u8) -> u8 {
+    let result = ror(reg, value);
+    adc(reg, result);
+    result
+}
+
+fn rra_binary(reg: &mut Registers, value: u8) -> u8 {
+    let result = ror(reg, value);
+    adc_binary(reg, result);
+    result
+}
+
+fn dcp(reg: &mut Registers, value: u8) -> u8 {
+    let result = value.wrapping_sub(1);
+    cmp(reg, result);
+    result
+}
+
+fn isc(reg: &mut Registers, value: u8) -> u8 {
+    let result = value.wrapping_add(1);
+    sbc(reg, result);
+    result
+}
+
+fn isc_binary(reg: &mut Registers, value: u8) -> u8 {
+    let result = value.wrapping_add(1);
+    sbc_binary(reg, result);
+    result
+}
+
+fn sax(reg: &mut Registers) -> u8 {
+    reg.a & reg.x
+}
+
+fn lax(reg: &mut Registers, value: u8) {
+    reg.a = value;
+    reg.x = value;
+    reg.status.set_zero_negative(value);
+}
+
+fn anc(reg: &mut Registers, value: u8) {
+    and(reg, value);
+    reg.status.set_carry(reg.a & 0b10000000 != 0);
+}
+
+fn alr(reg: &mut Registers, value: u8) {
+    reg.a = lsr(reg, reg.a & value);
+}
+
+/// AND then ROR A, except that the flags come out of the adder: C is bit 6 of the result and V is bit 6 XOR
+/// bit 5. In decimal mode the result is also BCD adjusted, digit by digit, with C set by the high digit.
+fn arr(reg: &mut Registers, value: u8) {
+    if reg.status.decimal_mode() {
+        arr_decimal(reg, value);
+    } else {
+        arr_binary(reg, value);
+    }
+}
+
+fn arr_binary(reg: &mut Registers, value: u8) {
+    let carry = if reg.status.carry() { 0b10000000 } else { 0 };
+    let result = ((reg.a & value) >> 1) | carry;
+    reg.status.set_zero_negative(result);
+    reg.status.set_carry(result & 0b01000000 != 0);
+    reg.status.set_overflow(((result >> 6) ^ (result >> 5)) & 0x01 != 0);
+    reg.a = result;
+}
+
+fn arr_decimal(reg: &mut Registers, value: u8) {
+    let carry = if reg.status.carry() { 0b10000000 } else { 0 };
+    let and = reg.a & value;
+    let mut result = (and >> 1) | carry;
+    reg.status.set_zero_negative(result);
+    reg.status.set_overflow((and ^ result) & 0b01000000 != 0);
+
+    if (and & 0x0F) + (and & 0x01) > 0x05 {
+        result = (result & 0xF0) | (result.wrapping_add(0x06) & 0x0F);
+    }
+    let high = and >> 4;
+    let carry = high + (high & 0x01) > 0x05;
+    if carry {
+        result = result.wrapping_add(0x60);
+    }
+    reg.status.set_carry(carry);
+    reg.a = result;
+}
+
+/// X = (A AND X) - value, setting the flags like CMP. Decimal mode doesn't apply.
+fn sbx(reg: &mut Registers, value: u8) {
+    let and = reg.a & reg.x;
+    reg.status.set_carry(and >= value);
+    reg.x = and.wrapping_sub(value);
+    reg.status.set_zero_negative(reg.x);
+}
+
+fn las(reg: &mut Registers, value: u8) {
+    let result = value & reg.stack;
+    (reg.a, reg.x, reg.stack) = (result, result, result);
+    reg.status.set_zero_negative(result);
+}
+
+fn ane(reg: &mut Registers, value: u8, magic: u8) {
+    reg.a = (reg.a | magic) & reg.x & value;
+    reg.status.set_zero_negative(reg.a);
+}
+
+fn lxa(reg: &mut Registers, value: u8, magic: u8) {
+    reg.a = (reg.a | magic) & value;
+    reg.x = reg.a;
+    reg.status.set_zero_negative(reg.a);
+}
+
+fn sha(reg: &mut Registers, high: u8) -> u8 {
+    reg.a & reg.x & high
+}
+
+fn shx(reg: &mut Registers, high: u8) -> u8 {
+    reg.x & high
+}
+
+fn shy(reg: &mut Registers, high: u8) -> u8 {
+    reg.y & high
+}
+
+fn tas(reg: &mut Registers, high: u8) -> u8 {
+    reg.stack = reg.a & reg.x;
+    reg.stack & high
+}
+
+// 65C02 operations
+
+/// The 65C02's decimal mode addition gets the same result as the NMOS part, but sets N and Z from it.
+fn adc_cmos(reg: &mut Registers, value: u8) {
+    adc(reg, value);
+    reg.status.set_zero_negative(reg.a);
+}
+
+/// The 65C02 adjusts decimal mode subtraction differently from the NMOS part, which only matters for invalid
+/// BCD digits. C and V are the same as for a binary subtraction, and N and Z are set from the result.
+/// See http://www.6502.org/tutorials/decimal_mode.html#A
+fn sbc_cmos(reg: &mut Registers, value: u8) {
+    if !reg.status.decimal_mode() {
+        sbc_binary(reg, value);
+        return;
+    }
+
+    let borrow = if reg.status.carry() { 0 } else { 1 };
+    let (a, operand) = (reg.a as i16, value as i16);
+
+    let low = (a & 0x0F) - (operand & 0x0F) - borrow;
+    let mut total = a - operand - borrow;
+    if total < 0 {
+        total -= 0x60;
+    }
+    if low < 0 {
+        total -= 0x06;
+    }
+
+    adc_binary(reg, !value);
+    reg.a = total as u8;
+    reg.status.set_zero_negative(reg.a);
+}
+
+/// BIT immediate only sets Z, since N and V would come from the operand rather than memory.
+fn bit_immediate(reg: &mut Registers, value: u8) {
+    reg.status.set_zero(reg.a & value == 0);
+}
+
+fn stz(_reg: &mut Registers) -> u8 {
+    0
+}
+
+fn tsb(reg: &mut Registers, value: u8) -> u8 {
+    reg.status.set_zero(reg.a & value == 0);
+    value | reg.a
+}
+
+fn trb(reg: &mut Registers, value: u8) -> u8 {
+    reg.status.set_zero(reg.a & value == 0);
+    value & !reg.a
+}
+
+fn phx(reg: &mut Registers) -> u8 {
+    reg.x
+}
+
+fn phy(reg: &mut Registers) -> u8 {
+    reg.y
+}
+
+fn plx(reg: &mut Registers, value: u8) {
+    reg.x = value;
+    reg.status.set_zero_negative(value);
+}
+
+fn ply(reg: &mut Registers, value: u8) {
+    reg.y = value;
+    reg.status.set_zero_negative(value);
+}
+
+fn rmb<const BIT: u8>(_reg: &mut Registers, value: u8) -> u8 {
+    value & !(1 << BIT)
+}
+
+fn smb<const BIT: u8>(_reg: &mut Registers, value: u8) -> u8 {
+    value | (1 << BIT)
+}
+
+// Addressing mode constructors, for building the opcode table
+
+const fn implied(op: ImpliedOp) -> Option<Operation> {
+    Some(Operation::Implied(op))
+}
+
+const fn accumulator(op: ModifyOp) -> Option<Operation> {
+    Some(Operation::Accumulator(op))
+}
+
+const fn immediate(op: ReadOp) -> Option<Operation> {
+    Some(Operation::Immediate(op))
+}
+
+const fn zero_page(access: Access) -> Option<Operation> {
+    Some(Operation::ZeroPage(access))
+}
+
+const fn zero_page_x(access: Access) -> Option<Operation> {
+    Some(Operation::ZeroPageIndexed(access, Index::X))
+}
+
+const fn zero_page_y(access: Access) -> Option<Operation> {
+    Some(Operation::ZeroPageIndexed(access, Index::Y))
+}
+
+const fn absolute(access: Access) -> Option<Operation> {
+    Some(Operation::Absolute(access))
+}
+
+const fn absolute_x(access: Access) -> Option<Operation> {
+    Some(Operation::AbsoluteIndexed(access, Index::X, Fixup::default_for(access)))
+}
+
+const fn absolute_y(access: Access) -> Option<Operation> {
+    Some(Operation::AbsoluteIndexed(access, Index::Y, Fixup::default_for(access)))
+}
+
+/// The 65C02's shifts and rotates, which only fix the address up when the index crosses a page
+const fn absolute_x_when_crossing(access: Access) -> Option<Operation> {
+    Some(Operation::AbsoluteIndexed(access, Index::X, Fixup::WhenCrossing))
+}
+
+const fn indexed_indirect(access: Access) -> Option<Operation> {
+    Some(Operation::IndexedIndirect(access))
+}
+
+const fn indirect_indexed(access: Access) -> Option<Operation> {
+    Some(Operation::IndirectIndexed(access))
+}
+
+const fn push_stack(op: WriteOp) -> Option<Operation> {
+    Some(Operation::Push(op))
+}
+
+const fn pull_stack(op: ReadOp) -> Option<Operation> {
+    Some(Operation::Pull(op))
+}
+
+const fn immediate_magic(op: MagicOp) -> Option<Operation> {
+    Some(Operation::ImmediateMagic(op))
+}
+
+const fn relative(flag: Flags, set: bool) -> Option<Operation> {
+    Some(Operation::Relative(Branch::Flag(flag, set)))
+}
+
+const fn zero_page_indirect(access: Access) -> Option<Operation> {
+    Some(Operation::ZeroPageIndirect(access))
+}
+
+const fn branch_bit(bit: u8, set: bool) -> Option<Operation> {
+    Some(Operation::BranchBit(bit, set))
+}
+
+/// What each opcode decodes to on each variant, indexed by the opcode. Opcodes we don't know how to run are None.
+static NMOS_OPCODES: [Option<Operation>; 256] = nmos_table();
+static CMOS_OPCODES: [Option<Operation>; 256] = cmos_table();
+static BINARY_OPCODES: [Option<Operation>; 256] = binary_table();
+
+/// The documented opcodes, which every variant shares.
+const fn documented_table() -> [Option<Operation>; 256] {
+    let mut table = [None; 256];
+
+    // ADC
+    table[0x69] = immediate(adc);
+    table[0x65] = zero_page(Read(adc));
+    table[0x75] = zero_page_x(Read(adc));
+    table[0x6d] = absolute(Read(adc));
+    table[0x7d] = absolute_x(Read(adc));
+    table[0x79] = absolute_y(Read(adc));
+    table[0x61] = indexed_indirect(Read(adc));
+    table[0x71] = indirect_indexed(Read(adc));
+
+    // AND
+    table[0x29] = immediate(and);
+    table[0x25] = zero_page(Read(and));
+    table[0x35] = zero_page_x(Read(and));
+    table[0x2d] = absolute(Read(and));
+    table[0x3d] = absolute_x(Read(and));
+    table[0x39] = absolute_y(Read(and));
+    table[0x21] = indexed_indirect(Read(and));
+    table[0x31] = indirect_indexed(Read(and));
+
+    // ASL
+    table[0x0a] = accumulator(asl);
+    table[0x06] = zero_page(Modify(asl));
+    table[0x16] = zero_page_x(Modify(asl));
+    table[0x0e] = absolute(Modify(asl));
+    table[0x1e] = absolute_x(Modify(asl));
+
+    // Branches
+    table[0x90] = relative(StatusRegister::CARRY, false);
+    table[0xb0] = relative(StatusRegister::CARRY, true);
+    table[0xd0] = relative(StatusRegister::ZERO, false);
+    table[0xf0] = relative(StatusRegister::ZERO, true);
+    table[0x10] = relative(StatusRegister::NEGATIVE, false);
+    table[0x30] = relative(StatusRegister::NEGATIVE, true);
+    table[0x50] = relative(StatusRegister::OVERFLOW, false);
+    table[0x70] = relative(StatusRegister::OVERFLOW, true);
+
+    // BIT
+    table[0x24] = zero_page(Read(bit));
+    table[0x2c] = absolute(Read(bit));
+
+    // Flag instructions
+    table[0x18] = implied(clc);
+    table[0xd8] = implied(cld);
+    table[0x58] = implied(cli);
+    table[0xb8] = implied(clv);
+    table[0x38] = implied(sec);
+    table[0xf8] = implied(sed);
+    table[0x78] = implied(sei);
+
+    // CMP
+    table[0xc9] = immediate(cmp);
+    table[0xc5] = zero_page(Read(cmp));
+    table[0xd5] = zero_page_x(Read(cmp));
+    table[0xcd] = absolute(Read(cmp));
+    table[0xdd] = absolute_x(Read(cmp));
+    table[0xd9] = absolute_y(Read(cmp));
+    table[0xc1] = indexed_indirect(Read(cmp));
+    table[0xd1] = indirect_indexed(Read(cmp));
+
+    // CPX
+    table[0xe0] = immediate(cpx);
+    table[0xe4] = zero_page(Read(cpx));
+    table[0xec] = absolute(Read(cpx));
+
+    // CPY
+    table[0xc0] = immediate(cpy);
+    table[0xc4] = zero_page(Read(cpy));
+    table[0xcc] = absolute(Read(cpy));
+
+    // DEC
+    table[0xc6] = zero_page(Modify(dec));
+    table[0xd6] = zero_page_x(Modify(dec));
+    table[0xce] = absolute(Modify(dec));
+    table[0xde] = absolute_x(Modify(dec));
+    table[0xca] = implied(dex);
+    table[0x88] = implied(dey);
+
+    // EOR
+    table[0x49] = immediate(eor);
+    table[0x45] = zero_page(Read(eor));
+    table[0x55] = zero_page_x(Read(eor));
+    table[0x4d] = absolute(Read(eor));
+    table[0x5d] = absolute_x(Read(eor));
+    table[0x59] = absolute_y(Read(eor));
+    table[0x41] = indexed_indirect(Read(eor));
+    table[0x51] = indirect_indexed(Read(eor));
+
+    // INC
+    table[0xe6] = zero_page(Modify(inc));
+    table[0xf6] = zero_page_x(Modify(inc));
+    table[0xee] = absolute(Modify(inc));
+    table[0xfe] = absolute_x(Modify(inc));
+    table[0xe8] = implied(inx);
+    table[0xc8] = implied(iny);
+
+    // JMP
+    table[0x4c] = Some(Operation::JmpAbsolute);
+    table[0x6c] = Some(Operation::JmpIndirect);
+
+    // Subroutines and interrupts
+    table[0x20] = Some(Operation::Jsr);
+    table[0x60] = Some(Operation::Rts);
+    table[0x40] = Some(Operation::Rti);
+    table[0x00] = Some(Operation::Interrupt(Interrupt::Brk));
+
+    // LDA
+    table[0xa9] = immediate(lda);
+    table[0xa5] = zero_page(Read(lda));
+    table[0xb5] = zero_page_x(Read(lda));
+    table[0xad] = absolute(Read(lda));
+    table[0xbd] = absolute_x(Read(lda));
+    table[0xb9] = absolute_y(Read(lda));
+    table[0xa1] = indexed_indirect(Read(lda));
+    table[0xb1] = indirect_indexed(Read(lda));
+
+    // LDX
+    table[0xa2] = immediate(ldx);
+    table[0xa6] = zero_page(Read(ldx));
+    table[0xb6] = zero_page_y(Read(ldx));
+    table[0xae] = absolute(Read(ldx));
+    table[0xbe] = absolute_y(Read(ldx));
+
+    // LDY
+    table[0xa0] = immediate(ldy);
+    table[0xa4] = zero_page(Read(ldy));
+    table[0xb4] = zero_page_x(Read(ldy));
+    table[0xac] = absolute(Read(ldy));
+    table[0xbc] = absolute_x(Read(ldy));
+
+    // LSR
+    table[0x4a] = accumulator(lsr);
+    table[0x46] = zero_page(Modify(lsr));
+    table[0x56] = zero_page_x(Modify(lsr));
+    table[0x4e] = absolute(Modify(lsr));
+    table[0x5e] = absolute_x(Modify(lsr));
+
+    // NOP
+    table[0xea] = implied(nop);
+
+    // ORA
+    table[0x09] = immediate(ora);
+    table[0x05] = zero_page(Read(ora));
+    table[0x15] = zero_page_x(Read(ora));
+    table[0x0d] = absolute(Read(ora));
+    table[0x1d] = absolute_x(Read(ora));
+    table[0x19] = absolute_y(Read(ora));
+    table[0x01] = indexed_indirect(Read(ora));
+    table[0x11] = indirect_indexed(Read(ora));
+
+    // ROL
+    table[0x2a] = accumulator(rol);
+    table[0x26] = zero_page(Modify(rol));
+    table[0x36] = zero_page_x(Modify(rol));
+    table[0x2e] = absolute(Modify(rol));
+    table[0x3e] = absolute_x(Modify(rol));
+
+    // ROR
+    table[0x6a] = accumulator(ror);
+    table[0x66] = zero_page(Modify(ror));
+    table[0x76] = zero_page_x(Modify(ror));
+    table[0x6e] = absolute(Modify(ror));
+    table[0x7e] = absolute_x(Modify(ror));
+
+    // SBC
+    table[0xe9] = immediate(sbc);
+    table[0xe5] = zero_page(Read(sbc));
+    table[0xf5] = zero_page_x(Read(sbc));
+    table[0xed] = absolute(Read(sbc));
+    table[0xfd] = absolute_x(Read(sbc));
+    table[0xf9] = absolute_y(Read(sbc));
+    table[0xe1] = indexed_indirect(Read(sbc));
+    table[0xf1] = indirect_indexed(Read(sbc));
+
+    // STA
+    table[0x85] = zero_page(Write(sta));
+    table[0x95] = zero_page_x(Write(sta));
+    table[0x8d] = absolute(Write(sta));
+    table[0x9d] = absolute_x(Write(sta));
+    table[0x99] = absolute_y(Write(sta));
+    table[0x81] = indexed_indirect(Write(sta));
+    table[0x91] = indirect_indexed(Write(sta));
+
+    // STX
+    table[0x86] = zero_page(Write(stx));
+    table[0x96] = zero_page_y(Write(stx));
+    table[0x8e] = absolute(Write(stx));
+
+    // STY
+    table[0x84] = zero_page(Write(sty));
+    table[0x94] = zero_page_x(Write(sty));
+    table[0x8c] = absolute(Write(sty));
+
+    // Register transfers
+    table[0xaa] = implied(tax);
+    table[0xa8] = implied(tay);
+    table[0x8a] = implied(txa);
+    table[0x98] = implied(tya);
+    table[0xba] = implied(tsx);
+    table[0x9a] = implied(txs);
+
+    // Stack
+    table[0x48] = push_stack(pha);
+    table[0x08] = push_stack(php);
+    table[0x68] = pull_stack(pla);
+    table[0x28] = pull_stack(plp);
+
+    table
+}
+
+/// The NMOS part, which runs the undocumented opcodes as whatever its decoder happens to make of them.
+const fn nmos_table() -> [Option<Operation>; 256] {
+    let mut table = documented_table();
+
+    // JAM
+    let mut jams = [0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2].as_slice();
+    while let [opcode, rest @ ..] = jams {
+        table[*opcode] = Some(Operation::Jam);
+        jams = rest;
+    }
+
+    // Undocumented
+    // SLO
+    table[0x07] = zero_page(Modify(slo));
+    table[0x17] = zero_page_x(Modify(slo));
+    table[0x0f] = absolute(Modify(slo));
+    table[0x1f] = absolute_x(Modify(slo));
+    table[0x1b] = absolute_y(Modify(slo));
+    table[0x03] = indexed_indirect(Modify(slo));
+    table[0x13] = indirect_indexed(Modify(slo));
+
+    // RLA
+    table[0x27] = zero_page(Modify(rla));
+    table[0x37] = zero_page_x(Modify(rla));
+    table[0x2f] = absolute(Modify(rla));
+    table[0x3f] = absolute_x(Modify(rla));
+    table[0x3b] = absolute_y(Modify(rla));
+    table[0x23] = indexed_indirect(Modify(rla));
+    table[0x33] = indirect_indexed(Modify(rla));
+
+    // SRE
+    table[0x47] = zero_page(Modify(sre));
+    table[0x57] = zero_page_x(Modify(sre));
+    table[0x4f] = absolute(Modify(sre));
+    table[0x5f] = absolute_x(Modify(sre));
+    table[0x5b] = absolute_y(Modify(sre));
+    table[0x43] = indexed_indirect(Modify(sre));
+    table[0x53] = indirect_indexed(Modify(sre));
+
+    // RRA
+    table[0x67] = zero_page(Modify(rra));
+    table[0x77] = zero_page_x(Modify(rra));
+    table[0x6f] = absolute(Modify(rra));
+    table[0x7f] = absolute_x(Modify(rra));
+    table[0x7b] = absolute_y(Modify(rra));
+    table[0x63] = indexed_indirect(Modify(rra));
+    table[0x73] = indirect_indexed(Modify(rra));
+
+    // SAX
+    table[0x87] = zero_page(Write(sax));
+    table[0x97] = zero_page_y(Write(sax));
+    table[0x8f] = absolute(Write(sax));
+    table[0x83] = indexed_indirect(Write(sax));
+
+    // LAX
+    table[0xa7] = zero_page(Read(lax));
+    table[0xb7] = zero_page_y(Read(lax));
+    table[0xaf] = absolute(Read(lax));
+    table[0xbf] = absolute_y(Read(lax));
+    table[0xa3] = indexed_indirect(Read(lax));
+    table[0xb3] = indirect_indexed(Read(lax));
+
+    // DCP
+    table[0xc7] = zero_page(Modify(dcp));
+    table[0xd7] = zero_page_x(Modify(dcp));
+    table[0xcf] = absolute(Modify(dcp));
+    table[0xdf] = absolute_x(Modify(dcp));
+    table[0xdb] = absolute_y(Modify(dcp));
+    table[0xc3] = indexed_indirect(Modify(dcp));
+    table[0xd3] = indirect_indexed(Modify(dcp));
+
+    // ISC
+    table[0xe7] = zero_page(Modify(isc));
+    table[0xf7] = zero_page_x(Modify(isc));
+    table[0xef] = absolute(Modify(isc));
+    table[0xff] = absolute_x(Modify(isc));
+    table[0xfb] = absolute_y(Modify(isc));
+    table[0xe3] = indexed_indirect(Modify(isc));
+    table[0xf3] = indirect_indexed(Modify(isc));
+
+    // Immediate
+    table[0x0b] = immediate(anc);
+    table[0x2b] = immediate(anc);
+    table[0x4b] = immediate(alr);
+    table[0x6b] = immediate(arr);
+    table[0xcb] = immediate(sbx);
+    table[0xeb] = immediate(sbc);
+
+    // Unstable
+    table[0x8b] = immediate_magic(ane);
+    table[0xab] = immediate_magic(lxa);
+    table[0xbb] = absolute_y(Read(las));
+    table[0x93] = indirect_indexed(StoreHigh(sha));
+    table[0x9f] = absolute_y(StoreHigh(sha));
+    table[0x9e] = absolute_y(StoreHigh(shx));
+    table[0x9c] = absolute_x(StoreHigh(shy));
+    table[0x9b] = absolute_y(StoreHigh(tas));
+
+    // NOPs, which go through the motions of their addressing mode
+    table[0x1a] = implied(nop);
+    table[0x3a] = implied(nop);
+    table[0x5a] = implied(nop);
+    table[0x7a] = implied(nop);
+    table[0xda] = implied(nop);
+    table[0xfa] = implied(nop);
+    table[0x80] = immediate(nop_read);
+    table[0x82] = immediate(nop_read);
+    table[0x89] = immediate(nop_read);
+    table[0xc2] = immediate(nop_read);
+    table[0xe2] = immediate(nop_read);
+    table[0x04] = zero_page(Read(nop_read));
+    table[0x44] = zero_page(Read(nop_read));
+    table[0x64] = zero_page(Read(nop_read));
+    table[0x14] = zero_page_x(Read(nop_read));
+    table[0x34] = zero_page_x(Read(nop_read));
+    table[0x54] = zero_page_x(Read(nop_read));
+    table[0x74] = zero_page_x(Read(nop_read));
+    table[0xd4] = zero_page_x(Read(nop_read));
+    table[0xf4] = zero_page_x(Read(nop_read));
+    table[0x0c] = absolute(Read(nop_read));
+    table[0x1c] = absolute_x(Read(nop_read));
+    table[0x3c] = absolute_x(Read(nop_read));
+    table[0x5c] = absolute_x(Read(nop_read));
+    table[0x7c] = absolute_x(Read(nop_read));
+    table[0xdc] = absolute_x(Read(nop_read));
+    table[0xfc] = absolute_x(Read(nop_read));
+
+    table
+}
+
+/// The NMOS part without decimal mode, as in the 2A03, where everything that would adjust for it works in binary.
+const fn binary_table() -> [Option<Operation>; 256] {
+    let mut table = nmos_table();
+
+    // ADC
+    table[0x69] = immediate(adc_binary);
+    table[0x65] = zero_page(Read(adc_binary));
+    table[0x75] = zero_page_x(Read(adc_binary));
+    table[0x6d] = absolute(Read(adc_binary));
+    table[0x7d] = absolute_x(Read(adc_binary));
+    table[0x79] = absolute_y(Read(adc_binary));
+    table[0x61] = indexed_indirect(Read(adc_binary));
+    table[0x71] = indirect_indexed(Read(adc_binary));
+
+    // SBC
+    table[0xe9] = immediate(sbc_binary);
+    table[0xeb] = immediate(sbc_binary);
+    table[0xe5] = zero_page(Read(sbc_binary));
+    table[0xf5] = zero_page_x(Read(sbc_binary));
+    table[0xed] = absolute(Read(sbc_binary));
+    table[0xfd] = absolute_x(Read(sbc_binary));
+    table[0xf9] = absolute_y(Read(sbc_binary));
+    table[0xe1] = indexed_indirect(Read(sbc_binary));
+    table[0xf1] = indirect_indexed(Read(sbc_binary));
+
+    // RRA
+    table[0x67] = zero_page(Modify(rra_binary));
+    table[0x77] = zero_page_x(Modify(rra_binary));
+    table[0x6f] = absolute(Modify(rra_binary));
+    table[0x7f] = absolute_x(Modify(rra_binary));
+    table[0x7b] = absolute_y(Modify(rra_binary));
+    table[0x63] = indexed_indirect(Modify(rra_binary));
+    table[0x73] = indirect_indexed(Modify(rra_binary));
+
+    // ISC
+    table[0xe7] = zero_page(Modify(isc_binary));
+    table[0xf7] = zero_page_x(Modify(isc_binary));
+    table[0xef] = absolute(Modify(isc_binary));
+    table[0xff] = absolute_x(Modify(isc_binary));
+    table[0xfb] = absolute_y(Modify(isc_binary));
+    table[0xe3] = indexed_indirect(Modify(isc_binary));
+    table[0xf3] = indirect_indexed(Modify(isc_binary));
+
+    // ARR
+    table[0x6b] = immediate(arr_binary);
+
+    table
+}
+
+/// The 65C02, which adds its own instructions and makes every other opcode a NOP.
+const fn cmos_table() -> [Option<Operation>; 256] {
+    let mut table = documented_table();
+
+    // ADC and SBC, which set N and Z properly in decimal mode
+    table[0x69] = immediate(adc_cmos);
+    table[0x65] = zero_page(Read(adc_cmos));
+    table[0x75] = zero_page_x(Read(adc_cmos));
+    table[0x6d] = absolute(Read(adc_cmos));
+    table[0x7d] = absolute_x(Read(adc_cmos));
+    table[0x79] = absolute_y(Read(adc_cmos));
+    table[0x61] = indexed_indirect(Read(adc_cmos));
+    table[0x71] = indirect_indexed(Read(adc_cmos));
+    table[0xe9] = immediate(sbc_cmos);
+    table[0xe5] = zero_page(Read(sbc_cmos));
+    table[0xf5] = zero_page_x(Read(sbc_cmos));
+    table[0xed] = absolute(Read(sbc_cmos));
+    table[0xfd] = absolute_x(Read(sbc_cmos));
+    table[0xf9] = absolute_y(Read(sbc_cmos));
+    table[0xe1] = indexed_indirect(Read(sbc_cmos));
+    table[0xf1] = indirect_indexed(Read(sbc_cmos));
+
+    // Shifts and rotates
+    table[0x1e] = absolute_x_when_crossing(Modify(asl));
+    table[0x5e] = absolute_x_when_crossing(Modify(lsr));
+    table[0x3e] = absolute_x_when_crossing(Modify(rol));
+    table[0x7e] = absolute_x_when_crossing(Modify(ror));
+
+    // (zp)
+    table[0x12] = zero_page_indirect(Read(ora));
+    table[0x32] = zero_page_indirect(Read(and));
+    table[0x52] = zero_page_indirect(Read(eor));
+    table[0x72] = zero_page_indirect(Read(adc_cmos));
+    table[0x92] = zero_page_indirect(Write(sta));
+    table[0xb2] = zero_page_indirect(Read(lda));
+    table[0xd2] = zero_page_indirect(Read(cmp));
+    table[0xf2] = zero_page_indirect(Read(sbc_cmos));
+
+    // BIT
+    table[0x89] = immediate(bit_immediate);
+    table[0x34] = zero_page_x(Read(bit));
+    table[0x3c] = absolute_x(Read(bit));
+
+    // INC A, DEC A
+    table[0x1a] = accumulator(inc);
+    table[0x3a] = accumulator(dec);
+
+    // BRA
+    table[0x80] = Some(Operation::Relative(Branch::Always));
+
+    // JMP
+    table[0x7c] = Some(Operation::JmpIndexedIndirect);
+
+    // Stack
+    table[0xda] = push_stack(phx);
+    table[0x5a] = push_stack(phy);
+    table[0xfa] = pull_stack(plx);
+    table[0x7a] = pull_stack(ply);
+
+    // STZ
+    table[0x64] = zero_page(Write(stz));
+    table[0x74] = zero_page_x(Write(stz));
+    table[0x9c] = absolute(Write(stz));
+    table[0x9e] = absolute_x(Write(stz));
+
+    // TSB, TRB
+    table[0x04] = zero_page(Modify(tsb));
+    table[0x0c] = absolute(Modify(tsb));
+    table[0x14] = zero_page(Modify(trb));
+    table[0x1c] = absolute(Modify(trb));
+
+    // RMB, SMB
+    table[0x07] = zero_page(Modify(rmb::<0>));
+    table[0x17] = zero_page(Modify(rmb::<1>));
+    table[0x27] = zero_page(Modify(rmb::<2>));
+    table[0x37] = zero_page(Modify(rmb::<3>));
+    table[0x47] = zero_page(Modify(rmb::<4>));
+    table[0x57] = zero_page(Modify(rmb::<5>));
+    table[0x67] = zero_page(Modify(rmb::<6>));
+    table[0x77] = zero_page(Modify(rmb::<7>));
+    table[0x87] = zero_page(Modify(smb::<0>));
+    table[0x97] = zero_page(Modify(smb::<1>));
+    table[0xa7] = zero_page(Modify(smb::<2>));
+    table[0xb7] = zero_page(Modify(smb::<3>));
+    table[0xc7] = zero_page(Modify(smb::<4>));
+    table[0xd7] = zero_page(Modify(smb::<5>));
+    table[0xe7] = zero_page(Modify(smb::<6>));
+    table[0xf7] = zero_page(Modify(smb::<7>));
+
+    // BBR, BBS
+    let mut bit = 0;
+    while bit < 8 {
+        table[0x0f + bit as usize * 0x10] = branch_bit(bit, false);
+        table[0x8f + bit as usize * 0x10] = branch_bit(bit, true);
+        bit += 1;
+    }
+
+    // WAI, STP
+    table[0xcb] = Some(Operation::Wait);
+    table[0xdb] = Some(Operation::Stop);
+
+    // NOPs. The rest of columns 3 and B are done with on the opcode fetch, and the others read an operand the
+    // way the NMOS part's NOPs in the same place do.
+    let mut opcode = 0;
+    while opcode < 0x100 {
+        if table[opcode].is_none() && (opcode & 0x07 == 0x03) {
+            table[opcode] = Some(Operation::NopSingleCycle);
+        }
+        opcode += 1;
+    }
+    let mut immediates = [0x02, 0x22, 0x42, 0x62, 0x82, 0xc2, 0xe2].as_slice();
+    while let [opcode, rest @ ..] = immediates {
+        table[*opcode] = immediate(nop_read);
+        immediates = rest;
+    }
+    table[0x44] = zero_page(Read(nop_read));
+    table[0x54] = zero_page_x(Read(nop_read));
+    table[0xd4] = zero_page_x(Read(nop_read));
+    table[0xf4] = zero_page_x(Read(nop_read));
+    table[0xdc] = absolute(Read(nop_read));
+    table[0xfc] = absolute(Read(nop_read));
+    table[0x5c] = Some(Operation::Nop5C);
+
+    table
+}
+
+/// Decode an opcode into the instruction that runs it on a variant, or None if it's one we don't know how to run.
+/// The magic constant is what the NMOS part's unstable ANE and LXA opcodes OR into A.
+pub fn find_instruction(opcode: u8, variant: Variant, magic: u8) -> Option<Instruction> {
+    let table = match variant {
+        Variant::Wdc65C02 => &CMOS_OPCODES,
+        _ if !variant.decimal_mode() => &BINARY_OPCODES,
+        _ => &NMOS_OPCODES,
+    };
+    table[opcode as usize].map(|operation| Instruction {
+        decimal_cycle: variant.cmos() && opcode_info::CMOS_OPCODES[opcode as usize].decimal_cycle,
+        ..Instruction::new(operation, variant, magic)
+    })
+}
+
+/// The instruction that runs an interrupt sequence in place of the next opcode.
+pub fn interrupt_sequence(interrupt: Interrupt, variant: Variant) -> Instruction {
+    Instruction::new(Operation::Interrupt(interrupt), variant, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::cpu_6502::{CPU6502, ExecutionMode, Registers};
+    use crate::cpu::opcode_info::{self, AddressingMode};
+    use crate::cpu::opcodes::{find_instruction, Access, Fixup, Index, Instruction, InstructionState, Operation};
+    use crate::cpu::status_register::{Flags, StatusRegister};
+    use crate::cpu::variant::Variant;
+    use crate::io_device::IODevice;
+    use crate::memory::ram::RAM;
+    use std::cell::RefCell;
+
+    use BusAccess::{Read, Write};
+    use Mode::*;
+
+    const START: u16 = 0x0200;
+
+    #[derive(Clone, Copy, Debug)]
+    enum Mode {
+        Immediate,
+        ZeroPage,
+        ZeroPageX,
+        ZeroPageY,
+        Absolute,
+        AbsoluteX,
+        AbsoluteY,
+        IndirectX,
+        IndirectY,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum BusAccess {
+        Read(u16, u8),
+        Write(u16, u8),
+    }
+
+    /// 64k of RAM that keeps a log of every access made through it.
+    struct TestBus {
+        ram: RAM<0x10000>,
+        log: RefCell<Vec<BusAccess>>,
+    }
+
+    impl IODevice for TestBus {
+        fn get(&self, addr: u16) -> u8 {
+            let value = self.ram.get(addr);
+            self.log.borrow_mut().push(Read(addr, value));
+            value
+        }
+
+        fn get_hl(&self, high: u8, low: u8) -> u8 {
+            self.get(u16::from_le_bytes([low, high]))
+        }
+
+        fn put(&mut self, addr: u16, value: u8) {
+            self.log.borrow_mut().push(Write(addr, value));
+            self.ram.put(addr, value);
+        }
+
+        fn put_hl(&mut self, high: u8, low: u8, value: u8) {
+            self.put(u16::from_le_bytes([low, high]), value);
+        }
+    }
+
+    struct Harness {
+        cpu: CPU6502,
+        bus: TestBus,
+    }
+
+    impl Harness {
+        fn new(program: &[u8]) -> Self {
+            let mut ram = RAM::<0x10000>::new(None);
+            for (offset, byte) in program.iter().enumerate() {
+                ram.put(START + offset as u16, *byte);
+            }
+
+            let mut cpu = CPU6502::new();
+            cpu.registers.pc = START;
+            Self { cpu, bus: TestBus { ram, log: RefCell::new(Vec::new()) } }
+        }
+
+        /// Build a harness for an instruction whose operand resolves to `value`, either as the immediate byte
+        /// or stored at `address(mode)`.
+        fn with_operand(opcode: u8, mode: Mode, value: u8) -> Self {
+            let mut harness = match mode {
+                Immediate => Self::new(&[opcode, value]),
+                ZeroPage | ZeroPageX | ZeroPageY | IndirectX | IndirectY => Self::new(&[opcode, 0x40]),
+                Absolute => Self::new(&[opcode, 0x34, 0x12]),
+                AbsoluteX | AbsoluteY => Self::new(&[opcode, 0x30, 0x12]),
+            };
+
+            match mode {
+                ZeroPageX => harness.cpu.registers.x = 0x02,
+                ZeroPageY => harness.cpu.registers.y = 0x02,
+                AbsoluteX => harness.cpu.registers.x = 0x04,
+                AbsoluteY => harness.cpu.registers.y = 0x04,
+                IndirectX => {
+                    harness.cpu.registers.x = 0x02;
+                    harness.bus.put(0x0042, 0x34);
+                    harness.bus.put(0x0043, 0x12);
+                },
+                IndirectY => {
+                    harness.cpu.registers.y = 0x04;
+                    harness.bus.put(0x0040, 0x30);
+                    harness.bus.put(0x0041, 0x12);
+                },
+                _ => (),
+            }
+
+            if let Some(addr) = address(mode) {
+                harness.bus.put(addr, value);
+            }
+            harness
+        }
+
+        /// Like with_operand, but the index carries the effective address from $12FF into $1300.
+        fn crossing_page(opcode: u8, mode: Mode, value: u8) -> Self {
+            let mut harness = match mode {
+                AbsoluteX | AbsoluteY => Self::new(&[opcode, 0xff, 0x12]),
+                IndirectY => Self::new(&[opcode, 0x40]),
+                _ => panic!("{mode:?} can't cross a page"),
+            };
+
+            match mode {
+                AbsoluteX => harness.cpu.registers.x = 0x01,
+                AbsoluteY => harness.cpu.registers.y = 0x01,
+                _ => {
+                    harness.cpu.registers.y = 0x01;
+                    harness.bus.put(0x0040, 0xff);
+                    harness.bus.put(0x0041, 0x12);
+                },
+            }
+
+            harness.bus.put(0x1300, value);
+            harness
+        }
+
+        /// Swap the CPU for another variant with the same registers.
+        fn variant(mut self, variant: Variant) -> Self {
+            let registers = self.cpu.registers;
+            self.cpu = CPU6502::with_variant(variant);
+            self.cpu.registers = registers;
+            self
+        }
+
+        fn cmos(self) -> Self {
+            self.variant(Variant::Wdc65C02)
+        }
+
+        /// Run a single instruction and return the number of cycles it took. The bus accesses it made are left
+        /// in the bus log.
+        fn step(&mut self) -> usize {
+            self.bus.log.borrow_mut().clear();
+            let mut cycles = 0;
+            loop {
+                self.cpu.tick(&mut self.bus);
+                cycles += 1;
+                if self.cpu.instruction.is_none() {
+                    return cycles;
+                }
+            }
+        }
+
+        fn accesses(&self) -> Vec<BusAccess> {
+            self.bus.log.borrow().clone()
+        }
+
+        fn flag(&self, flag: Flags) -> bool {
+            self.cpu.registers.status.flag(flag)
+        }
+    }
+
+    fn address(mode: Mode) -> Option<u16> {
+        match mode {
+            Immediate => None,
+            ZeroPage => Some(0x0040),
+            ZeroPageX | ZeroPageY => Some(0x0042),
+            _ => Some(0x1234),
+        }
+    }
+
+    #[test]
+    fn bus_implied() {
+        let mut harness = Harness::new(&[0xea, 0xe8]);
+        harness.step();
+        assert_eq!(harness.accesses(), vec![Read(0x0200, 0xea), Read(0x0201, 0xe8)]);
+        assert_eq!(harness.cpu.registers.pc, 0x0201);
+    }
+
+    #[test]
+    fn bus_immediate() {
+        let mut harness = Harness::new(&[0xa9, 0x42]);
+        harness.step();
+        assert_eq!(harness.accesses(), vec![Read(0x0200, 0xa9), Read(0x0201, 0x42)]);
+    }
+
+    #[test]
+    fn bus_zero_page_indexed() {
+        let mut harness = Harness::with_operand(0xb5, ZeroPageX, 0x99);
+        harness.step();
+        assert_eq!(harness.accesses(), vec![Read(0x0200, 0xb5), Read(0x0201, 0x40), Read(0x0040, 0x00), Read(0x0042, 0x99)]);
+    }
+
+    #[test]
+    fn bus_absolute_indexed_write() {
+        let mut harness = Harness::new(&[0x9d, 0xff, 0x12]);
+        harness.cpu.registers.a = 0x42;
+        harness.cpu.registers.x = 0x01;
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0x9d), Read(0x0201, 0xff), Read(0x0202, 0x12), Read(0x1200, 0x00), Write(0x1300, 0x42),
+        ]);
+        assert_eq!((harness.cpu.registers.adh, harness.cpu.registers.adl, harness.cpu.registers.data), (0x13, 0x00, 0x42));
+    }
+
+    #[test]
+    fn bus_read_modify_write() {
+        let mut harness = Harness::with_operand(0xe6, ZeroPage, 0x05);
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0xe6), Read(0x0201, 0x40), Read(0x0040, 0x05), Write(0x0040, 0x05), Write(0x0040, 0x06),
+        ]);
+
+        let mut harness = Harness::new(&[0xfe, 0xff, 0x12]);
+        harness.cpu.registers.x = 0x01;
+        harness.bus.put(0x1300, 0x05);
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0xfe), Read(0x0201, 0xff), Read(0x0202, 0x12), Read(0x1200, 0x00),
+            Read(0x1300, 0x05), Write(0x1300, 0x05), Write(0x1300, 0x06),
+        ]);
+    }
+
+    #[test]
+    fn bus_indexed_indirect() {
+        let mut harness = Harness::with_operand(0xa1, IndirectX, 0x99);
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0xa1), Read(0x0201, 0x40), Read(0x0040, 0x00), Read(0x0042, 0x34), Read(0x0043, 0x12),
+            Read(0x1234, 0x99),
+        ]);
+    }
+
+    #[test]
+    fn bus_indirect_indexed_write() {
+        let mut harness = Harness::new(&[0x91, 0x40]);
+        harness.bus.put(0x0040, 0xff);
+        harness.bus.put(0x0041, 0x12);
+        harness.cpu.registers.a = 0x42;
+        harness.cpu.registers.y = 0x01;
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0x91), Read(0x0201, 0x40), Read(0x0040, 0xff), Read(0x0041, 0x12), Read(0x1200, 0x00),
+            Write(0x1300, 0x42),
+        ]);
+    }
+
+    #[test]
+    fn bus_stack() {
+        let mut harness = Harness::new(&[0x48, 0x68]);
+        harness.cpu.registers.a = 0x42;
+        harness.cpu.registers.stack = 0xff;
+
+        harness.step();
+        assert_eq!(harness.accesses(), vec![Read(0x0200, 0x48), Read(0x0201, 0x68), Write(0x01ff, 0x42)]);
+
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0201, 0x68), Read(0x0202, 0x00), Read(0x01fe, 0x00), Read(0x01ff, 0x42),
+        ]);
+    }
+
+    #[test]
+    fn bus_jsr_rts() {
+        let mut harness = Harness::new(&[0x20, 0x10, 0x02]);
+        harness.bus.put(0x0210, 0x60);
+        harness.cpu.registers.stack = 0xff;
+
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0x20), Read(0x0201, 0x10), Read(0x01ff, 0x00), Write(0x01ff, 0x02), Write(0x01fe, 0x02),
+            Read(0x0202, 0x02),
+        ]);
+
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0210, 0x60), Read(0x0211, 0x00), Read(0x01fd, 0x00), Read(0x01fe, 0x02), Read(0x01ff, 0x02),
+            Read(0x0202, 0x02),
+        ]);
+        assert_eq!(harness.cpu.registers.pc, 0x0203);
+    }
+
+    #[test]
+    fn bus_brk_rti() {
+        let mut harness = Harness::new(&[0x00, 0xff]);
+        harness.cpu.registers.stack = 0xff;
+        harness.bus.put(0xfffe, 0x10);
+        harness.bus.put(0xffff, 0x02);
+        harness.bus.put(0x0210, 0x40);
+
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0x00), Read(0x0201, 0xff), Write(0x01ff, 0x02), Write(0x01fe, 0x02), Write(0x01fd, 0x30),
+            Read(0xfffe, 0x10), Read(0xffff, 0x02),
+        ]);
+
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0210, 0x40), Read(0x0211, 0x00), Read(0x01fc, 0x00), Read(0x01fd, 0x30), Read(0x01fe, 0x02),
+            Read(0x01ff, 0x02),
+        ]);
+    }
+
+    #[test]
+    fn bus_jmp_indirect() {
+        let mut harness = Harness::new(&[0x6c, 0xff, 0x30]);
+        harness.bus.put(0x30ff, 0x34);
+        harness.bus.put(0x3000, 0x12);
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0x6c), Read(0x0201, 0xff), Read(0x0202, 0x30), Read(0x30ff, 0x34), Read(0x3000, 0x12),
+        ]);
+        assert_eq!(harness.cpu.registers.pc, 0x1234);
+    }
+
+    // The addressing modes on their own, run with stand-in operations instead of any real opcode's
+
+    fn stand_in_read(reg: &mut Registers, value: u8) {
+        reg.a = value;
+    }
+
+    fn stand_in_write(reg: &mut Registers) -> u8 {
+        reg.a
+    }
+
+    fn stand_in_modify(_reg: &mut Registers, value: u8) -> u8 {
+        value.wrapping_add(1)
+    }
+
+    /// The accesses an operation makes once its mode has worked out an address.
+    type Tail = fn(u16) -> Vec<BusAccess>;
+
+    /// A read, write and modify access, each with the accesses it makes once its mode has worked out an address
+    /// holding $05, with $42 in A.
+    fn stand_in_accesses() -> [(Access, Tail); 3] {
+        [
+            (Access::Read(stand_in_read), |addr| vec![Read(addr, 0x05)]),
+            (Access::Write(stand_in_write), |addr| vec![Write(addr, 0x42)]),
+            (Access::Modify(stand_in_modify), |addr| vec![Read(addr, 0x05), Write(addr, 0x05), Write(addr, 0x06)]),
+        ]
+    }
+
+    /// Run an operation as though its opcode had just been fetched from START, with the operand bytes after it
+    /// and $42 in A. Returns the accesses it made after the fetch.
+    fn run_operation(operation: Operation, variant: Variant, operand: &[u8], setup: impl FnOnce(&mut Harness))
+        -> Vec<BusAccess> {
+        let mut harness = Harness::new(&[&[0x00], operand].concat());
+        harness.cpu.registers.a = 0x42;
+        harness.cpu.registers.pc = START + 1;
+        setup(&mut harness);
+        harness.bus.log.borrow_mut().clear();
+
+        let mut instruction = Instruction::new(operation, variant, 0);
+        let mut step = 0;
+        while instruction.cycle(step, &mut harness.cpu.registers, &mut harness.bus) == InstructionState::Continue {
+            step += 1;
+        }
+        assert_eq!(harness.cpu.registers.pc, START + 1 + operand.len() as u16);
+        harness.accesses()
+    }
+
+    #[test]
+    fn mode_zero_page() {
+        for (access, tail) in stand_in_accesses() {
+            let accesses = run_operation(Operation::ZeroPage(access), Variant::Mos6502, &[0x40], |harness| {
+                harness.bus.put(0x0040, 0x05);
+            });
+            assert_eq!(accesses, [vec![Read(0x0201, 0x40)], tail(0x0040)].concat());
+        }
+    }
+
+    #[test]
+    fn mode_zero_page_indexed() {
+        // The index wraps around within page zero
+        for (access, tail) in stand_in_accesses() {
+            for index in [Index::X, Index::Y] {
+                let operation = Operation::ZeroPageIndexed(access, index);
+                let accesses = run_operation(operation, Variant::Mos6502, &[0x40], |harness| {
+                    harness.cpu.registers.x = 0xc2;
+                    harness.cpu.registers.y = 0xc2;
+                    harness.bus.put(0x0002, 0x05);
+                });
+                assert_eq!(accesses, [vec![Read(0x0201, 0x40), Read(0x0040, 0x00)], tail(0x0002)].concat());
+            }
+        }
+    }
+
+    #[test]
+    fn mode_absolute() {
+        for (access, tail) in stand_in_accesses() {
+            let accesses = run_operation(Operation::Absolute(access), Variant::Mos6502, &[0x34, 0x12], |harness| {
+                harness.bus.put(0x1234, 0x05);
+            });
+            assert_eq!(accesses, [vec![Read(0x0201, 0x34), Read(0x0202, 0x12)], tail(0x1234)].concat());
+        }
+    }
+
+    #[test]
+    fn mode_absolute_indexed() {
+        for (access, tail) in stand_in_accesses() {
+            for index in [Index::X, Index::Y] {
+                for (fixup, base, offset) in [
+                    (Fixup::WhenCrossing, 0x1230, 0x04),
+                    (Fixup::WhenCrossing, 0x12ff, 0x35),
+                    (Fixup::Always, 0x1230, 0x04),
+                    (Fixup::Always, 0x12ff, 0x35),
+                ] {
+                    let operation = Operation::AbsoluteIndexed(access, index, fixup);
+                    let [low, high] = u16::to_le_bytes(base);
+                    let accesses = run_operation(operation, Variant::Mos6502, &[low, high], |harness| {
+                        harness.cpu.registers.x = offset;
+                        harness.cpu.registers.y = offset;
+                        harness.bus.put(0x1234, 0x05);
+                        harness.bus.put(0x1334, 0x05);
+                    });
+
+                    let address = base + offset as u16;
+                    let uncarried = (base & 0xff00) | (address & 0x00ff);
+                    let mut expected = vec![Read(0x0201, low), Read(0x0202, high)];
+                    if matches!(fixup, Fixup::Always) || address != uncarried {
+                        expected.push(Read(uncarried, if uncarried == 0x1234 { 0x05 } else { 0x00 }));
+                    }
+                    assert_eq!(accesses, [expected, tail(address)].concat());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mode_indexed_indirect() {
+        for (access, tail) in stand_in_accesses() {
+            let accesses = run_operation(Operation::IndexedIndirect(access), Variant::Mos6502, &[0x40], |harness| {
+                harness.cpu.registers.x = 0x02;
+                harness.bus.put(0x0042, 0x34);
+                harness.bus.put(0x0043, 0x12);
+                harness.bus.put(0x1234, 0x05);
+            });
+            assert_eq!(accesses, [
+                vec![Read(0x0201, 0x40), Read(0x0040, 0x00), Read(0x0042, 0x34), Read(0x0043, 0x12)],
+                tail(0x1234),
+            ].concat());
+        }
+    }
+
+    #[test]
+    fn mode_indirect_indexed() {
+        for (access, tail) in stand_in_accesses() {
+            for (pointer, offset, address) in [(0x1230u16, 0x04, 0x1234u16), (0x12ff, 0x35, 0x1334)] {
+                let [low, high] = pointer.to_le_bytes();
+                let accesses = run_operation(Operation::IndirectIndexed(access), Variant::Mos6502, &[0x40], |harness| {
+                    harness.cpu.registers.y = offset;
+                    harness.bus.put(0x0040, low);
+                    harness.bus.put(0x0041, high);
+                    harness.bus.put(address, 0x05);
+                });
+
+                // Only reads that stay on the page skip the cycle fixing up the address
+                let mut expected = vec![Read(0x0201, 0x40), Read(0x0040, low), Read(0x0041, high)];
+                let uncarried = (pointer & 0xff00) | (address & 0x00ff);
+                if !matches!(access, Access::Read(_)) || address != uncarried {
+                    expected.push(Read(uncarried, if uncarried == address { 0x05 } else { 0x00 }));
+                }
+                assert_eq!(accesses, [expected, tail(address)].concat());
+            }
+        }
+    }
+
+    #[test]
+    fn mode_zero_page_indirect() {
+        // Only the 65C02 has (zp), and it has no read-modify-write instructions that use it
+        for (access, tail) in stand_in_accesses().into_iter().take(2) {
+            let operation = Operation::ZeroPageIndirect(access);
+            let accesses = run_operation(operation, Variant::Wdc65C02, &[0x40], |harness| {
+                harness.bus.put(0x0040, 0x34);
+                harness.bus.put(0x0041, 0x12);
+                harness.bus.put(0x1234, 0x05);
+            });
+            assert_eq!(accesses, [
+                vec![Read(0x0201, 0x40), Read(0x0040, 0x34), Read(0x0041, 0x12)],
+                tail(0x1234),
+            ].concat());
+        }
+    }
+
+    #[test]
+    fn adc() {
+        for (opcode, mode, cycles) in [
+            (0x69, Immediate, 2), (0x65, ZeroPage, 3), (0x75, ZeroPageX, 4), (0x6d, Absolute, 4),
+            (0x7d, AbsoluteX, 4), (0x79, AbsoluteY, 4), (0x61, IndirectX, 6), (0x71, IndirectY, 5),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x50);
+            harness.cpu.registers.a = 0x30;
+            harness.cpu.registers.status.set_flag(StatusRegister::CARRY);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0x81, "{opcode:#04x}");
+            assert!(!harness.flag(StatusRegister::CARRY));
+            assert!(harness.flag(StatusRegister::OVERFLOW));
+            assert!(harness.flag(StatusRegister::NEGATIVE));
+            assert!(!harness.flag(StatusRegister::ZERO));
+        }
+    }
+
+    #[test]
+    fn adc_carry_out() {
+        let mut harness = Harness::with_operand(0x69, Immediate, 0xff);
+        harness.cpu.registers.a = 0x01;
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0x00);
+        assert!(harness.flag(StatusRegister::CARRY));
+        assert!(harness.flag(StatusRegister::ZERO));
+        assert!(!harness.flag(StatusRegister::OVERFLOW));
+    }
+
+    #[test]
+    fn sbc() {
+        for (opcode, mode, cycles) in [
+            (0xe9, Immediate, 2), (0xe5, ZeroPage, 3), (0xf5, ZeroPageX, 4), (0xed, Absolute, 4),
+            (0xfd, AbsoluteX, 4), (0xf9, AbsoluteY, 4), (0xe1, IndirectX, 6), (0xf1, IndirectY, 5),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x01);
+            harness.cpu.registers.a = 0x80;
+            harness.cpu.registers.status.set_flag(StatusRegister::CARRY);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0x7f, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::CARRY));
+            assert!(harness.flag(StatusRegister::OVERFLOW));
+            assert!(!harness.flag(StatusRegister::NEGATIVE));
+        }
+    }
+
+    #[test]
+    fn sbc_borrow() {
+        let mut harness = Harness::with_operand(0xe9, Immediate, 0x01);
+        harness.cpu.registers.a = 0x00;
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0xfe);
+        assert!(!harness.flag(StatusRegister::CARRY));
+        assert!(harness.flag(StatusRegister::NEGATIVE));
+    }
+
+    /// Accumulator followed by the N, V, Z and C flags
+    type DecimalResult = (u8, bool, bool, bool, bool);
+
+    /// Run an operation in decimal mode straight against the registers.
+    fn decimal(op: fn(&mut Registers, u8), a: u8, value: u8, carry: bool) -> DecimalResult {
+        let mut reg = CPU6502::new().registers;
+        reg.a = a;
+        reg.status = StatusRegister::new(0);
+        reg.status.set_decimal_mode(true);
+        reg.status.set_carry(carry);
+        op(&mut reg, value);
+        (
+            reg.a,
+            reg.status.flag(StatusRegister::NEGATIVE),
+            reg.status.flag(StatusRegister::OVERFLOW),
+            reg.status.flag(StatusRegister::ZERO),
+            reg.status.flag(StatusRegister::CARRY),
+        )
+    }
+
+    fn to_bcd(number: i32) -> u8 {
+        (((number / 10) << 4) | (number % 10)) as u8
+    }
+
+    /// Every pair of valid BCD bytes, with and without carry, along with the numbers they stand for.
+    fn valid_bcd() -> impl Iterator<Item = (u8, u8, bool, i32, i32)> {
+        (0..100).flat_map(|a| {
+            (0..100).flat_map(move |b| [false, true].map(|carry| (to_bcd(a), to_bcd(b), carry, a, b)))
+        })
+    }
+
+    #[test]
+    fn adc_decimal_valid_bcd() {
+        // The result and carry are plain decimal addition. The NMOS part takes Z from the binary sum, and the
+        // 65C02 sets N and Z from the result.
+        for (a, value, carry, x, y) in valid_bcd() {
+            let sum = x + y + carry as i32;
+            let result = to_bcd(sum % 100);
+            let binary_zero = (a as u16 + value as u16 + carry as u16) as u8 == 0;
+
+            let (nmos, _, _, zero, c) = decimal(super::adc, a, value, carry);
+            assert_eq!((nmos, zero, c), (result, binary_zero, sum >= 100), "{a:#04x}, {value:#04x}, carry {carry}");
+            let (cmos, negative, _, zero, c) = decimal(super::adc_cmos, a, value, carry);
+            assert_eq!(
+                (cmos, negative, zero, c),
+                (result, result & 0x80 != 0, result == 0, sum >= 100),
+                "65C02 {a:#04x}, {value:#04x}, carry {carry}"
+            );
+        }
+    }
+
+    #[test]
+    fn sbc_decimal_valid_bcd() {
+        // The result and carry are plain decimal subtraction. The NMOS part sets N, V and Z as a binary
+        // subtraction would, and the 65C02 sets V that way but N and Z from the result.
+        for (a, value, carry, x, y) in valid_bcd() {
+            let borrow = !carry as i32;
+            let difference = x - y - borrow;
+            let result = to_bcd(difference.rem_euclid(100));
+            let binary = a as i32 - value as i32 - borrow;
+            let overflow = !(-128..=127).contains(&(a as i8 as i32 - value as i8 as i32 - borrow));
+
+            assert_eq!(
+                decimal(super::sbc, a, value, carry),
+                (result, binary & 0x80 != 0, overflow, binary & 0xFF == 0, difference >= 0),
+                "{a:#04x}, {value:#04x}, carry {carry}"
+            );
+            assert_eq!(
+                decimal(super::sbc_cmos, a, value, carry),
+                (result, result & 0x80 != 0, overflow, result == 0, difference >= 0),
+                "65C02 {a:#04x}, {value:#04x}, carry {carry}"
+            );
+        }
+    }
+
+    #[test]
+    fn adc_decimal_published() {
+        // Bruce Clark's examples from http://www.6502.org/tutorials/decimal_mode.html, invalid digits included.
+        // The 65C02 gets the same result and carry.
+        for (a, value, carry, expected) in [
+            (0x79, 0x00, true, (0x80, true, true, false, false)),
+            (0x24, 0x56, false, (0x80, true, true, false, false)),
+            (0x93, 0x82, false, (0x75, false, true, false, true)),
+            (0x89, 0x76, false, (0x65, false, false, false, true)),
+            (0x89, 0x76, true, (0x66, false, false, true, true)),
+            (0x99, 0x01, false, (0x00, true, false, false, true)),
+            (0x80, 0xf0, false, (0xd0, false, true, false, true)),
+            (0x80, 0xfa, false, (0xe0, true, false, false, true)),
+            (0x2f, 0x4f, false, (0x74, false, false, false, false)),
+            (0x6f, 0x00, true, (0x76, false, false, false, false)),
+        ] {
+            assert_eq!(decimal(super::adc, a, value, carry), expected, "{a:#04x}, {value:#04x}, carry {carry}");
+            let (result, _, _, _, c) = decimal(super::adc_cmos, a, value, carry);
+            assert_eq!((result, c), (expected.0, expected.4), "65C02 {a:#04x}, {value:#04x}, carry {carry}");
+        }
+    }
+
+    #[test]
+    fn sbc_decimal_published() {
+        // Bruce Clark's examples, invalid digits included, with the result and carry
+        for (a, value, carry, result, c) in [
+            (0x00, 0x00, false, 0x99, false),
+            (0x00, 0x01, true, 0x99, false),
+            (0x0a, 0x00, true, 0x0a, true),
+            (0x0b, 0x00, false, 0x0a, true),
+            (0x9a, 0x00, true, 0x9a, true),
+            (0x9b, 0x00, false, 0x9a, true),
+        ] {
+            let (actual, _, _, _, actual_carry) = decimal(super::sbc, a, value, carry);
+            assert_eq!((actual, actual_carry), (result, c), "{a:#04x}, {value:#04x}, carry {carry}");
+        }
+    }
+
+    #[test]
+    fn adc_decimal() {
+        for (opcode, mode, cycles) in [(0x69, Immediate, 2), (0x6d, Absolute, 4), (0x71, IndirectY, 5)] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x01);
+            harness.cpu.registers.a = 0x99;
+            harness.cpu.registers.status.set_flag(StatusRegister::DECIMAL_MODE);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0x00, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::CARRY));
+        }
+    }
+
+    #[test]
+    fn sbc_decimal() {
+        for (opcode, mode, cycles) in [(0xe9, Immediate, 2), (0xed, Absolute, 4), (0xf1, IndirectY, 5)] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x01);
+            harness.cpu.registers.a = 0x10;
+            harness.cpu.registers.status.set_flag(StatusRegister::DECIMAL_MODE);
+            harness.cpu.registers.status.set_flag(StatusRegister::CARRY);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0x09, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::CARRY));
+        }
+    }
+
+    #[test]
+    fn and() {
+        for (opcode, mode, cycles) in [
+            (0x29, Immediate, 2), (0x25, ZeroPage, 3), (0x35, ZeroPageX, 4), (0x2d, Absolute, 4),
+            (0x3d, AbsoluteX, 4), (0x39, AbsoluteY, 4), (0x21, IndirectX, 6), (0x31, IndirectY, 5),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0b1100_0011);
+            harness.cpu.registers.a = 0b1010_1010;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0b1000_0010, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::NEGATIVE));
+        }
+    }
+
+    #[test]
+    fn ora() {
+        for (opcode, mode, cycles) in [
+            (0x09, Immediate, 2), (0x05, ZeroPage, 3), (0x15, ZeroPageX, 4), (0x0d, Absolute, 4),
+            (0x1d, AbsoluteX, 4), (0x19, AbsoluteY, 4), (0x01, IndirectX, 6), (0x11, IndirectY, 5),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0b0000_0011);
+            harness.cpu.registers.a = 0b0010_1000;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0b0010_1011, "{opcode:#04x}");
+            assert!(!harness.flag(StatusRegister::NEGATIVE));
+            assert!(!harness.flag(StatusRegister::ZERO));
+        }
+    }
+
+    #[test]
+    fn eor() {
+        for (opcode, mode, cycles) in [
+            (0x49, Immediate, 2), (0x45, ZeroPage, 3), (0x55, ZeroPageX, 4), (0x4d, Absolute, 4),
+            (0x5d, AbsoluteX, 4), (0x59, AbsoluteY, 4), (0x41, IndirectX, 6), (0x51, IndirectY, 5),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0b1010_1010);
+            harness.cpu.registers.a = 0b1010_1010;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::ZERO));
+        }
+    }
+
+    #[test]
+    fn bit() {
+        for (opcode, mode, cycles) in [(0x24, ZeroPage, 3), (0x2c, Absolute, 4)] {
+            let mut harness = Harness::with_operand(opcode, mode, 0b1100_0000);
+            harness.cpu.registers.a = 0b0011_1111;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0b0011_1111);
+            assert!(harness.flag(StatusRegister::ZERO));
+            assert!(harness.flag(StatusRegister::NEGATIVE));
+            assert!(harness.flag(StatusRegister::OVERFLOW));
+        }
+    }
+
+    #[test]
+    fn cmp() {
+        for (opcode, mode, cycles) in [
+            (0xc9, Immediate, 2), (0xc5, ZeroPage, 3), (0xd5, ZeroPageX, 4), (0xcd, Absolute, 4),
+            (0xdd, AbsoluteX, 4), (0xd9, AbsoluteY, 4), (0xc1, IndirectX, 6), (0xd1, IndirectY, 5),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x40);
+            harness.cpu.registers.a = 0x40;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::CARRY));
+            assert!(harness.flag(StatusRegister::ZERO));
+            assert!(!harness.flag(StatusRegister::NEGATIVE));
+        }
+    }
+
+    #[test]
+    fn cmp_less_than() {
+        let mut harness = Harness::with_operand(0xc9, Immediate, 0x41);
+        harness.cpu.registers.a = 0x40;
+        harness.step();
+        assert!(!harness.flag(StatusRegister::CARRY));
+        assert!(!harness.flag(StatusRegister::ZERO));
+        assert!(harness.flag(StatusRegister::NEGATIVE));
+    }
+
+    #[test]
+    fn cpx() {
+        for (opcode, mode, cycles) in [(0xe0, Immediate, 2), (0xe4, ZeroPage, 3), (0xec, Absolute, 4)] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x10);
+            harness.cpu.registers.x = 0x20;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::CARRY));
+            assert!(!harness.flag(StatusRegister::ZERO));
+        }
+    }
+
+    #[test]
+    fn cpy() {
+        for (opcode, mode, cycles) in [(0xc0, Immediate, 2), (0xc4, ZeroPage, 3), (0xcc, Absolute, 4)] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x30);
+            harness.cpu.registers.y = 0x20;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert!(!harness.flag(StatusRegister::CARRY));
+            assert!(harness.flag(StatusRegister::NEGATIVE));
+        }
+    }
+
+    #[test]
+    fn lda() {
+        for (opcode, mode, cycles) in [
+            (0xa9, Immediate, 2), (0xa5, ZeroPage, 3), (0xb5, ZeroPageX, 4), (0xad, Absolute, 4),
+            (0xbd, AbsoluteX, 4), (0xb9, AbsoluteY, 4), (0xa1, IndirectX, 6), (0xb1, IndirectY, 5),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x80);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0x80, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::NEGATIVE));
+            assert!(!harness.flag(StatusRegister::ZERO));
+        }
+    }
+
+    #[test]
+    fn ldx() {
+        for (opcode, mode, cycles) in [
+            (0xa2, Immediate, 2), (0xa6, ZeroPage, 3), (0xb6, ZeroPageY, 4), (0xae, Absolute, 4), (0xbe, AbsoluteY, 4),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x00);
+            harness.cpu.registers.x = 0xff;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.x, 0x00, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::ZERO));
+        }
+    }
+
+    #[test]
+    fn ldy() {
+        for (opcode, mode, cycles) in [
+            (0xa0, Immediate, 2), (0xa4, ZeroPage, 3), (0xb4, ZeroPageX, 4), (0xac, Absolute, 4), (0xbc, AbsoluteX, 4),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x7f);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.y, 0x7f, "{opcode:#04x}");
+            assert!(!harness.flag(StatusRegister::NEGATIVE));
+            assert!(!harness.flag(StatusRegister::ZERO));
+        }
+    }
+
+    #[test]
+    fn sta() {
+        for (opcode, mode, cycles) in [
+            (0x85, ZeroPage, 3), (0x95, ZeroPageX, 4), (0x8d, Absolute, 4), (0x9d, AbsoluteX, 5),
+            (0x99, AbsoluteY, 5), (0x81, IndirectX, 6), (0x91, IndirectY, 6),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x00);
+            harness.cpu.registers.a = 0x42;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0x42, "{opcode:#04x}");
+        }
+    }
+
+    #[test]
+    fn stx() {
+        for (opcode, mode, cycles) in [(0x86, ZeroPage, 3), (0x96, ZeroPageY, 4), (0x8e, Absolute, 4)] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x00);
+            harness.cpu.registers.x = 0x42;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0x42, "{opcode:#04x}");
+        }
+    }
+
+    #[test]
+    fn sty() {
+        for (opcode, mode, cycles) in [(0x84, ZeroPage, 3), (0x94, ZeroPageX, 4), (0x8c, Absolute, 4)] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x00);
+            harness.cpu.registers.y = 0x42;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0x42, "{opcode:#04x}");
+        }
+    }
+
+    #[test]
+    fn asl() {
+        for (opcode, mode, cycles) in [
+            (0x06, ZeroPage, 5), (0x16, ZeroPageX, 6), (0x0e, Absolute, 6), (0x1e, AbsoluteX, 7),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0b1000_0001);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0b0000_0010, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::CARRY));
+        }
+
+        let mut harness = Harness::new(&[0x0a]);
+        harness.cpu.registers.a = 0b0100_0000;
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.a, 0b1000_0000);
+        assert!(!harness.flag(StatusRegister::CARRY));
+        assert!(harness.flag(StatusRegister::NEGATIVE));
+    }
+
+    #[test]
+    fn lsr() {
+        for (opcode, mode, cycles) in [
+            (0x46, ZeroPage, 5), (0x56, ZeroPageX, 6), (0x4e, Absolute, 6), (0x5e, AbsoluteX, 7),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0b0000_0011);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0b0000_0001, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::CARRY));
+        }
+
+        let mut harness = Harness::new(&[0x4a]);
+        harness.cpu.registers.a = 0b0000_0001;
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.a, 0);
+        assert!(harness.flag(StatusRegister::CARRY));
+        assert!(harness.flag(StatusRegister::ZERO));
+    }
+
+    #[test]
+    fn rol() {
+        for (opcode, mode, cycles) in [
+            (0x26, ZeroPage, 5), (0x36, ZeroPageX, 6), (0x2e, Absolute, 6), (0x3e, AbsoluteX, 7),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0b1000_0000);
+            harness.cpu.registers.status.set_flag(StatusRegister::CARRY);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0b0000_0001, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::CARRY));
+        }
+
+        let mut harness = Harness::new(&[0x2a]);
+        harness.cpu.registers.a = 0b0100_0000;
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.a, 0b1000_0000);
+        assert!(!harness.flag(StatusRegister::CARRY));
+    }
+
+    #[test]
+    fn ror() {
+        for (opcode, mode, cycles) in [
+            (0x66, ZeroPage, 5), (0x76, ZeroPageX, 6), (0x6e, Absolute, 6), (0x7e, AbsoluteX, 7),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0b0000_0001);
+            harness.cpu.registers.status.set_flag(StatusRegister::CARRY);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0b1000_0000, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::CARRY));
+            assert!(harness.flag(StatusRegister::NEGATIVE));
+        }
+
+        let mut harness = Harness::new(&[0x6a]);
+        harness.cpu.registers.a = 0b0000_0010;
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.a, 0b0000_0001);
+        assert!(!harness.flag(StatusRegister::CARRY));
+    }
+
+    #[test]
+    fn inc() {
+        for (opcode, mode, cycles) in [
+            (0xe6, ZeroPage, 5), (0xf6, ZeroPageX, 6), (0xee, Absolute, 6), (0xfe, AbsoluteX, 7),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0xff);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0x00, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::ZERO));
+        }
+    }
+
+    #[test]
+    fn dec() {
+        for (opcode, mode, cycles) in [
+            (0xc6, ZeroPage, 5), (0xd6, ZeroPageX, 6), (0xce, Absolute, 6), (0xde, AbsoluteX, 7),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x00);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0xff, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::NEGATIVE));
+        }
+    }
+
+    #[test]
+    fn increment_decrement_registers() {
+        let mut harness = Harness::new(&[0xe8, 0xc8, 0xca, 0xca, 0x88]);
+        harness.cpu.registers.x = 0xff;
+        harness.cpu.registers.y = 0x7f;
+
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.x, 0x00);
+        assert!(harness.flag(StatusRegister::ZERO));
+
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.y, 0x80);
+        assert!(harness.flag(StatusRegister::NEGATIVE));
+
+        harness.step();
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.x, 0xfe);
+
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.y, 0x7f);
+        assert!(!harness.flag(StatusRegister::NEGATIVE));
+    }
+
+    #[test]
+    fn transfers() {
+        let mut harness = Harness::new(&[0xaa, 0xa8, 0xa9, 0x00, 0x8a, 0xa9, 0x00, 0x98]);
+        harness.cpu.registers.a = 0x80;
+
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.x, 0x80);
+        assert!(harness.flag(StatusRegister::NEGATIVE));
+
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.y, 0x80);
+
+        harness.step();
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.a, 0x80);
+
+        harness.step();
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.a, 0x80);
+    }
+
+    #[test]
+    fn flags() {
+        let mut harness = Harness::new(&[0x38, 0xf8, 0x78, 0x18, 0xd8, 0x58, 0xb8]);
+        harness.cpu.registers.status.set_flag(StatusRegister::OVERFLOW);
+
+        for flag in [StatusRegister::CARRY, StatusRegister::DECIMAL_MODE, StatusRegister::IRQ_DISABLE] {
+            assert_eq!(harness.step(), 2);
+            assert!(harness.flag(flag));
+        }
+
+        for flag in [
+            StatusRegister::CARRY, StatusRegister::DECIMAL_MODE, StatusRegister::IRQ_DISABLE, StatusRegister::OVERFLOW,
+        ] {
+            assert_eq!(harness.step(), 2);
+            assert!(!harness.flag(flag));
+        }
+    }
+
+    #[test]
+    fn nop() {
+        let mut harness = Harness::new(&[0xea, 0xea]);
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.pc, START + 2);
+    }
+
+    #[test]
+    fn branches() {
+        for (opcode, flag, branch_when_set) in [
+            (0x90, StatusRegister::CARRY, false),
+            (0xb0, StatusRegister::CARRY, true),
+            (0xd0, StatusRegister::ZERO, false),
+            (0xf0, StatusRegister::ZERO, true),
+            (0x10, StatusRegister::NEGATIVE, false),
+            (0x30, StatusRegister::NEGATIVE, true),
+            (0x50, StatusRegister::OVERFLOW, false),
+            (0x70, StatusRegister::OVERFLOW, true),
+        ] {
+            for set in [false, true] {
+                // Branch forward over the first LDA, or fall through onto it
+                let mut harness = Harness::new(&[opcode, 0x02, 0xa9, 0x01, 0xa9, 0x02]);
+                if set {
+                    harness.cpu.registers.status.set_flag(flag);
+                }
+                let cycles = if set == branch_when_set { 3 } else { 2 };
+                assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+                harness.step();
+                let expected = if set == branch_when_set { 0x02 } else { 0x01 };
+                assert_eq!(harness.cpu.registers.a, expected, "{opcode:#04x} with flag set: {set}");
+            }
+        }
+    }
+
+    #[test]
+    fn branch_timing() {
+        // (opcode, flags that make the branch taken)
+        let branches = [
+            (0x90, 0), (0xb0, StatusRegister::CARRY.bits()), (0xd0, 0), (0xf0, StatusRegister::ZERO.bits()),
+            (0x10, 0), (0x30, StatusRegister::NEGATIVE.bits()), (0x50, 0), (0x70, StatusRegister::OVERFLOW.bits()),
+        ];
+
+        for (opcode, taken_flags) in branches {
+            // (where the branch is, offset, taken, cycles, where it ends up)
+            for (pc, offset, taken, cycles, target) in [
+                (0x0200u16, 0x10u8, false, 2, 0x0202u16),
+                (0x0200, 0x10, true, 3, 0x0212),
+                (0x0200, 0xfe, true, 3, 0x0200),
+                (0x02f0, 0x10, true, 4, 0x0302),
+                (0x0200, 0x80, true, 4, 0x0182),
+            ] {
+                let mut harness = Harness::new(&[]);
+                harness.bus.put(pc, opcode);
+                harness.bus.put(pc + 1, offset);
+                harness.cpu.registers.pc = pc;
+                let flags = if taken { taken_flags } else { !taken_flags & 0b1100_0011 };
+                harness.cpu.registers.status = StatusRegister::new(flags);
+
+                assert_eq!(harness.step(), cycles, "{opcode:#04x} at {pc:#06x} with offset {offset:#04x}");
+                assert_eq!(harness.cpu.registers.pc, target, "{opcode:#04x} at {pc:#06x} with offset {offset:#04x}");
+            }
+        }
+    }
+
+    #[test]
+    fn bus_branch_page_crossing() {
+        let mut harness = Harness::new(&[]);
+        harness.bus.put(0x02f0, 0xd0);
+        harness.bus.put(0x02f1, 0x10);
+        harness.cpu.registers.pc = 0x02f0;
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x02f0, 0xd0), Read(0x02f1, 0x10), Read(0x02f2, 0x00), Read(0x0202, 0x00),
+        ]);
+    }
+
+    #[test]
+    fn page_crossing_timing() {
+        // (opcode, mode, cycles without crossing a page, cycles crossing one)
+        for (opcode, mode, same_page, crossing) in [
+            (0x7d, AbsoluteX, 4, 5), (0x79, AbsoluteY, 4, 5), (0x71, IndirectY, 5, 6),
+            (0x3d, AbsoluteX, 4, 5), (0x39, AbsoluteY, 4, 5), (0x31, IndirectY, 5, 6),
+            (0xdd, AbsoluteX, 4, 5), (0xd9, AbsoluteY, 4, 5), (0xd1, IndirectY, 5, 6),
+            (0x5d, AbsoluteX, 4, 5), (0x59, AbsoluteY, 4, 5), (0x51, IndirectY, 5, 6),
+            (0xbd, AbsoluteX, 4, 5), (0xb9, AbsoluteY, 4, 5), (0xb1, IndirectY, 5, 6),
+            (0xbe, AbsoluteY, 4, 5), (0xbc, AbsoluteX, 4, 5),
+            (0x1d, AbsoluteX, 4, 5), (0x19, AbsoluteY, 4, 5), (0x11, IndirectY, 5, 6),
+            (0xfd, AbsoluteX, 4, 5), (0xf9, AbsoluteY, 4, 5), (0xf1, IndirectY, 5, 6),
+            // Stores and read-modify-write instructions always take the extra cycle
+            (0x9d, AbsoluteX, 5, 5), (0x99, AbsoluteY, 5, 5), (0x91, IndirectY, 6, 6),
+            (0x1e, AbsoluteX, 7, 7), (0x3e, AbsoluteX, 7, 7), (0x5e, AbsoluteX, 7, 7), (0x7e, AbsoluteX, 7, 7),
+            (0xde, AbsoluteX, 7, 7), (0xfe, AbsoluteX, 7, 7),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x00);
+            assert_eq!(harness.step(), same_page, "{opcode:#04x} without crossing a page");
+
+            let mut harness = Harness::crossing_page(opcode, mode, 0x00);
+            assert_eq!(harness.step(), crossing, "{opcode:#04x} crossing a page");
+        }
+    }
+
+    #[test]
+    fn page_crossing_read() {
+        let mut harness = Harness::crossing_page(0xbd, AbsoluteX, 0x42);
+        assert_eq!(harness.step(), 5);
+        assert_eq!(harness.cpu.registers.a, 0x42);
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0xbd), Read(0x0201, 0xff), Read(0x0202, 0x12), Read(0x1200, 0x00), Read(0x1300, 0x42),
+        ]);
+
+        let mut harness = Harness::crossing_page(0xb1, IndirectY, 0x42);
+        assert_eq!(harness.step(), 6);
+        assert_eq!(harness.cpu.registers.a, 0x42);
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0xb1), Read(0x0201, 0x40), Read(0x0040, 0xff), Read(0x0041, 0x12), Read(0x1200, 0x00),
+            Read(0x1300, 0x42),
+        ]);
+    }
+
+    #[test]
+    fn branch_backwards() {
+        // BNE -4 lands back on the first NOP
+        let mut harness = Harness::new(&[0xea, 0xea, 0xd0, 0xfc]);
+        harness.cpu.registers.pc = START + 2;
+        harness.step();
+        assert_eq!(harness.cpu.registers.pc, START);
+    }
+
+    #[test]
+    fn pha_pla() {
+        let mut harness = Harness::new(&[0x48, 0xa9, 0x00, 0x68]);
+        harness.cpu.registers.a = 0x80;
+        harness.cpu.registers.stack = 0xff;
+
+        assert_eq!(harness.step(), 3);
+        assert_eq!(harness.bus.get(0x01ff), 0x80);
+        assert_eq!(harness.cpu.registers.stack, 0xfe);
+
+        harness.step();
+        assert!(harness.flag(StatusRegister::ZERO));
+
+        assert_eq!(harness.step(), 4);
+        assert_eq!(harness.cpu.registers.a, 0x80);
+        assert_eq!(harness.cpu.registers.stack, 0xff);
+        assert!(!harness.flag(StatusRegister::ZERO));
+        assert!(harness.flag(StatusRegister::NEGATIVE));
+    }
+
+    #[test]
+    fn stack_wraps_within_page_one() {
+        let mut harness = Harness::new(&[0x48, 0x68, 0x68]);
+        harness.cpu.registers.a = 0x42;
+        harness.cpu.registers.stack = 0x00;
+        harness.bus.put(0x0101, 0x24);
+
+        harness.step();
+        assert_eq!(harness.bus.get(0x0100), 0x42);
+        assert_eq!(harness.cpu.registers.stack, 0xff);
+
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0x42);
+        assert_eq!(harness.cpu.registers.stack, 0x00);
+
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0x24);
+        assert_eq!(harness.cpu.registers.stack, 0x01);
+    }
+
+    #[test]
+    fn php_sets_break_and_unused_bits() {
+        let mut harness = Harness::new(&[0x08]);
+        harness.cpu.registers.stack = 0xff;
+        harness.cpu.registers.status = StatusRegister::new((StatusRegister::CARRY | StatusRegister::NEGATIVE).bits());
+
+        assert_eq!(harness.step(), 3);
+        assert_eq!(harness.bus.get(0x01ff), 0b1011_0001);
+        assert_eq!(harness.cpu.registers.status.bits(), (StatusRegister::CARRY | StatusRegister::NEGATIVE).bits());
+    }
+
+    #[test]
+    fn plp_ignores_break_and_unused_bits() {
+        let mut harness = Harness::new(&[0x28]);
+        harness.cpu.registers.stack = 0xfe;
+        harness.bus.put(0x01ff, 0xff);
+
+        assert_eq!(harness.step(), 4);
+        assert_eq!(harness.cpu.registers.status.bits(), 0b1100_1111);
+        assert_eq!(harness.cpu.registers.stack, 0xff);
+    }
+
+    #[test]
+    fn tsx_txs() {
+        let mut harness = Harness::new(&[0xba, 0xa2, 0x00, 0x9a]);
+        harness.cpu.registers.stack = 0x80;
+
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.x, 0x80);
+        assert!(harness.flag(StatusRegister::NEGATIVE));
+
+        harness.step();
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.stack, 0x00);
+        // TXS doesn't touch the flags
+        assert!(harness.flag(StatusRegister::ZERO));
+    }
+
+    #[test]
+    fn jsr_rts() {
+        // JSR $0210; LDX #$01; ... $0210: LDA #$42; RTS
+        let mut harness = Harness::new(&[0x20, 0x10, 0x02, 0xa2, 0x01]);
+        harness.bus.put(0x0210, 0xa9);
+        harness.bus.put(0x0211, 0x42);
+        harness.bus.put(0x0212, 0x60);
+        harness.cpu.registers.stack = 0xff;
+
+        assert_eq!(harness.step(), 6);
+        assert_eq!(harness.cpu.registers.stack, 0xfd);
+        assert_eq!(harness.bus.get(0x01ff), 0x02);
+        assert_eq!(harness.bus.get(0x01fe), 0x02);
+
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0x42);
+
+        assert_eq!(harness.step(), 6);
+        assert_eq!(harness.cpu.registers.stack, 0xff);
+
+        harness.step();
+        assert_eq!(harness.cpu.registers.x, 0x01);
+    }
+
+    #[test]
+    fn rti() {
+        let mut harness = Harness::new(&[0x40]);
+        harness.cpu.registers.stack = 0xfc;
+        harness.bus.put(0x01fd, 0xff);
+        harness.bus.put(0x01fe, 0x34);
+        harness.bus.put(0x01ff, 0x12);
+        harness.bus.put(0x1234, 0xa9);
+        harness.bus.put(0x1235, 0x42);
+
+        assert_eq!(harness.step(), 6);
+        assert_eq!(harness.cpu.registers.stack, 0xff);
+        assert_eq!(harness.cpu.registers.status.bits(), 0b1100_1111);
+
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0x42);
+    }
+
+    #[test]
+    fn brk() {
+        let mut harness = Harness::new(&[0x00, 0xea]);
+        harness.cpu.registers.stack = 0xff;
+        harness.cpu.registers.status = StatusRegister::new(StatusRegister::CARRY.bits());
+        harness.bus.put(0xfffe, 0x34);
+        harness.bus.put(0xffff, 0x12);
+        harness.bus.put(0x1234, 0x40);
+
+        assert_eq!(harness.step(), 7);
+        assert_eq!(harness.bus.get(0x01ff), 0x02);
+        assert_eq!(harness.bus.get(0x01fe), 0x02);
+        assert_eq!(harness.bus.get(0x01fd), 0b0011_0001);
+        assert_eq!(harness.cpu.registers.stack, 0xfc);
+        assert!(harness.flag(StatusRegister::IRQ_DISABLE));
+
+        // RTI returns past the padding byte
+        assert_eq!(harness.step(), 6);
+        assert_eq!(harness.cpu.registers.pc, START + 2);
+        assert_eq!(harness.cpu.registers.status.bits(), StatusRegister::CARRY.bits());
+    }
+
+    #[test]
+    fn jmp_absolute() {
+        let mut harness = Harness::new(&[0x4c, 0x34, 0x12]);
+        harness.bus.put(0x1234, 0xa9);
+        harness.bus.put(0x1235, 0x42);
+        assert_eq!(harness.step(), 3);
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0x42);
+    }
+
+    #[test]
+    fn jmp_indirect() {
+        let mut harness = Harness::new(&[0x6c, 0x00, 0x30]);
+        harness.bus.put(0x3000, 0x34);
+        harness.bus.put(0x3001, 0x12);
+        harness.bus.put(0x1234, 0xa9);
+        harness.bus.put(0x1235, 0x42);
+        assert_eq!(harness.step(), 5);
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0x42);
+    }
+
+    /// Run a program's first instruction on every variant, a cycle at a time and functionally, handing back each
+    /// harness once it has along with a label for it. The 6510 is left out, since its I/O port answers at $0000.
+    fn run_on_variants(program: &[u8], setup: impl Fn(&mut Harness)) -> Vec<(String, Harness)> {
+        let mut runs = Vec::new();
+        for variant in [Variant::Mos6502, Variant::Wdc65C02, Variant::Ricoh2A03, Variant::Mos6507] {
+            for mode in [ExecutionMode::CycleAccurate, ExecutionMode::Functional] {
+                let mut harness = Harness::new(program).variant(variant);
+                harness.cpu.set_mode(mode);
+                setup(&mut harness);
+                harness.cpu.step(&mut harness.bus).unwrap();
+                runs.push((format!("{variant:?} {mode:?}"), harness));
+            }
+        }
+        runs
+    }
+
+    #[test]
+    fn jmp_indirect_page_wrap() {
+        // The NMOS parts read the high byte from $1000, and the 65C02 from $1100
+        let runs = run_on_variants(&[0x6c, 0xff, 0x10], |harness| {
+            harness.bus.put(0x10ff, 0x34);
+            harness.bus.put(0x1000, 0x12);
+            harness.bus.put(0x1100, 0x56);
+        });
+        for (label, harness) in runs {
+            let expected = if harness.cpu.variant().jmp_indirect_wraps() { 0x1234 } else { 0x5634 };
+            assert_eq!(harness.cpu.registers.pc, expected, "{label}");
+        }
+    }
+
+    #[test]
+    fn zero_page_indexed_wraps() {
+        // LDA $F0,X and LDX $F0,Y read from $0010, and STA $F0,X writes there
+        for (opcode, register) in [(0xb5, 0), (0xb6, 1), (0x95, 2)] {
+            let runs = run_on_variants(&[opcode, 0xf0], |harness| {
+                harness.cpu.registers.a = 0x77;
+                harness.cpu.registers.x = 0x20;
+                harness.cpu.registers.y = 0x20;
+                harness.bus.put(0x0010, 0x42);
+                harness.bus.put(0x0110, 0x99);
+            });
+            for (label, harness) in runs {
+                let registers = &harness.cpu.registers;
+                match register {
+                    0 => assert_eq!(registers.a, 0x42, "{opcode:#04x} {label}"),
+                    1 => assert_eq!(registers.x, 0x42, "{opcode:#04x} {label}"),
+                    _ => assert_eq!(harness.bus.ram.get(0x0010), 0x77, "{opcode:#04x} {label}"),
+                }
+                assert_eq!(harness.bus.ram.get(0x0110), 0x99, "{opcode:#04x} {label}");
+            }
+        }
+    }
+
+    #[test]
+    fn indexed_indirect_wraps() {
+        // ($F0,X) with X = $20 reads its pointer from $0010, and ($FE,X) with X = $01 from $00FF and $0000
+        for (operand, x, low, high) in [(0xf0, 0x20, 0x0010, 0x0011), (0xfe, 0x01, 0x00ff, 0x0000)] {
+            let runs = run_on_variants(&[0xa1, operand], |harness| {
+                harness.cpu.registers.x = x;
+                harness.bus.put(low, 0x34);
+                harness.bus.put(high, 0x12);
+                harness.bus.put(0x1234, 0x42);
+            });
+            for (label, harness) in runs {
+                assert_eq!(harness.cpu.registers.a, 0x42, "{operand:#04x} {label}");
+            }
+        }
+    }
+
+    #[test]
+    fn indirect_indexed_wraps() {
+        // ($FF),Y reads its pointer from $00FF and $0000
+        let runs = run_on_variants(&[0xb1, 0xff], |harness| {
+            harness.cpu.registers.y = 0x04;
+            harness.bus.put(0x00ff, 0x30);
+            harness.bus.put(0x0000, 0x12);
+            harness.bus.put(0x0100, 0x99);
+            harness.bus.put(0x1234, 0x42);
+        });
+        for (label, harness) in runs {
+            assert_eq!(harness.cpu.registers.a, 0x42, "{label}");
+        }
+    }
+
+    #[test]
+    fn add_program() {
+        // test_bin/add.s
+        let mut harness = Harness::new(&[
+            0x18, 0xd8, 0xa9, 0x01, 0x8d, 0x00, 0x61, 0xa9, 0x02, 0x8d, 0x01, 0x61,
+            0xad, 0x00, 0x61, 0x6d, 0x01, 0x61, 0x8d, 0x02, 0x61, 0x60,
+        ]);
+        for _ in 0..9 {
+            harness.step();
+        }
+        assert_eq!(harness.bus.get(0x6100), 1);
+        assert_eq!(harness.bus.get(0x6101), 2);
+        assert_eq!(harness.bus.get(0x6102), 3);
+    }
+
+    #[test]
+    fn slo() {
+        for (opcode, mode, cycles) in [
+            (0x07, ZeroPage, 5), (0x17, ZeroPageX, 6), (0x0f, Absolute, 6), (0x1f, AbsoluteX, 7),
+            (0x1b, AbsoluteY, 7), (0x03, IndirectX, 8), (0x13, IndirectY, 8),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0b1000_0001);
+            harness.cpu.registers.a = 0b0000_0100;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0b0000_0010, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0b0000_0110, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::CARRY));
+        }
+    }
+
+    #[test]
+    fn rla() {
+        for (opcode, mode, cycles) in [
+            (0x27, ZeroPage, 5), (0x37, ZeroPageX, 6), (0x2f, Absolute, 6), (0x3f, AbsoluteX, 7),
+            (0x3b, AbsoluteY, 7), (0x23, IndirectX, 8), (0x33, IndirectY, 8),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0b1000_0001);
+            harness.cpu.registers.a = 0b0000_0010;
+            harness.cpu.registers.status.set_flag(StatusRegister::CARRY);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0b0000_0011, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0b0000_0010, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::CARRY));
+        }
+    }
+
+    #[test]
+    fn sre() {
+        for (opcode, mode, cycles) in [
+            (0x47, ZeroPage, 5), (0x57, ZeroPageX, 6), (0x4f, Absolute, 6), (0x5f, AbsoluteX, 7),
+            (0x5b, AbsoluteY, 7), (0x43, IndirectX, 8), (0x53, IndirectY, 8),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0b0000_0011);
+            harness.cpu.registers.a = 0b0000_0011;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0b0000_0001, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0b0000_0010, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::CARRY));
+        }
+    }
+
+    #[test]
+    fn rra() {
+        for (opcode, mode, cycles) in [
+            (0x67, ZeroPage, 5), (0x77, ZeroPageX, 6), (0x6f, Absolute, 6), (0x7f, AbsoluteX, 7),
+            (0x7b, AbsoluteY, 7), (0x63, IndirectX, 8), (0x73, IndirectY, 8),
+        ] {
+            // The carry out of the rotate is carried into the add
+            let mut harness = Harness::with_operand(opcode, mode, 0x03);
+            harness.cpu.registers.a = 0x10;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0x01, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0x12, "{opcode:#04x}");
+            assert!(!harness.flag(StatusRegister::CARRY));
+        }
+
+        let mut harness = Harness::with_operand(0x67, ZeroPage, 0x12);
+        harness.cpu.registers.a = 0x19;
+        harness.cpu.registers.status.set_flag(StatusRegister::DECIMAL_MODE);
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0x28);
+    }
+
+    #[test]
+    fn dcp() {
+        for (opcode, mode, cycles) in [
+            (0xc7, ZeroPage, 5), (0xd7, ZeroPageX, 6), (0xcf, Absolute, 6), (0xdf, AbsoluteX, 7),
+            (0xdb, AbsoluteY, 7), (0xc3, IndirectX, 8), (0xd3, IndirectY, 8),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x43);
+            harness.cpu.registers.a = 0x42;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0x42, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0x42, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::ZERO));
+            assert!(harness.flag(StatusRegister::CARRY));
+        }
+    }
+
+    #[test]
+    fn isc() {
+        for (opcode, mode, cycles) in [
+            (0xe7, ZeroPage, 5), (0xf7, ZeroPageX, 6), (0xef, Absolute, 6), (0xff, AbsoluteX, 7),
+            (0xfb, AbsoluteY, 7), (0xe3, IndirectX, 8), (0xf3, IndirectY, 8),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x41);
+            harness.cpu.registers.a = 0x50;
+            harness.cpu.registers.status.set_flag(StatusRegister::CARRY);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0x42, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0x0e, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::CARRY));
+        }
+    }
+
+    #[test]
+    fn sax() {
+        for (opcode, mode, cycles) in [
+            (0x87, ZeroPage, 3), (0x97, ZeroPageY, 4), (0x8f, Absolute, 4), (0x83, IndirectX, 6),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x00);
+            harness.cpu.registers.a = 0xf2;
+            if !matches!(mode, IndirectX) {
+                harness.cpu.registers.x = 0x3c;
+            }
+            let flags = harness.cpu.registers.status.bits();
+            let expected = harness.cpu.registers.a & harness.cpu.registers.x;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), expected, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.status.bits(), flags, "{opcode:#04x}");
+        }
+    }
+
+    #[test]
+    fn lax() {
+        for (opcode, mode, cycles) in [
+            (0xa7, ZeroPage, 3), (0xb7, ZeroPageY, 4), (0xaf, Absolute, 4), (0xbf, AbsoluteY, 4),
+            (0xa3, IndirectX, 6), (0xb3, IndirectY, 5),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x80);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!((harness.cpu.registers.a, harness.cpu.registers.x), (0x80, 0x80), "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::NEGATIVE));
+        }
+
+        for (opcode, mode, cycles) in [(0xbf, AbsoluteY, 5), (0xb3, IndirectY, 6)] {
+            let mut harness = Harness::crossing_page(opcode, mode, 0x42);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!((harness.cpu.registers.a, harness.cpu.registers.x), (0x42, 0x42), "{opcode:#04x}");
+        }
+    }
+
+    #[test]
+    fn anc() {
+        for opcode in [0x0b, 0x2b] {
+            let mut harness = Harness::with_operand(opcode, Immediate, 0x81);
+            harness.cpu.registers.a = 0xc0;
+            assert_eq!(harness.step(), 2, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0x80, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::NEGATIVE));
+            assert!(harness.flag(StatusRegister::CARRY));
+        }
+    }
+
+    #[test]
+    fn alr() {
+        let mut harness = Harness::with_operand(0x4b, Immediate, 0x07);
+        harness.cpu.registers.a = 0x03;
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.a, 0x01);
+        assert!(harness.flag(StatusRegister::CARRY));
+    }
+
+    #[test]
+    fn arr() {
+        let mut harness = Harness::with_operand(0x6b, Immediate, 0xc0);
+        harness.cpu.registers.a = 0xff;
+        harness.cpu.registers.status.set_flag(StatusRegister::CARRY);
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.a, 0xe0);
+        assert!(harness.flag(StatusRegister::NEGATIVE));
+        assert!(harness.flag(StatusRegister::CARRY));
+        assert!(!harness.flag(StatusRegister::OVERFLOW));
+
+        let mut harness = Harness::with_operand(0x6b, Immediate, 0x40);
+        harness.cpu.registers.a = 0xff;
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0x20);
+        assert!(!harness.flag(StatusRegister::CARRY));
+        assert!(harness.flag(StatusRegister::OVERFLOW));
+    }
+
+    #[test]
+    fn arr_decimal() {
+        let mut harness = Harness::with_operand(0x6b, Immediate, 0x55);
+        harness.cpu.registers.a = 0xff;
+        harness.cpu.registers.status.set_flag(StatusRegister::DECIMAL_MODE);
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0x80);
+        assert!(harness.flag(StatusRegister::CARRY));
+        assert!(harness.flag(StatusRegister::OVERFLOW));
+        assert!(!harness.flag(StatusRegister::NEGATIVE));
+    }
+
+    #[test]
+    fn sbx() {
+        let mut harness = Harness::with_operand(0xcb, Immediate, 0x04);
+        harness.cpu.registers.a = 0x0f;
+        harness.cpu.registers.x = 0x3c;
+        harness.cpu.registers.status.set_flag(StatusRegister::DECIMAL_MODE);
+        assert_eq!(harness.step(), 2);
+        assert_eq!((harness.cpu.registers.a, harness.cpu.registers.x), (0x0f, 0x08));
+        assert!(harness.flag(StatusRegister::CARRY));
+
+        let mut harness = Harness::with_operand(0xcb, Immediate, 0x01);
+        harness.step();
+        assert_eq!(harness.cpu.registers.x, 0xff);
+        assert!(!harness.flag(StatusRegister::CARRY));
+        assert!(harness.flag(StatusRegister::NEGATIVE));
+    }
+
+    #[test]
+    fn sbc_undocumented() {
+        let mut harness = Harness::with_operand(0xeb, Immediate, 0x10);
+        harness.cpu.registers.a = 0x50;
+        harness.cpu.registers.status.set_flag(StatusRegister::CARRY);
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.a, 0x40);
+    }
+
+    #[test]
+    fn ane_lxa_magic() {
+        let mut harness = Harness::with_operand(0x8b, Immediate, 0xff);
+        (harness.cpu.registers.a, harness.cpu.registers.x) = (0x01, 0xf0);
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.a, 0xe0);
+
+        let mut harness = Harness::with_operand(0x8b, Immediate, 0xff);
+        harness.cpu.set_magic(0x00);
+        (harness.cpu.registers.a, harness.cpu.registers.x) = (0x01, 0xf0);
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0x00);
+        assert!(harness.flag(StatusRegister::ZERO));
+
+        let mut harness = Harness::with_operand(0xab, Immediate, 0x0f);
+        harness.cpu.registers.a = 0x01;
+        assert_eq!(harness.step(), 2);
+        assert_eq!((harness.cpu.registers.a, harness.cpu.registers.x), (0x0f, 0x0f));
+
+        let mut harness = Harness::with_operand(0xab, Immediate, 0x0f);
+        harness.cpu.set_magic(0xff);
+        harness.step();
+        assert_eq!((harness.cpu.registers.a, harness.cpu.registers.x), (0x0f, 0x0f));
+    }
+
+    #[test]
+    fn las() {
+        let mut harness = Harness::with_operand(0xbb, AbsoluteY, 0x3c);
+        harness.cpu.registers.stack = 0xf0;
+        assert_eq!(harness.step(), 4);
+        let reg = &harness.cpu.registers;
+        assert_eq!((reg.a, reg.x, reg.stack), (0x30, 0x30, 0x30));
+
+        let mut harness = Harness::crossing_page(0xbb, AbsoluteY, 0x3c);
+        harness.cpu.registers.stack = 0xf0;
+        assert_eq!(harness.step(), 5);
+        assert_eq!(harness.cpu.registers.a, 0x30);
+    }
+
+    #[test]
+    fn unstable_stores() {
+        // The value is ANDed with the high byte of the base address plus one, $12 + 1
+        for (opcode, mode, cycles) in [(0x9f, AbsoluteY, 5), (0x93, IndirectY, 6)] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x00);
+            (harness.cpu.registers.a, harness.cpu.registers.x) = (0xff, 0x33);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(0x1234), 0x13, "{opcode:#04x}");
+        }
+
+        let mut harness = Harness::with_operand(0x9e, AbsoluteY, 0x00);
+        harness.cpu.registers.x = 0xff;
+        assert_eq!(harness.step(), 5);
+        assert_eq!(harness.bus.get(0x1234), 0x13);
+
+        let mut harness = Harness::with_operand(0x9c, AbsoluteX, 0x00);
+        harness.cpu.registers.y = 0xff;
+        assert_eq!(harness.step(), 5);
+        assert_eq!(harness.bus.get(0x1234), 0x13);
+
+        let mut harness = Harness::with_operand(0x9b, AbsoluteY, 0x00);
+        (harness.cpu.registers.a, harness.cpu.registers.x) = (0xf0, 0x3f);
+        assert_eq!(harness.step(), 5);
+        assert_eq!(harness.cpu.registers.stack, 0x30);
+        assert_eq!(harness.bus.get(0x1234), 0x10);
+    }
+
+    #[test]
+    fn unstable_stores_crossing_page() {
+        // Crossing into $1300, the value replaces the high byte of the address
+        for opcode in [0x9f, 0x93] {
+            let mode = if opcode == 0x9f { AbsoluteY } else { IndirectY };
+            let mut harness = Harness::crossing_page(opcode, mode, 0x00);
+            (harness.cpu.registers.a, harness.cpu.registers.x) = (0xff, 0x0f);
+            harness.step();
+            assert_eq!(harness.bus.get(0x0300), 0x03, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(0x1300), 0x00, "{opcode:#04x}");
+        }
+
+        let mut harness = Harness::crossing_page(0x9e, AbsoluteY, 0x00);
+        harness.cpu.registers.x = 0x0f;
+        harness.step();
+        assert_eq!(harness.bus.get(0x0300), 0x03);
+    }
+
+    #[test]
+    fn nop_undocumented() {
+        for (opcode, length, cycles) in [
+            (0x1a, 1, 2), (0x3a, 1, 2), (0x5a, 1, 2), (0x7a, 1, 2), (0xda, 1, 2), (0xfa, 1, 2),
+            (0x80, 2, 2), (0x82, 2, 2), (0x89, 2, 2), (0xc2, 2, 2), (0xe2, 2, 2),
+            (0x04, 2, 3), (0x44, 2, 3), (0x64, 2, 3),
+            (0x14, 2, 4), (0x34, 2, 4), (0x54, 2, 4), (0x74, 2, 4), (0xd4, 2, 4), (0xf4, 2, 4),
+            (0x0c, 3, 4), (0x1c, 3, 4), (0x3c, 3, 4), (0x5c, 3, 4), (0x7c, 3, 4), (0xdc, 3, 4), (0xfc, 3, 4),
+        ] {
+            let mut harness = Harness::new(&[opcode, 0x30, 0x12]);
+            harness.cpu.registers.x = 0x04;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.pc, START + length, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.status.bits(), 0, "{opcode:#04x}");
+        }
+
+        let mut harness = Harness::crossing_page(0x1c, AbsoluteX, 0x00);
+        assert_eq!(harness.step(), 5);
+    }
+
+    #[test]
+    fn cmos_every_opcode_decodes() {
+        for opcode in 0..=0xff {
+            assert!(find_instruction(opcode, Variant::Wdc65C02, 0xee).is_some(), "{opcode:#04x}");
+        }
+    }
+
+    #[test]
+    fn cmos_adc_decimal() {
+        for (opcode, mode, cycles) in [(0x69, Immediate, 3), (0x6d, Absolute, 5), (0x71, IndirectY, 6)] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x01).cmos();
+            harness.cpu.registers.a = 0x99;
+            harness.cpu.registers.status.set_flag(StatusRegister::DECIMAL_MODE);
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0x00, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::CARRY));
+            assert!(harness.flag(StatusRegister::ZERO));
+            assert!(!harness.flag(StatusRegister::NEGATIVE));
+        }
+
+        // Binary mode doesn't spend the extra cycle
+        let mut harness = Harness::with_operand(0x69, Immediate, 0x01).cmos();
+        assert_eq!(harness.step(), 2);
+    }
+
+    #[test]
+    fn cmos_sbc_decimal() {
+        let mut harness = Harness::with_operand(0xe9, Immediate, 0x01).cmos();
+        harness.cpu.registers.status.set_flag(StatusRegister::DECIMAL_MODE);
+        harness.cpu.registers.status.set_flag(StatusRegister::CARRY);
+        assert_eq!(harness.step(), 3);
+        assert_eq!(harness.cpu.registers.a, 0x99);
+        assert!(!harness.flag(StatusRegister::CARRY));
+        assert!(harness.flag(StatusRegister::NEGATIVE));
+
+        // An invalid digit comes out differently from the NMOS part
+        let mut harness = Harness::with_operand(0xe9, Immediate, 0x0f).cmos();
+        harness.cpu.registers.a = 0x00;
+        harness.cpu.registers.status.set_flag(StatusRegister::DECIMAL_MODE);
+        harness.cpu.registers.status.set_flag(StatusRegister::CARRY);
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0x8b);
+    }
+
+    #[test]
+    fn cmos_bus_decimal_cycle() {
+        let mut harness = Harness::with_operand(0x6d, Absolute, 0x01).cmos();
+        harness.cpu.registers.status.set_flag(StatusRegister::DECIMAL_MODE);
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0x6d), Read(0x0201, 0x34), Read(0x0202, 0x12), Read(0x1234, 0x01), Read(0x1234, 0x01),
+        ]);
+    }
+
+    #[test]
+    fn cmos_bus_read_modify_write() {
+        let mut harness = Harness::with_operand(0xe6, ZeroPage, 0x05).cmos();
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0xe6), Read(0x0201, 0x40), Read(0x0040, 0x05), Read(0x0040, 0x05), Write(0x0040, 0x06),
+        ]);
+    }
+
+    #[test]
+    fn cmos_bus_zero_page_indexed() {
+        let mut harness = Harness::with_operand(0xb5, ZeroPageX, 0x99).cmos();
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0xb5), Read(0x0201, 0x40), Read(0x0201, 0x40), Read(0x0042, 0x99),
+        ]);
+    }
+
+    #[test]
+    fn cmos_bus_absolute_indexed_write() {
+        let mut harness = Harness::new(&[0x9d, 0xff, 0x12]).cmos();
+        harness.cpu.registers.a = 0x42;
+        harness.cpu.registers.x = 0x01;
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0x9d), Read(0x0201, 0xff), Read(0x0202, 0x12), Read(0x0202, 0x12), Write(0x1300, 0x42),
+        ]);
+    }
+
+    #[test]
+    fn cmos_shift_timing() {
+        // Shifts and rotates only pay for a page crossing, INC and DEC always take seven cycles
+        for (opcode, cycles, crossing) in [
+            (0x1e, 6, 7), (0x5e, 6, 7), (0x3e, 6, 7), (0x7e, 6, 7), (0xfe, 7, 7), (0xde, 7, 7),
+        ] {
+            let mut harness = Harness::with_operand(opcode, AbsoluteX, 0x01).cmos();
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            let mut harness = Harness::crossing_page(opcode, AbsoluteX, 0x01).cmos();
+            assert_eq!(harness.step(), crossing, "{opcode:#04x}");
+        }
+    }
+
+    #[test]
+    fn cmos_zero_page_indirect() {
+        // CMP leaves A alone
+        for (opcode, expected) in [
+            (0x12, 0x5f), (0x32, 0x04), (0x52, 0x5b), (0x72, 0x64), (0xb2, 0x5c), (0xd2, 0x07), (0xf2, 0xab),
+        ] {
+            let mut harness = Harness::new(&[opcode, 0x40]).cmos();
+            harness.bus.put(0x0040, 0x34);
+            harness.bus.put(0x0041, 0x12);
+            harness.bus.put(0x1234, 0x5c);
+            harness.cpu.registers.a = 0x07;
+            harness.cpu.registers.status.set_flag(StatusRegister::CARRY);
+            assert_eq!(harness.step(), 5, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, expected, "{opcode:#04x}");
+        }
+
+        let mut harness = Harness::new(&[0x92, 0xff]).cmos();
+        harness.bus.put(0x00ff, 0x34);
+        harness.bus.put(0x0000, 0x12);
+        harness.cpu.registers.a = 0x42;
+        harness.step();
+        assert_eq!(harness.bus.get(0x1234), 0x42);
+    }
+
+    #[test]
+    fn cmos_bit() {
+        let mut harness = Harness::with_operand(0x89, Immediate, 0xc0).cmos();
+        harness.cpu.registers.a = 0x01;
+        assert_eq!(harness.step(), 2);
+        assert!(harness.flag(StatusRegister::ZERO));
+        assert!(!harness.flag(StatusRegister::NEGATIVE));
+        assert!(!harness.flag(StatusRegister::OVERFLOW));
+
+        for (opcode, mode, cycles) in [(0x34, ZeroPageX, 4), (0x3c, AbsoluteX, 4)] {
+            let mut harness = Harness::with_operand(opcode, mode, 0xc0).cmos();
+            harness.cpu.registers.a = 0x01;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::ZERO));
+            assert!(harness.flag(StatusRegister::NEGATIVE));
+            assert!(harness.flag(StatusRegister::OVERFLOW));
+        }
+    }
+
+    #[test]
+    fn cmos_increment_decrement_accumulator() {
+        let mut harness = Harness::new(&[0x1a, 0x3a, 0x3a]).cmos();
+        harness.cpu.registers.a = 0xff;
+        assert_eq!(harness.step(), 2);
+        assert_eq!(harness.cpu.registers.a, 0x00);
+        assert!(harness.flag(StatusRegister::ZERO));
+        harness.step();
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0xfe);
+        assert!(harness.flag(StatusRegister::NEGATIVE));
+    }
+
+    #[test]
+    fn cmos_bra() {
+        // Taken whatever the flags are, in 3 cycles on the same page and 4 onto another
+        for flags in [0x00, 0xff] {
+            let mut harness = Harness::new(&[0x80, 0x02, 0xa9, 0x01, 0xa9, 0x02]).cmos();
+            harness.cpu.registers.status = StatusRegister::new(flags);
+            assert_eq!(harness.step(), 3, "{flags:#04x}");
+            assert_eq!(harness.cpu.registers.pc, 0x0204, "{flags:#04x}");
+
+            let mut harness = Harness::new(&[0x80, 0xfc]).cmos();
+            harness.cpu.registers.status = StatusRegister::new(flags);
+            assert_eq!(harness.step(), 4, "{flags:#04x}");
+            assert_eq!(harness.cpu.registers.pc, 0x01fe, "{flags:#04x}");
+        }
+    }
+
+    #[test]
+    fn cmos_stack_xy() {
+        let mut harness = Harness::new(&[0xda, 0x5a, 0xfa, 0x7a]).cmos();
+        (harness.cpu.registers.x, harness.cpu.registers.y, harness.cpu.registers.stack) = (0x12, 0x80, 0xff);
+        assert_eq!(harness.step(), 3);
+        assert_eq!(harness.step(), 3);
+        assert_eq!(harness.bus.get(0x01ff), 0x12);
+        assert_eq!(harness.bus.get(0x01fe), 0x80);
+
+        // Pull them back the other way round
+        assert_eq!(harness.step(), 4);
+        assert_eq!(harness.cpu.registers.x, 0x80);
+        assert!(harness.flag(StatusRegister::NEGATIVE));
+        assert_eq!(harness.step(), 4);
+        assert_eq!(harness.cpu.registers.y, 0x12);
+        assert!(!harness.flag(StatusRegister::NEGATIVE));
+        assert_eq!(harness.cpu.registers.stack, 0xff);
+    }
+
+    #[test]
+    fn cmos_stz() {
+        for (opcode, mode, cycles) in [
+            (0x64, ZeroPage, 3), (0x74, ZeroPageX, 4), (0x9c, Absolute, 4), (0x9e, AbsoluteX, 5),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x99).cmos();
+            harness.cpu.registers.a = 0x42;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), 0x00, "{opcode:#04x}");
+        }
+    }
+
+    #[test]
+    fn cmos_tsb_trb() {
+        for (opcode, mode, cycles, expected) in [
+            (0x04, ZeroPage, 5, 0x3c), (0x0c, Absolute, 6, 0x3c), (0x14, ZeroPage, 5, 0x30), (0x1c, Absolute, 6, 0x30),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x34).cmos();
+            harness.cpu.registers.a = 0x0c;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.bus.get(address(mode).unwrap()), expected, "{opcode:#04x}");
+            assert!(!harness.flag(StatusRegister::ZERO), "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.a, 0x0c);
+        }
+
+        let mut harness = Harness::with_operand(0x04, ZeroPage, 0xf0).cmos();
+        harness.cpu.registers.a = 0x0f;
+        harness.step();
+        assert!(harness.flag(StatusRegister::ZERO));
+    }
+
+    #[test]
+    fn cmos_rmb_smb() {
+        for bit in 0..8 {
+            let mut harness = Harness::with_operand(0x07 + bit * 0x10, ZeroPage, 0xff).cmos();
+            assert_eq!(harness.step(), 5);
+            assert_eq!(harness.bus.get(0x0040), !(1 << bit), "RMB{bit}");
+
+            let mut harness = Harness::with_operand(0x87 + bit * 0x10, ZeroPage, 0x00).cmos();
+            assert_eq!(harness.step(), 5);
+            assert_eq!(harness.bus.get(0x0040), 1 << bit, "SMB{bit}");
+        }
+    }
+
+    #[test]
+    fn cmos_bbr_bbs() {
+        for bit in 0..8 {
+            for set in [false, true] {
+                let opcode = if set { 0x8f } else { 0x0f } + bit * 0x10;
+                let mut harness = Harness::new(&[opcode, 0x40, 0x02, 0xa9, 0x01, 0xa9, 0x02]).cmos();
+                harness.bus.put(0x0040, 1 << bit);
+                let cycles = if set { 6 } else { 5 };
+                assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+                let pc = if set { 0x0205 } else { 0x0203 };
+                assert_eq!(harness.cpu.registers.pc, pc, "{opcode:#04x}");
+            }
+        }
+
+        let mut harness = Harness::new(&[0x0f, 0x40, 0x02]).cmos();
+        harness.step();
+        assert_eq!(harness.accesses(), vec![
+            Read(0x0200, 0x0f), Read(0x0201, 0x40), Read(0x0040, 0x00), Read(0x0040, 0x00), Read(0x0202, 0x02),
+            Read(0x0203, 0x00),
+        ]);
+    }
+
+    #[test]
+    fn cmos_jmp_indirect_page_wrap_fixed() {
+        let mut harness = Harness::new(&[0x6c, 0xff, 0x30]).cmos();
+        harness.bus.put(0x30ff, 0x34);
+        harness.bus.put(0x3000, 0x56);
+        harness.bus.put(0x3100, 0x12);
+        assert_eq!(harness.step(), 6);
+        assert_eq!(harness.cpu.registers.pc, 0x1234);
+    }
+
+    #[test]
+    fn cmos_zero_page_indirect_wraps() {
+        let mut harness = Harness::new(&[0xb2, 0xff]).cmos();
+        harness.bus.put(0x00ff, 0x34);
+        harness.bus.put(0x0000, 0x12);
+        harness.bus.put(0x0100, 0x99);
+        harness.bus.put(0x1234, 0x42);
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0x42);
+    }
+
+    #[test]
+    fn cmos_jmp_indexed_indirect() {
+        let mut harness = Harness::new(&[0x7c, 0x00, 0x30]).cmos();
+        harness.cpu.registers.x = 0x04;
+        harness.bus.put(0x3004, 0x34);
+        harness.bus.put(0x3005, 0x12);
+        assert_eq!(harness.step(), 6);
+        assert_eq!(harness.cpu.registers.pc, 0x1234);
+    }
+
+    #[test]
+    fn cmos_brk_clears_decimal() {
+        let mut harness = Harness::new(&[0x00]).cmos();
+        harness.cpu.registers.stack = 0xff;
+        harness.cpu.registers.status.set_flag(StatusRegister::DECIMAL_MODE);
+        harness.step();
+        assert!(!harness.flag(StatusRegister::DECIMAL_MODE));
+        // The pushed copy keeps it
+        assert_eq!(harness.bus.get(0x01fd) & StatusRegister::DECIMAL_MODE.bits(), StatusRegister::DECIMAL_MODE.bits());
+    }
+
+    #[test]
+    fn cmos_nop() {
+        for (opcode, length, cycles) in [
+            (0x03, 1, 1), (0x0b, 1, 1), (0x13, 1, 1), (0x1b, 1, 1), (0xf3, 1, 1), (0xfb, 1, 1), (0xea, 1, 2),
+            (0x02, 2, 2), (0x22, 2, 2), (0x42, 2, 2), (0x62, 2, 2), (0x82, 2, 2), (0xc2, 2, 2), (0xe2, 2, 2),
+            (0x44, 2, 3), (0x54, 2, 4), (0xd4, 2, 4), (0xf4, 2, 4),
+            (0xdc, 3, 4), (0xfc, 3, 4), (0x5c, 3, 8),
+        ] {
+            let mut harness = Harness::new(&[opcode, 0x30, 0x12]).cmos();
+            harness.cpu.registers.x = 0x04;
+            assert_eq!(harness.step(), cycles, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.pc, START + length, "{opcode:#04x}");
+            assert_eq!(harness.cpu.registers.status.bits(), 0, "{opcode:#04x}");
+        }
+    }
+
+    #[test]
+    fn ricoh_ignores_decimal() {
+        // Each of these would come out differently with the decimal adjustment
+        for (opcode, mode, a, expected) in [
+            (0x69, Immediate, 0x09, 0x0c), (0xe9, Immediate, 0x10, 0x0e),
+            (0x67, ZeroPage, 0x19, 0x9a), (0xe7, ZeroPage, 0x10, 0x0d),
+        ] {
+            let mut harness = Harness::with_operand(opcode, mode, 0x02).variant(Variant::Ricoh2A03);
+            harness.cpu.registers.a = a;
+            harness.cpu.registers.status.set_flag(StatusRegister::DECIMAL_MODE);
+            harness.cpu.registers.status.set_flag(StatusRegister::CARRY);
+            harness.step();
+            assert_eq!(harness.cpu.registers.a, expected, "{opcode:#04x}");
+            assert!(harness.flag(StatusRegister::DECIMAL_MODE));
+        }
+
+        let mut harness = Harness::with_operand(0x6b, Immediate, 0xff).variant(Variant::Ricoh2A03);
+        harness.cpu.registers.a = 0xff;
+        harness.cpu.registers.status.set_flag(StatusRegister::DECIMAL_MODE);
+        harness.cpu.registers.status.set_flag(StatusRegister::CARRY);
+        harness.step();
+        assert_eq!(harness.cpu.registers.a, 0xff);
+    }
+
+    /// Run every opcode with and without indexing or a branch crossing a page, and in and out of decimal mode, and
+    /// check the cycles it takes and the bytes it takes up against the opcode table.
+    #[test]
+    fn opcode_table_cycles() {
+        for variant in [Variant::Mos6502, Variant::Wdc65C02] {
+            for opcode in 0..=0xff {
+                let info = opcode_info::lookup(opcode, variant);
+                if matches!(info.mnemonic, "JAM" | "STP" | "WAI") {
+                    continue;
+                }
+
+                for (crossing, decimal) in [(false, false), (true, false), (false, true)] {
+                    // $40 indexes and branches forward within the page, $F0 indexes across one and branches back
+                    // into page one
+                    let operand = if crossing { 0xf0 } else { 0x40 };
+                    let mut harness = Harness::new(&[opcode, operand, operand]).variant(variant);
+                    harness.cpu.registers.x = if crossing { 0x20 } else { 0x01 };
+                    harness.cpu.registers.y = harness.cpu.registers.x;
+                    harness.cpu.registers.status.set_decimal_mode(decimal);
+                    harness.bus.put(operand as u16, operand);
+                    harness.bus.put(operand as u16 + 1, 0x12);
+                    let cycles = harness.step();
+
+                    let fallthrough = START + info.length as u16;
+                    let moved = harness.cpu.registers.pc != fallthrough;
+                    let branch = matches!(info.mode, AddressingMode::Relative | AddressingMode::ZeroPageRelative);
+                    let taken = branch && info.mnemonic != "BRA" && moved;
+                    let crossed = info.page_cross && crossing && (!branch || moved);
+                    let expected = info.cycles as usize
+                        + taken as usize
+                        + crossed as usize
+                        + (info.decimal_cycle && decimal) as usize;
+                    assert_eq!(cycles, expected, "{variant:?} {opcode:#04x} crossing: {crossing}, decimal: {decimal}");
+
+                    if !branch && !matches!(info.mnemonic, "JMP" | "JSR" | "RTS" | "RTI" | "BRK") {
+                        assert_eq!(harness.cpu.registers.pc, fallthrough, "{variant:?} {opcode:#04x}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run every opcode with each flag it doesn't read flipped, and check nothing it does changes, and that it
+    /// never changes a flag it doesn't write.
+    #[test]
+    fn opcode_table_flags() {
+        let flags = [
+            StatusRegister::CARRY,
+            StatusRegister::ZERO,
+            StatusRegister::IRQ_DISABLE,
+            StatusRegister::DECIMAL_MODE,
+            StatusRegister::OVERFLOW,
+            StatusRegister::NEGATIVE,
+        ];
+        for variant in [Variant::Mos6502, Variant::Wdc65C02, Variant::Ricoh2A03] {
+            for opcode in 0..=0xff {
+                let info = opcode_info::lookup(opcode, variant);
+                let run = |status: u8| {
+                    let mut harness = Harness::new(&[opcode, 0x40, 0x12]).variant(variant);
+                    harness.cpu.registers.a = 0x19;
+                    harness.cpu.registers.x = 0x02;
+                    harness.cpu.registers.y = 0x04;
+                    harness.cpu.registers.status = StatusRegister::new(status);
+                    for (addr, value) in [(0x0040, 0x38), (0x0041, 0x12), (0x0042, 0x3c), (0x0043, 0x12)] {
+                        harness.bus.put(addr, value);
+                    }
+                    for addr in [0x1212, 0x1238, 0x123c, 0x1240, 0x1242, 0x1244] {
+                        harness.bus.put(addr, 0x28);
+                    }
+                    harness.bus.log.borrow_mut().clear();
+                    let step = harness.cpu.step(&mut harness.bus);
+                    (step, harness)
+                };
+
+                for status in [0x00, 0xff] {
+                    let (step, harness) = run(status & !(StatusRegister::BRK_COMMAND | StatusRegister::UNUSED).bits());
+                    let before = StatusRegister::from_pull(status).bits();
+                    let after = harness.cpu.registers.status.bits();
+                    assert_eq!((before ^ after) & !info.flags_written, 0, "{variant:?} {opcode:#04x} wrote a flag");
+
+                    for flag in flags.map(Flags::bits).into_iter().filter(|flag| info.flags_read & flag == 0) {
+                        let (flipped_step, flipped) = run(before ^ flag);
+                        let (a, b) = (&harness.cpu.registers, &flipped.cpu.registers);
+                        assert_eq!(
+                            (step, a.a, a.x, a.y, a.stack, a.pc, a.status.bits() & !flag, harness.accesses()),
+                            (flipped_step, b.a, b.x, b.y, b.stack, b.pc, b.status.bits() & !flag, flipped.accesses()),
+                            "{variant:?} {opcode:#04x} read {flag:#010b}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn functional_matches_cycles() {
+        let mut seed: u32 = 0x6502;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        };
+
+        let variants = [Variant::Mos6502, Variant::Wdc65C02, Variant::Ricoh2A03, Variant::Mos6507, Variant::Mos6510];
+        let opcodes = variants.into_iter().flat_map(|variant| {
+            (0..=0xff)
+                .filter(move |opcode| find_instruction(*opcode, variant, 0xee).is_some())
+                .map(move |opcode| (variant, opcode))
+        });
+        for (variant, opcode) in opcodes {
+            for _ in 0..32 {
+                let program = [opcode, random(), random()];
+                let memory: Vec<(u16, u8)> = (0x0000..0x0200)
+                    .chain(0xfffa..=0xffff)
+                    .map(|addr| (addr, random()))
+                    .collect();
+                let registers = Registers {
+                    a: random(),
+                    x: random(),
+                    y: random(),
+                    status: StatusRegister::new(random()),
+                    pc: START,
+                    stack: random(),
+                    adl: 0,
+                    adh: 0,
+                    data: 0,
+                };
+
+                let harness = || Harness::new(&program).variant(variant);
+                let [mut cycles, mut functional] = [harness(), harness()];
+                for harness in [&mut cycles, &mut functional] {
+                    for (addr, value) in &memory {
+                        harness.bus.ram.put(*addr, *value);
+                    }
+                    harness.cpu.registers = registers;
+                }
+                functional.cpu.set_mode(ExecutionMode::Functional);
+
+                assert_eq!(
+                    cycles.cpu.step(&mut cycles.bus),
+                    functional.cpu.step(&mut functional.bus),
+                    "{variant:?} {opcode:#04x}"
+                );
+
+                let (a, b) = (&cycles.cpu.registers, &functional.cpu.registers);
+                assert_eq!(
+                    (a.a, a.x, a.y, a.stack, a.status.bits(), a.pc),
+                    (b.a, b.x, b.y, b.stack, b.status.bits(), b.pc),
+                    "{variant:?} {opcode:#04x}"
+                );
+                for access in cycles.accesses() {
+                    if let Write(addr, _) = access {
+                        assert_eq!(cycles.bus.ram.get(addr), functional.bus.ram.get(addr), "{variant:?} {opcode:#04x}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn writes_at_matches_bus() {
+        for variant in [Variant::Mos6502, Variant::Wdc65C02] {
+            for (operand, index, decimal) in [(0x40, 0x02, false), (0xff, 0x01, false), (0x40, 0x02, true)] {
+                for opcode in 0..=0xffu8 {
+                    let mut harness = Harness::new(&[opcode, operand, 0x12]).variant(variant);
+                    harness.cpu.registers.x = index;
+                    harness.cpu.registers.y = index;
+                    harness.cpu.registers.status.update_flag(StatusRegister::DECIMAL_MODE, decimal);
+                    harness.bus.put(0x0040, 0xff);
+                    harness.bus.put(0x0041, 0x12);
+                    harness.bus.put(0x0042, 0x34);
+                    harness.bus.put(0x0043, 0x12);
+
+                    for _ in 0..10 {
+                        let predicted = harness.cpu.instruction.as_ref().is_some_and(|i| i.writes_at(harness.cpu.cycle));
+                        harness.bus.log.borrow_mut().clear();
+                        harness.cpu.tick(&mut harness.bus);
+                        let wrote = harness.accesses().iter().any(|access| matches!(access, Write(..)));
+                        assert_eq!(predicted, wrote, "{variant:?} {opcode:#04x} operand {operand:#04x}");
+                        if harness.cpu.instruction.is_none() {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,1475 @@
+use crate::io_device::LongIODevice;
+
+use super::cpu_65816::{Interrupt, Registers};
+use super::opcodes::InstructionState;
+use super::status_register::{Flags, StatusRegister};
+
+/*
+ * The 65816 runs an instruction at a time. Every read and write the instruction makes counts a cycle, and the
+ * internal cycles the datasheet lists between them are counted with io(), so the cycle counts come out right
+ * for every register width, direct page and index without being tabulated.
+ *
+ * Resources:
+ * - https://www.westerndesigncenter.com/wdc/documentation/w65c816s.pdf
+ * - http://www.6502.org/tutorials/65c816opcodes.html
+ */
+
+type ReadOp = fn(&mut Registers, u16);
+type WriteOp = fn(&Registers) -> u16;
+type ModifyOp = fn(&mut Registers, u16) -> u16;
+type ImpliedOp = fn(&mut Registers);
+
+/// Where an operand lives. Direct page and stack addresses wrap within bank 0, everything else carries into
+/// the next bank.
+#[derive(Clone, Copy)]
+enum Address {
+    Bank0(u16),
+    Long(u32),
+}
+
+impl Address {
+    fn long(self) -> u32 {
+        match self {
+            Address::Bank0(address) => address as u32,
+            Address::Long(address) => address,
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            Address::Bank0(address) => Address::Bank0(address.wrapping_add(1)),
+            Address::Long(address) => Address::Long(address.wrapping_add(1) & 0xFFFFFF),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Direct,
+    DirectX,
+    DirectY,
+    DirectIndirect,
+    DirectIndirectLong,
+    DirectIndexedIndirect,
+    DirectIndirectIndexed,
+    DirectIndirectLongIndexed,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    AbsoluteLong,
+    AbsoluteLongX,
+    StackRelative,
+    StackRelativeIndirectIndexed,
+}
+
+/// Which register's width an operation works at.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Width {
+    Memory,
+    Index,
+}
+
+impl Width {
+    fn wide(self, reg: &Registers) -> bool {
+        match self {
+            Width::Memory => !reg.m8(),
+            Width::Index => !reg.x8(),
+        }
+    }
+}
+
+/// An instruction being run, and the cycles it has taken so far.
+struct Context<'a> {
+    reg: &'a mut Registers,
+    bus: &'a mut dyn LongIODevice,
+    cycles: usize,
+}
+
+impl Context<'_> {
+    fn read(&mut self, address: u32) -> u8 {
+        self.cycles += 1;
+        self.bus.get_long(address)
+    }
+
+    fn write(&mut self, address: u32, value: u8) {
+        self.cycles += 1;
+        self.bus.put_long(address, value);
+    }
+
+    /// An internal cycle, which doesn't touch anything the functional core cares about.
+    fn io(&mut self) {
+        self.cycles += 1;
+    }
+
+    fn fetch(&mut self) -> u8 {
+        let value = self.read(self.reg.program_counter());
+        self.reg.pc = self.reg.pc.wrapping_add(1);
+        value
+    }
+
+    fn fetch_word(&mut self) -> u16 {
+        let low = self.fetch();
+        u16::from_le_bytes([low, self.fetch()])
+    }
+
+    fn fetch_long(&mut self) -> u32 {
+        let word = self.fetch_word() as u32;
+        (self.fetch() as u32) << 16 | word
+    }
+
+    /// Fetch an immediate operand as wide as the register it's for.
+    fn fetch_immediate(&mut self, wide: bool) -> u16 {
+        match wide {
+            true => self.fetch_word(),
+            false => self.fetch() as u16,
+        }
+    }
+
+    fn read_data(&mut self, address: Address, wide: bool) -> u16 {
+        let low = self.read(address.long());
+        match wide {
+            true => u16::from_le_bytes([low, self.read(address.next().long())]),
+            false => low as u16,
+        }
+    }
+
+    /// Write a value, high byte first as the hardware does.
+    fn write_data(&mut self, address: Address, value: u16, wide: bool) {
+        let [low, high] = value.to_le_bytes();
+        if wide {
+            self.write(address.next().long(), high);
+        }
+        self.write(address.long(), low);
+    }
+
+    fn push(&mut self, value: u8) {
+        self.write(self.reg.stack as u32, value);
+        self.reg.stack = self.reg.stack.wrapping_sub(1);
+        self.keep_stack_in_page_one();
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.reg.stack = self.reg.stack.wrapping_add(1);
+        self.keep_stack_in_page_one();
+        self.read(self.reg.stack as u32)
+    }
+
+    fn push_word(&mut self, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.push(high);
+        self.push(low);
+    }
+
+    fn pull_word(&mut self) -> u16 {
+        let low = self.pull();
+        u16::from_le_bytes([low, self.pull()])
+    }
+
+    /// In emulation mode the stack stays in page one, as on the 6502.
+    fn keep_stack_in_page_one(&mut self) {
+        if self.reg.emulation {
+            self.reg.stack = 0x0100 | (self.reg.stack & 0xFF);
+        }
+    }
+
+    /// An address in the direct page. In emulation mode with the direct page aligned to a page, it wraps within
+    /// the page like the 6502's zero page.
+    fn direct_address(&self, offset: u16) -> u16 {
+        if self.reg.emulation && self.reg.direct & 0xFF == 0 {
+            self.reg.direct | (offset & 0xFF)
+        } else {
+            self.reg.direct.wrapping_add(offset)
+        }
+    }
+
+    /// Fetch a direct page offset. Direct page addressing takes a cycle longer when the direct page isn't aligned
+    /// to a page.
+    fn fetch_direct(&mut self) -> u8 {
+        let offset = self.fetch();
+        if self.reg.direct & 0xFF != 0 {
+            self.io();
+        }
+        offset
+    }
+
+    /// Read a 16-bit pointer from the direct page.
+    fn read_direct_pointer(&mut self, offset: u16) -> u16 {
+        let low = self.read(self.direct_address(offset) as u32);
+        u16::from_le_bytes([low, self.read(self.direct_address(offset.wrapping_add(1)) as u32)])
+    }
+
+    /// Add an index to an address in the data bank or beyond. Reads only take a cycle to do it if it crosses a
+    /// page or the index is 16 bits wide, but writes and read-modify-write instructions always do.
+    fn index(&mut self, base: u32, index: u16, always: bool) -> Address {
+        let address = base.wrapping_add(index as u32) & 0xFFFFFF;
+        if always || !self.reg.x8() || (base ^ address) & 0xFF00 != 0 {
+            self.io();
+        }
+        Address::Long(address)
+    }
+
+    fn data_bank(&self, address: u16) -> u32 {
+        (self.reg.data_bank as u32) << 16 | address as u32
+    }
+
+    /// Work out where an addressing mode's operand is, fetching whatever it needs to.
+    fn address(&mut self, mode: Mode, always: bool) -> Address {
+        match mode {
+            Mode::Direct => {
+                let offset = self.fetch_direct();
+                Address::Bank0(self.direct_address(offset as u16))
+            },
+            Mode::DirectX | Mode::DirectY => {
+                let offset = self.fetch_direct() as u16;
+                self.io();
+                let index = if matches!(mode, Mode::DirectX) { self.reg.x } else { self.reg.y };
+                Address::Bank0(self.direct_address(offset.wrapping_add(index)))
+            },
+            Mode::DirectIndirect => {
+                let offset = self.fetch_direct();
+                let pointer = self.read_direct_pointer(offset as u16);
+                Address::Long(self.data_bank(pointer))
+            },
+            Mode::DirectIndirectLong | Mode::DirectIndirectLongIndexed => {
+                let offset = self.fetch_direct();
+                let pointer = Address::Bank0(self.reg.direct.wrapping_add(offset as u16));
+                let word = self.read_data(pointer, true) as u32;
+                let address = (self.read(pointer.next().next().long()) as u32) << 16 | word;
+                match mode {
+                    Mode::DirectIndirectLong => Address::Long(address),
+                    _ => Address::Long(address.wrapping_add(self.reg.y as u32) & 0xFFFFFF),
+                }
+            },
+            Mode::DirectIndexedIndirect => {
+                let offset = self.fetch_direct() as u16;
+                self.io();
+                let pointer = self.read_direct_pointer(offset.wrapping_add(self.reg.x));
+                Address::Long(self.data_bank(pointer))
+            },
+            Mode::DirectIndirectIndexed => {
+                let offset = self.fetch_direct();
+                let pointer = self.read_direct_pointer(offset as u16);
+                self.index(self.data_bank(pointer), self.reg.y, always)
+            },
+            Mode::Absolute => {
+                let address = self.fetch_word();
+                Address::Long(self.data_bank(address))
+            },
+            Mode::AbsoluteX | Mode::AbsoluteY => {
+                let address = self.fetch_word();
+                let index = if matches!(mode, Mode::AbsoluteX) { self.reg.x } else { self.reg.y };
+                self.index(self.data_bank(address), index, always)
+            },
+            Mode::AbsoluteLong => Address::Long(self.fetch_long()),
+            Mode::AbsoluteLongX => {
+                let address = self.fetch_long();
+                Address::Long(address.wrapping_add(self.reg.x as u32) & 0xFFFFFF)
+            },
+            Mode::StackRelative => {
+                let offset = self.fetch();
+                self.io();
+                Address::Bank0(self.reg.stack.wrapping_add(offset as u16))
+            },
+            Mode::StackRelativeIndirectIndexed => {
+                let offset = self.fetch();
+                self.io();
+                let pointer = self.read_data(Address::Bank0(self.reg.stack.wrapping_add(offset as u16)), true);
+                self.io();
+                Address::Long(self.data_bank(pointer).wrapping_add(self.reg.y as u32) & 0xFFFFFF)
+            },
+        }
+    }
+
+    fn immediate(&mut self, op: ReadOp, width: Width) {
+        let value = self.fetch_immediate(width.wide(self.reg));
+        op(self.reg, value);
+    }
+
+    fn load(&mut self, mode: Mode, op: ReadOp, width: Width) {
+        let address = self.address(mode, false);
+        let value = self.read_data(address, width.wide(self.reg));
+        op(self.reg, value);
+    }
+
+    fn store(&mut self, mode: Mode, op: WriteOp, width: Width) {
+        let address = self.address(mode, true);
+        self.write_data(address, op(self.reg), width.wide(self.reg));
+    }
+
+    fn modify(&mut self, mode: Mode, op: ModifyOp) {
+        let address = self.address(mode, true);
+        let wide = !self.reg.m8();
+        let value = self.read_data(address, wide);
+        self.io();
+        let result = op(self.reg, value);
+        self.write_data(address, result, wide);
+    }
+
+    fn accumulator(&mut self, op: ModifyOp) {
+        self.io();
+        let result = op(self.reg, accumulator(self.reg));
+        set_accumulator(self.reg, result);
+    }
+
+    fn implied(&mut self, op: ImpliedOp) {
+        self.io();
+        op(self.reg);
+    }
+
+    fn push_register(&mut self, value: u16, wide: bool) {
+        self.io();
+        match wide {
+            true => self.push_word(value),
+            false => self.push(value as u8),
+        }
+    }
+
+    fn pull_register(&mut self, wide: bool) -> u16 {
+        self.io();
+        self.io();
+        match wide {
+            true => self.pull_word(),
+            false => self.pull() as u16,
+        }
+    }
+
+    fn branch(&mut self, flag: Flags, set: bool) {
+        let offset = self.fetch();
+        if self.reg.status.flag(flag) == set {
+            self.branch_to(self.reg.pc.wrapping_add(offset as i8 as u16));
+        }
+    }
+
+    /// Take a branch, which costs a cycle, and another in emulation mode if it crosses a page.
+    fn branch_to(&mut self, target: u16) {
+        self.io();
+        if self.reg.emulation && (target ^ self.reg.pc) & 0xFF00 != 0 {
+            self.io();
+        }
+        self.reg.pc = target;
+    }
+
+    fn interrupt(&mut self, interrupt: Interrupt) {
+        if !self.reg.emulation {
+            self.push(self.reg.program_bank);
+        }
+        self.push_word(self.reg.pc);
+        let status = match (self.reg.emulation, interrupt) {
+            (true, interrupt) => self.reg.status.for_push(interrupt == Interrupt::Brk),
+            (false, _) => self.reg.status.bits(),
+        };
+        self.push(status);
+        self.reg.status.set_irq_disable(true);
+        self.reg.status.set_decimal_mode(false);
+        self.reg.program_bank = 0;
+        self.reg.pc = self.read_data(Address::Bank0(interrupt.vector(self.reg.emulation)), true);
+    }
+
+    fn block_move(&mut self, step: u16) {
+        let destination = self.fetch();
+        let source = self.fetch();
+        self.reg.data_bank = destination;
+        let value = self.read((source as u32) << 16 | self.reg.x as u32);
+        self.write((destination as u32) << 16 | self.reg.y as u32, value);
+        self.io();
+        self.io();
+
+        set_x(self.reg, self.reg.x.wrapping_add(step));
+        set_y(self.reg, self.reg.y.wrapping_add(step));
+        self.reg.a = self.reg.a.wrapping_sub(1);
+        if self.reg.a != 0xFFFF {
+            self.reg.pc = self.reg.pc.wrapping_sub(3);
+        }
+    }
+
+    fn run(&mut self, opcode: u8) -> InstructionState {
+        // The eight operations of the 6502's first group have the same addressing modes at the same offsets
+        let group_one = match opcode & 0x1F {
+            0x01 => Some(Mode::DirectIndexedIndirect),
+            0x03 => Some(Mode::StackRelative),
+            0x05 => Some(Mode::Direct),
+            0x07 => Some(Mode::DirectIndirectLong),
+            0x0D => Some(Mode::Absolute),
+            0x0F => Some(Mode::AbsoluteLong),
+            0x11 => Some(Mode::DirectIndirectIndexed),
+            0x12 => Some(Mode::DirectIndirect),
+            0x13 => Some(Mode::StackRelativeIndirectIndexed),
+            0x15 => Some(Mode::DirectX),
+            0x17 => Some(Mode::DirectIndirectLongIndexed),
+            0x19 => Some(Mode::AbsoluteY),
+            0x1D => Some(Mode::AbsoluteX),
+            0x1F => Some(Mode::AbsoluteLongX),
+            _ => None,
+        };
+        if let Some(mode) = group_one {
+            match opcode >> 5 {
+                0 => self.load(mode, ora, Width::Memory),
+                1 => self.load(mode, and, Width::Memory),
+                2 => self.load(mode, eor, Width::Memory),
+                3 => self.load(mode, adc, Width::Memory),
+                4 => self.store(mode, sta, Width::Memory),
+                5 => self.load(mode, lda, Width::Memory),
+                6 => self.load(mode, cmp, Width::Memory),
+                _ => self.load(mode, sbc, Width::Memory),
+            }
+            return InstructionState::Finished;
+        }
+
+        match opcode {
+            0x09 => self.immediate(ora, Width::Memory),
+            0x29 => self.immediate(and, Width::Memory),
+            0x49 => self.immediate(eor, Width::Memory),
+            0x69 => self.immediate(adc, Width::Memory),
+            0x89 => self.immediate(bit_immediate, Width::Memory),
+            0xA9 => self.immediate(lda, Width::Memory),
+            0xC9 => self.immediate(cmp, Width::Memory),
+            0xE9 => self.immediate(sbc, Width::Memory),
+
+            // Shifts and rotates
+            0x06 => self.modify(Mode::Direct, asl),
+            0x0A => self.accumulator(asl),
+            0x0E => self.modify(Mode::Absolute, asl),
+            0x16 => self.modify(Mode::DirectX, asl),
+            0x1E => self.modify(Mode::AbsoluteX, asl),
+            0x26 => self.modify(Mode::Direct, rol),
+            0x2A => self.accumulator(rol),
+            0x2E => self.modify(Mode::Absolute, rol),
+            0x36 => self.modify(Mode::DirectX, rol),
+            0x3E => self.modify(Mode::AbsoluteX, rol),
+            0x46 => self.modify(Mode::Direct, lsr),
+            0x4A => self.accumulator(lsr),
+            0x4E => self.modify(Mode::Absolute, lsr),
+            0x56 => self.modify(Mode::DirectX, lsr),
+            0x5E => self.modify(Mode::AbsoluteX, lsr),
+            0x66 => self.modify(Mode::Direct, ror),
+            0x6A => self.accumulator(ror),
+            0x6E => self.modify(Mode::Absolute, ror),
+            0x76 => self.modify(Mode::DirectX, ror),
+            0x7E => self.modify(Mode::AbsoluteX, ror),
+
+            // INC, DEC
+            0x1A => self.accumulator(inc),
+            0x3A => self.accumulator(dec),
+            0xC6 => self.modify(Mode::Direct, dec),
+            0xCE => self.modify(Mode::Absolute, dec),
+            0xD6 => self.modify(Mode::DirectX, dec),
+            0xDE => self.modify(Mode::AbsoluteX, dec),
+            0xE6 => self.modify(Mode::Direct, inc),
+            0xEE => self.modify(Mode::Absolute, inc),
+            0xF6 => self.modify(Mode::DirectX, inc),
+            0xFE => self.modify(Mode::AbsoluteX, inc),
+            0xC8 => self.implied(iny),
+            0xCA => self.implied(dex),
+            0x88 => self.implied(dey),
+            0xE8 => self.implied(inx),
+
+            // TSB, TRB
+            0x04 => self.modify(Mode::Direct, tsb),
+            0x0C => self.modify(Mode::Absolute, tsb),
+            0x14 => self.modify(Mode::Direct, trb),
+            0x1C => self.modify(Mode::Absolute, trb),
+
+            // BIT
+            0x24 => self.load(Mode::Direct, bit, Width::Memory),
+            0x2C => self.load(Mode::Absolute, bit, Width::Memory),
+            0x34 => self.load(Mode::DirectX, bit, Width::Memory),
+            0x3C => self.load(Mode::AbsoluteX, bit, Width::Memory),
+
+            // X and Y
+            0xA0 => self.immediate(ldy, Width::Index),
+            0xA4 => self.load(Mode::Direct, ldy, Width::Index),
+            0xAC => self.load(Mode::Absolute, ldy, Width::Index),
+            0xB4 => self.load(Mode::DirectX, ldy, Width::Index),
+            0xBC => self.load(Mode::AbsoluteX, ldy, Width::Index),
+            0xA2 => self.immediate(ldx, Width::Index),
+            0xA6 => self.load(Mode::Direct, ldx, Width::Index),
+            0xAE => self.load(Mode::Absolute, ldx, Width::Index),
+            0xB6 => self.load(Mode::DirectY, ldx, Width::Index),
+            0xBE => self.load(Mode::AbsoluteY, ldx, Width::Index),
+            0xC0 => self.immediate(cpy, Width::Index),
+            0xC4 => self.load(Mode::Direct, cpy, Width::Index),
+            0xCC => self.load(Mode::Absolute, cpy, Width::Index),
+            0xE0 => self.immediate(cpx, Width::Index),
+            0xE4 => self.load(Mode::Direct, cpx, Width::Index),
+            0xEC => self.load(Mode::Absolute, cpx, Width::Index),
+            0x84 => self.store(Mode::Direct, sty, Width::Index),
+            0x8C => self.store(Mode::Absolute, sty, Width::Index),
+            0x94 => self.store(Mode::DirectX, sty, Width::Index),
+            0x86 => self.store(Mode::Direct, stx, Width::Index),
+            0x8E => self.store(Mode::Absolute, stx, Width::Index),
+            0x96 => self.store(Mode::DirectY, stx, Width::Index),
+
+            // STZ
+            0x64 => self.store(Mode::Direct, stz, Width::Memory),
+            0x74 => self.store(Mode::DirectX, stz, Width::Memory),
+            0x9C => self.store(Mode::Absolute, stz, Width::Memory),
+            0x9E => self.store(Mode::AbsoluteX, stz, Width::Memory),
+
+            // Transfers
+            0xAA => self.implied(tax),
+            0xA8 => self.implied(tay),
+            0x8A => self.implied(txa),
+            0x98 => self.implied(tya),
+            0xBA => self.implied(tsx),
+            0x9A => self.implied(txs),
+            0x9B => self.implied(txy),
+            0xBB => self.implied(tyx),
+            0x1B => self.implied(tcs),
+            0x3B => self.implied(tsc),
+            0x5B => self.implied(tcd),
+            0x7B => self.implied(tdc),
+            0xEB => {
+                self.io();
+                self.implied(xba);
+            },
+            0xFB => self.implied(xce),
+
+            // Flags
+            0x18 => self.implied(|reg| reg.status.set_carry(false)),
+            0x38 => self.implied(|reg| reg.status.set_carry(true)),
+            0x58 => self.implied(|reg| reg.status.set_irq_disable(false)),
+            0x78 => self.implied(|reg| reg.status.set_irq_disable(true)),
+            0xB8 => self.implied(|reg| reg.status.set_overflow(false)),
+            0xD8 => self.implied(|reg| reg.status.set_decimal_mode(false)),
+            0xF8 => self.implied(|reg| reg.status.set_decimal_mode(true)),
+            0xC2 => {
+                let flags = self.fetch();
+                self.io();
+                set_status(self.reg, self.reg.status.bits() & !flags);
+            },
+            0xE2 => {
+                let flags = self.fetch();
+                self.io();
+                set_status(self.reg, self.reg.status.bits() | flags);
+            },
+
+            // Stack
+            0x48 => self.push_register(accumulator(self.reg), !self.reg.m8()),
+            0xDA => self.push_register(self.reg.x, !self.reg.x8()),
+            0x5A => self.push_register(self.reg.y, !self.reg.x8()),
+            0x08 => {
+                let status = match self.reg.emulation {
+                    true => self.reg.status.for_push(true),
+                    false => self.reg.status.bits(),
+                };
+                self.push_register(status as u16, false);
+            },
+            0x8B => self.push_register(self.reg.data_bank as u16, false),
+            0x4B => self.push_register(self.reg.program_bank as u16, false),
+            0x0B => self.push_register(self.reg.direct, true),
+            0x68 => {
+                let value = self.pull_register(!self.reg.m8());
+                lda(self.reg, value);
+            },
+            0xFA => {
+                let value = self.pull_register(!self.reg.x8());
+                ldx(self.reg, value);
+            },
+            0x7A => {
+                let value = self.pull_register(!self.reg.x8());
+                ldy(self.reg, value);
+            },
+            0x28 => {
+                let value = self.pull_register(false);
+                set_status(self.reg, value as u8);
+            },
+            0xAB => {
+                self.reg.data_bank = self.pull_register(false) as u8;
+                update_zero_negative(self.reg, self.reg.data_bank as u16, false);
+            },
+            0x2B => {
+                self.reg.direct = self.pull_register(true);
+                update_zero_negative(self.reg, self.reg.direct, true);
+            },
+            0xF4 => {
+                let value = self.fetch_word();
+                self.push_word(value);
+            },
+            0xD4 => {
+                let offset = self.fetch_direct();
+                let value = self.read_direct_pointer(offset as u16);
+                self.push_word(value);
+            },
+            0x62 => {
+                let offset = self.fetch_word();
+                self.io();
+                self.push_word(self.reg.pc.wrapping_add(offset));
+            },
+
+            // Branches
+            0x10 => self.branch(StatusRegister::NEGATIVE, false),
+            0x30 => self.branch(StatusRegister::NEGATIVE, true),
+            0x50 => self.branch(StatusRegister::OVERFLOW, false),
+            0x70 => self.branch(StatusRegister::OVERFLOW, true),
+            0x90 => self.branch(StatusRegister::CARRY, false),
+            0xB0 => self.branch(StatusRegister::CARRY, true),
+            0xD0 => self.branch(StatusRegister::ZERO, false),
+            0xF0 => self.branch(StatusRegister::ZERO, true),
+            0x80 => {
+                let offset = self.fetch();
+                self.branch_to(self.reg.pc.wrapping_add(offset as i8 as u16));
+            },
+            0x82 => {
+                let offset = self.fetch_word();
+                self.io();
+                self.reg.pc = self.reg.pc.wrapping_add(offset);
+            },
+
+            // Jumps
+            0x4C => self.reg.pc = self.fetch_word(),
+            0x5C => {
+                let address = self.fetch_long();
+                (self.reg.program_bank, self.reg.pc) = ((address >> 16) as u8, address as u16);
+            },
+            0x6C => {
+                let pointer = self.fetch_word();
+                self.reg.pc = self.read_data(Address::Bank0(pointer), true);
+            },
+            0x7C => {
+                let pointer = self.fetch_word().wrapping_add(self.reg.x);
+                self.io();
+                let bank = (self.reg.program_bank as u32) << 16;
+                let low = self.read(bank | pointer as u32);
+                self.reg.pc = u16::from_le_bytes([low, self.read(bank | pointer.wrapping_add(1) as u32)]);
+            },
+            0xDC => {
+                let pointer = Address::Bank0(self.fetch_word());
+                self.reg.pc = self.read_data(pointer, true);
+                self.reg.program_bank = self.read(pointer.next().next().long());
+            },
+
+            // Subroutines
+            0x20 => {
+                let address = self.fetch_word();
+                self.io();
+                self.push_word(self.reg.pc.wrapping_sub(1));
+                self.reg.pc = address;
+            },
+            0x22 => {
+                let address = self.fetch_word();
+                self.push(self.reg.program_bank);
+                self.io();
+                self.reg.program_bank = self.fetch();
+                self.push_word(self.reg.pc.wrapping_sub(1));
+                self.reg.pc = address;
+            },
+            0xFC => {
+                let low = self.fetch();
+                self.push_word(self.reg.pc);
+                let pointer = u16::from_le_bytes([low, self.fetch()]).wrapping_add(self.reg.x);
+                self.io();
+                let bank = (self.reg.program_bank as u32) << 16;
+                let low = self.read(bank | pointer as u32);
+                self.reg.pc = u16::from_le_bytes([low, self.read(bank | pointer.wrapping_add(1) as u32)]);
+            },
+            0x60 => {
+                self.io();
+                self.io();
+                self.reg.pc = self.pull_word().wrapping_add(1);
+                self.io();
+            },
+            0x6B => {
+                self.io();
+                self.io();
+                self.reg.pc = self.pull_word().wrapping_add(1);
+                self.reg.program_bank = self.pull();
+            },
+            0x40 => {
+                self.io();
+                self.io();
+                let status = self.pull();
+                set_status(self.reg, status);
+                self.reg.pc = self.pull_word();
+                if !self.reg.emulation {
+                    self.reg.program_bank = self.pull();
+                }
+            },
+
+            // Interrupts
+            0x00 | 0x02 => {
+                self.fetch();
+                self.interrupt(if opcode == 0x00 { Interrupt::Brk } else { Interrupt::Cop });
+            },
+
+            // Block moves
+            0x44 => self.block_move(0xFFFF),
+            0x54 => self.block_move(0x0001),
+
+            0xCB => {
+                self.io();
+                self.io();
+                return InstructionState::Waiting;
+            },
+            0xDB => {
+                self.io();
+                self.io();
+                return InstructionState::Jammed;
+            },
+            0x42 => {
+                self.fetch();
+            },
+            _ => self.io(),
+        }
+        InstructionState::Finished
+    }
+}
+
+/// Fetch and run the instruction at PC. Returns the opcode, the state it finished in, and the cycles it took.
+pub fn execute(reg: &mut Registers, address_bus: &mut dyn LongIODevice) -> (u8, InstructionState, usize) {
+    let mut context = Context { reg, bus: address_bus, cycles: 0 };
+    let opcode = context.fetch();
+    let state = context.run(opcode);
+    (opcode, state, context.cycles)
+}
+
+/// Run an interrupt sequence in place of the instruction at PC. Returns the opcode it discarded along with the
+/// cycles it took.
+pub fn interrupt_sequence(
+    interrupt: Interrupt,
+    reg: &mut Registers,
+    address_bus: &mut dyn LongIODevice,
+) -> (u8, usize) {
+    let mut context = Context { reg, bus: address_bus, cycles: 0 };
+    let opcode = context.read(context.reg.program_counter());
+    context.io();
+
+    if interrupt == Interrupt::Reset {
+        // The stack is read from rather than written to, and everything is put back as it is after power on
+        for _ in 0..3 {
+            context.read(context.reg.stack as u32);
+            context.reg.stack = context.reg.stack.wrapping_sub(1);
+        }
+        let reg = &mut context.reg;
+        reg.emulation = true;
+        reg.direct = 0;
+        (reg.data_bank, reg.program_bank) = (0, 0);
+        reg.stack = 0x0100 | (reg.stack & 0xFF);
+        set_status(reg, reg.status.bits() | StatusRegister::IRQ_DISABLE.bits());
+        reg.status.set_decimal_mode(false);
+        context.reg.pc = context.read_data(Address::Bank0(interrupt.vector(true)), true);
+    } else {
+        context.interrupt(interrupt);
+    }
+    (opcode, context.cycles)
+}
+
+// Registers
+
+fn accumulator(reg: &Registers) -> u16 {
+    match reg.m8() {
+        true => reg.a & 0xFF,
+        false => reg.a,
+    }
+}
+
+/// Set A, leaving B alone when A is 8 bits wide.
+fn set_accumulator(reg: &mut Registers, value: u16) {
+    reg.a = match reg.m8() {
+        true => (reg.a & 0xFF00) | (value & 0xFF),
+        false => value,
+    };
+}
+
+fn set_x(reg: &mut Registers, value: u16) {
+    reg.x = if reg.x8() { value & 0xFF } else { value };
+}
+
+fn set_y(reg: &mut Registers, value: u16) {
+    reg.y = if reg.x8() { value & 0xFF } else { value };
+}
+
+/// Set P. In emulation mode M and X stay set, and setting X clears the high bytes of the index registers.
+fn set_status(reg: &mut Registers, flags: u8) {
+    reg.status = StatusRegister::new(flags);
+    if reg.emulation {
+        reg.status.set_flag(StatusRegister::MEMORY_SELECT | StatusRegister::INDEX_SELECT);
+    }
+    if reg.x8() {
+        reg.x &= 0xFF;
+        reg.y &= 0xFF;
+    }
+}
+
+fn sign_bit(wide: bool) -> u16 {
+    if wide { 0x8000 } else { 0x80 }
+}
+
+fn update_zero_negative(reg: &mut Registers, value: u16, wide: bool) {
+    let mask = if wide { 0xFFFF } else { 0xFF };
+    reg.status.set_zero(value & mask == 0);
+    reg.status.set_negative(value & sign_bit(wide) != 0);
+}
+
+// Operations
+
+fn ora(reg: &mut Registers, value: u16) {
+    lda(reg, accumulator(reg) | value);
+}
+
+fn and(reg: &mut Registers, value: u16) {
+    lda(reg, accumulator(reg) & value);
+}
+
+fn eor(reg: &mut Registers, value: u16) {
+    lda(reg, accumulator(reg) ^ value);
+}
+
+fn adc(reg: &mut Registers, value: u16) {
+    add(reg, value, false);
+}
+
+fn sbc(reg: &mut Registers, value: u16) {
+    add(reg, value, true);
+}
+
+/// ADC, or SBC by adding the complement. In decimal mode each digit is adjusted as it's added, carrying into the
+/// next, and unlike the 6502 every flag is valid. V comes from the sum before the top digit is adjusted.
+fn add(reg: &mut Registers, value: u16, subtract: bool) {
+    let wide = !reg.m8();
+    let mask: i32 = if wide { 0xFFFF } else { 0xFF };
+    let a = accumulator(reg) as i32;
+    let value = if subtract { !value as i32 & mask } else { value as i32 & mask };
+    let mut carry = reg.status.carry() as i32;
+
+    let mut result;
+    if reg.status.decimal_mode() {
+        result = 0;
+        let digits = if wide { 4 } else { 2 };
+        let mut overflow = false;
+        for digit in 0..digits {
+            let shift = digit * 4;
+            let nibble = 0xF << shift;
+            result = (a & nibble) + (value & nibble) + (carry << shift) + (result & ((1 << shift) - 1));
+            if digit == digits - 1 {
+                overflow = !(a ^ value) & (a ^ result) & sign_bit(wide) as i32 != 0;
+            }
+            if subtract && result < 0x10 << shift {
+                result -= 0x6 << shift;
+            } else if !subtract && result >= 0xA << shift {
+                result += 0x6 << shift;
+            }
+            carry = (result >= 0x10 << shift) as i32;
+        }
+        reg.status.set_overflow(overflow);
+    } else {
+        result = a + value + carry;
+        let overflow = !(a ^ value) & (a ^ result) & sign_bit(wide) as i32 != 0;
+        reg.status.set_overflow(overflow);
+        carry = (result > mask) as i32;
+    }
+
+    reg.status.set_carry(carry != 0);
+    lda(reg, (result & mask) as u16);
+}
+
+fn bit(reg: &mut Registers, value: u16) {
+    let wide = !reg.m8();
+    reg.status.set_zero(accumulator(reg) & value == 0);
+    reg.status.set_negative(value & sign_bit(wide) != 0);
+    reg.status.set_overflow(value & (sign_bit(wide) >> 1) != 0);
+}
+
+/// BIT immediate only sets Z.
+fn bit_immediate(reg: &mut Registers, value: u16) {
+    reg.status.set_zero(accumulator(reg) & value == 0);
+}
+
+fn compare(reg: &mut Registers, register: u16, value: u16, wide: bool) {
+    reg.status.set_carry(register >= value);
+    update_zero_negative(reg, register.wrapping_sub(value), wide);
+}
+
+fn cmp(reg: &mut Registers, value: u16) {
+    compare(reg, accumulator(reg), value, !reg.m8());
+}
+
+fn cpx(reg: &mut Registers, value: u16) {
+    compare(reg, reg.x, value, !reg.x8());
+}
+
+fn cpy(reg: &mut Registers, value: u16) {
+    compare(reg, reg.y, value, !reg.x8());
+}
+
+fn lda(reg: &mut Registers, value: u16) {
+    set_accumulator(reg, value);
+    update_zero_negative(reg, value, !reg.m8());
+}
+
+fn ldx(reg: &mut Registers, value: u16) {
+    set_x(reg, value);
+    update_zero_negative(reg, value, !reg.x8());
+}
+
+fn ldy(reg: &mut Registers, value: u16) {
+    set_y(reg, value);
+    update_zero_negative(reg, value, !reg.x8());
+}
+
+fn sta(reg: &Registers) -> u16 {
+    reg.a
+}
+
+fn stx(reg: &Registers) -> u16 {
+    reg.x
+}
+
+fn sty(reg: &Registers) -> u16 {
+    reg.y
+}
+
+fn stz(_reg: &Registers) -> u16 {
+    0
+}
+
+fn asl(reg: &mut Registers, value: u16) -> u16 {
+    let wide = !reg.m8();
+    reg.status.set_carry(value & sign_bit(wide) != 0);
+    let result = value << 1;
+    update_zero_negative(reg, result, wide);
+    result
+}
+
+fn lsr(reg: &mut Registers, value: u16) -> u16 {
+    reg.status.set_carry(value & 0x01 != 0);
+    let result = value >> 1;
+    update_zero_negative(reg, result, !reg.m8());
+    result
+}
+
+fn rol(reg: &mut Registers, value: u16) -> u16 {
+    let wide = !reg.m8();
+    let carry = reg.status.carry() as u16;
+    reg.status.set_carry(value & sign_bit(wide) != 0);
+    let result = value << 1 | carry;
+    update_zero_negative(reg, result, wide);
+    result
+}
+
+fn ror(reg: &mut Registers, value: u16) -> u16 {
+    let wide = !reg.m8();
+    let carry = if reg.status.carry() { sign_bit(wide) } else { 0 };
+    reg.status.set_carry(value & 0x01 != 0);
+    let result = value >> 1 | carry;
+    update_zero_negative(reg, result, wide);
+    result
+}
+
+fn inc(reg: &mut Registers, value: u16) -> u16 {
+    let result = value.wrapping_add(1);
+    update_zero_negative(reg, result, !reg.m8());
+    result
+}
+
+fn dec(reg: &mut Registers, value: u16) -> u16 {
+    let result = value.wrapping_sub(1);
+    update_zero_negative(reg, result, !reg.m8());
+    result
+}
+
+fn tsb(reg: &mut Registers, value: u16) -> u16 {
+    reg.status.set_zero(accumulator(reg) & value == 0);
+    value | accumulator(reg)
+}
+
+fn trb(reg: &mut Registers, value: u16) -> u16 {
+    reg.status.set_zero(accumulator(reg) & value == 0);
+    value & !accumulator(reg)
+}
+
+fn inx(reg: &mut Registers) {
+    ldx(reg, reg.x.wrapping_add(1));
+}
+
+fn iny(reg: &mut Registers) {
+    ldy(reg, reg.y.wrapping_add(1));
+}
+
+fn dex(reg: &mut Registers) {
+    ldx(reg, reg.x.wrapping_sub(1));
+}
+
+fn dey(reg: &mut Registers) {
+    ldy(reg, reg.y.wrapping_sub(1));
+}
+
+fn tax(reg: &mut Registers) {
+    ldx(reg, reg.a);
+}
+
+fn tay(reg: &mut Registers) {
+    ldy(reg, reg.a);
+}
+
+fn txa(reg: &mut Registers) {
+    lda(reg, reg.x);
+}
+
+fn tya(reg: &mut Registers) {
+    lda(reg, reg.y);
+}
+
+fn txy(reg: &mut Registers) {
+    ldy(reg, reg.x);
+}
+
+fn tyx(reg: &mut Registers) {
+    ldx(reg, reg.y);
+}
+
+fn tsx(reg: &mut Registers) {
+    ldx(reg, reg.stack);
+}
+
+/// TXS doesn't touch the flags. In emulation mode the stack stays in page one.
+fn txs(reg: &mut Registers) {
+    reg.stack = if reg.emulation { 0x0100 | (reg.x & 0xFF) } else { reg.x };
+}
+
+fn tcs(reg: &mut Registers) {
+    reg.stack = if reg.emulation { 0x0100 | (reg.a & 0xFF) } else { reg.a };
+}
+
+/// The transfers to and from the 16-bit registers move all 16 bits whatever M is.
+fn tsc(reg: &mut Registers) {
+    reg.a = reg.stack;
+    update_zero_negative(reg, reg.a, true);
+}
+
+fn tcd(reg: &mut Registers) {
+    reg.direct = reg.a;
+    update_zero_negative(reg, reg.direct, true);
+}
+
+fn tdc(reg: &mut Registers) {
+    reg.a = reg.direct;
+    update_zero_negative(reg, reg.a, true);
+}
+
+/// Swap A and B, setting N and Z from the new A.
+fn xba(reg: &mut Registers) {
+    reg.a = reg.a.swap_bytes();
+    update_zero_negative(reg, reg.a, false);
+}
+
+/// Swap the carry and emulation flags. Going into emulation mode makes every register 8 bits wide and moves the
+/// stack back into page one.
+fn xce(reg: &mut Registers) {
+    let carry = reg.status.carry();
+    reg.status.set_carry(reg.emulation);
+    reg.emulation = carry;
+    if reg.emulation {
+        reg.stack = 0x0100 | (reg.stack & 0xFF);
+    }
+    set_status(reg, reg.status.bits());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::address_decoder::LongAddressDecoder;
+    use crate::cpu::cpu_65816::{CPU65816, Registers};
+    use crate::cpu::opcodes::InstructionState;
+    use crate::cpu::opcodes_65816::execute;
+    use crate::cpu::status_register::StatusRegister;
+    use crate::io_device::LongIODevice;
+    use crate::memory::ram::RAM;
+
+    /// RAM in banks $00, $01 and $7E, with a program at $00:0200.
+    fn setup(program: &[u8]) -> LongAddressDecoder {
+        let mut bus = LongAddressDecoder::new();
+        bus.add_device(0x000000..=0x00FFFF, Box::new(RAM::<0x10000>::new(None)));
+        bus.add_device(0x010000..=0x01FFFF, Box::new(RAM::<0x10000>::new(None)));
+        bus.add_device(0x7E0000..=0x7EFFFF, Box::new(RAM::<0x10000>::new(None)));
+        load(&mut bus, 0x000200, program);
+        bus
+    }
+
+    fn load(bus: &mut LongAddressDecoder, address: u32, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            bus.put_long(address + i as u32, *byte);
+        }
+    }
+
+    fn emulation() -> Registers {
+        Registers { pc: 0x0200, stack: 0x01FF, ..CPU65816::new().registers }
+    }
+
+    /// Native mode with every register 16 bits wide.
+    fn native() -> Registers {
+        Registers { status: StatusRegister::new(0), emulation: false, ..emulation() }
+    }
+
+    /// Run the next instruction and return the cycles it took.
+    fn step(reg: &mut Registers, bus: &mut LongAddressDecoder) -> usize {
+        let (_, state, cycles) = execute(reg, bus);
+        assert_eq!(state, InstructionState::Finished);
+        cycles
+    }
+
+    #[test]
+    fn emulation_cycles() {
+        let mut bus = setup(&[
+            0xA9, 0x42,       // LDA #$42
+            0xA5, 0x10,       // LDA $10
+            0xB5, 0x10,       // LDA $10,X
+            0xBD, 0xF0, 0x12, // LDA $12F0,X
+            0xBD, 0x00, 0x12, // LDA $1200,X
+            0x9D, 0x00, 0x12, // STA $1200,X
+            0xB1, 0x20,       // LDA ($20),Y
+            0xA1, 0x20,       // LDA ($20,X)
+            0xE6, 0x10,       // INC $10
+            0xFE, 0x00, 0x12, // INC $1200,X
+            0x48,             // PHA
+            0x68,             // PLA
+            0x20, 0x00, 0x03, // JSR $0300
+        ]);
+        load(&mut bus, 0x000300, &[0x60]);
+        let mut reg = Registers { x: 0x20, y: 0x01, ..emulation() };
+
+        let expected = [2, 3, 4, 5, 4, 5, 5, 6, 5, 7, 3, 4, 6, 6];
+        for cycles in expected {
+            assert_eq!(step(&mut reg, &mut bus), cycles);
+        }
+        assert_eq!(reg.pc, 0x021D);
+    }
+
+    #[test]
+    fn emulation_direct_page_wraps() {
+        let mut bus = setup(&[0xB5, 0xF0]); // LDA $F0,X
+        load(&mut bus, 0x000010, &[0x11]);
+        load(&mut bus, 0x000110, &[0x22]);
+
+        let mut reg = Registers { x: 0x20, ..emulation() };
+        step(&mut reg, &mut bus);
+        assert_eq!(reg.a, 0x11);
+
+        // Only a page aligned direct page wraps
+        let mut reg = Registers { x: 0x20, direct: 0x0001, pc: 0x0200, ..emulation() };
+        load(&mut bus, 0x000111, &[0x33]);
+        assert_eq!(step(&mut reg, &mut bus), 5);
+        assert_eq!(reg.a, 0x33);
+    }
+
+    #[test]
+    fn xce() {
+        let mut bus = setup(&[
+            0x18,       // CLC
+            0xFB,       // XCE
+            0xC2, 0x30, // REP #$30
+            0xA2, 0x34, 0x12, // LDX #$1234
+            0x38,       // SEC
+            0xFB,       // XCE
+        ]);
+        let mut reg = Registers { stack: 0x01F0, ..emulation() };
+
+        step(&mut reg, &mut bus);
+        assert_eq!(step(&mut reg, &mut bus), 2);
+        assert!(!reg.emulation);
+        assert!(reg.status.flag(StatusRegister::CARRY));
+        assert!(reg.m8() && reg.x8());
+
+        assert_eq!(step(&mut reg, &mut bus), 3);
+        assert!(!reg.m8() && !reg.x8());
+        assert_eq!(step(&mut reg, &mut bus), 3);
+        assert_eq!(reg.x, 0x1234);
+
+        // Going back to emulation mode makes everything 8 bits wide again
+        step(&mut reg, &mut bus);
+        step(&mut reg, &mut bus);
+        assert!(reg.emulation && reg.m8() && reg.x8());
+        assert!(!reg.status.flag(StatusRegister::CARRY));
+        assert_eq!(reg.x, 0x34);
+        assert_eq!(reg.stack, 0x01F0);
+    }
+
+    #[test]
+    fn emulation_keeps_m_and_x() {
+        let mut bus = setup(&[0xC2, 0x30, 0x28]); // REP #$30, PLP
+        let mut reg = emulation();
+        step(&mut reg, &mut bus);
+        assert!(reg.m8() && reg.x8());
+        assert!(reg.status.flag(StatusRegister::MEMORY_SELECT | StatusRegister::INDEX_SELECT));
+
+        step(&mut reg, &mut bus);
+        assert!(reg.status.flag(StatusRegister::MEMORY_SELECT | StatusRegister::INDEX_SELECT));
+    }
+
+    #[test]
+    fn sixteen_bit() {
+        let mut bus = setup(&[
+            0xA9, 0x34, 0x12, // LDA #$1234
+            0x85, 0x10,       // STA $10
+            0x1A,             // INC
+            0x0E, 0x10, 0x00, // ASL $0010
+            0xE2, 0x20,       // SEP #$20
+            0xA9, 0xFF,       // LDA #$FF
+            0xEB,             // XBA
+        ]);
+        let mut reg = native();
+
+        assert_eq!(step(&mut reg, &mut bus), 3);
+        assert_eq!(step(&mut reg, &mut bus), 4);
+        assert_eq!((bus.get_long(0x10), bus.get_long(0x11)), (0x34, 0x12));
+        step(&mut reg, &mut bus);
+        assert_eq!(reg.a, 0x1235);
+        assert_eq!(step(&mut reg, &mut bus), 8);
+        assert_eq!((bus.get_long(0x10), bus.get_long(0x11)), (0x68, 0x24));
+
+        // With A back to 8 bits, B is left alone
+        step(&mut reg, &mut bus);
+        step(&mut reg, &mut bus);
+        assert_eq!(reg.a, 0x12FF);
+        assert_eq!(step(&mut reg, &mut bus), 3);
+        assert_eq!(reg.a, 0xFF12);
+        assert!(!reg.status.flag(StatusRegister::NEGATIVE));
+    }
+
+    #[test]
+    fn direct_page() {
+        let mut bus = setup(&[
+            0xA9, 0x00, 0x10, // LDA #$1000
+            0x5B,             // TCD
+            0xA5, 0x10,       // LDA $10
+            0xA9, 0x01, 0x10, // LDA #$1001
+            0x5B,             // TCD
+            0xA5, 0x10,       // LDA $10
+        ]);
+        load(&mut bus, 0x001010, &[0x11, 0x22, 0x33]);
+        let mut reg = native();
+
+        step(&mut reg, &mut bus);
+        assert_eq!(step(&mut reg, &mut bus), 2);
+        assert_eq!(reg.direct, 0x1000);
+        assert_eq!(step(&mut reg, &mut bus), 4);
+        assert_eq!(reg.a, 0x2211);
+
+        // A direct page off a page boundary costs a cycle
+        step(&mut reg, &mut bus);
+        step(&mut reg, &mut bus);
+        assert_eq!(step(&mut reg, &mut bus), 5);
+        assert_eq!(reg.a, 0x3322);
+    }
+
+    #[test]
+    fn banks() {
+        let mut bus = setup(&[
+            0xAF, 0x00, 0x80, 0x01, // LDA $018000
+            0x9F, 0xFF, 0xFF, 0x7E, // STA $7EFFFF,X
+            0xAB,                   // PLB
+            0xAD, 0x00, 0x80,       // LDA $8000
+            0xB9, 0xFF, 0x7F,       // LDA $7FFF,Y
+            0x87, 0x20,             // STA [$20]
+            0x97, 0x20,             // STA [$20],Y
+        ]);
+        load(&mut bus, 0x018000, &[0x34, 0x12]);
+        load(&mut bus, 0x000100, &[0x7E]);
+        load(&mut bus, 0x7E8000, &[0x78, 0x56]);
+        load(&mut bus, 0x000020, &[0x00, 0x90, 0x01]);
+        let mut reg = Registers { stack: 0x00FF, x: 0x0002, y: 0x0001, ..native() };
+
+        assert_eq!(step(&mut reg, &mut bus), 6);
+        assert_eq!(reg.a, 0x1234);
+
+        // Long indexing carries into the next bank, which has nothing mapped
+        assert_eq!(step(&mut reg, &mut bus), 6);
+        assert_eq!(bus.get_long(0x7EFFFF), 0x00);
+
+        assert_eq!(step(&mut reg, &mut bus), 4);
+        assert_eq!(reg.data_bank, 0x7E);
+        assert_eq!(step(&mut reg, &mut bus), 5);
+        assert_eq!(reg.a, 0x5678);
+        assert_eq!(step(&mut reg, &mut bus), 6);
+        assert_eq!(reg.a, 0x5678);
+
+        assert_eq!(step(&mut reg, &mut bus), 7);
+        assert_eq!((bus.get_long(0x019000), bus.get_long(0x019001)), (0x78, 0x56));
+        assert_eq!(step(&mut reg, &mut bus), 7);
+        assert_eq!(bus.get_long(0x019002), 0x56);
+    }
+
+    #[test]
+    fn program_bank() {
+        let mut bus = setup(&[
+            0x22, 0x00, 0x80, 0x01, // JSL $018000
+            0xDC, 0x10, 0x00,       // JML [$0010]
+        ]);
+        load(&mut bus, 0x018000, &[
+            0x4B,       // PHK
+            0xAB,       // PLB
+            0x6B,       // RTL
+        ]);
+        // The program counter wraps within the bank
+        load(&mut bus, 0x01FFFF, &[0xEA]);
+        load(&mut bus, 0x010000, &[0xEA]);
+        load(&mut bus, 0x000010, &[0xFF, 0xFF, 0x01]);
+        let mut reg = native();
+
+        assert_eq!(step(&mut reg, &mut bus), 8);
+        assert_eq!(reg.program_counter(), 0x018000);
+        assert_eq!((bus.get_long(0x01FF), bus.get_long(0x01FE), bus.get_long(0x01FD)), (0x00, 0x02, 0x03));
+        assert_eq!(step(&mut reg, &mut bus), 3);
+        assert_eq!(step(&mut reg, &mut bus), 4);
+        assert_eq!(reg.data_bank, 0x01);
+        assert_eq!(step(&mut reg, &mut bus), 6);
+        assert_eq!(reg.program_counter(), 0x000204);
+        assert_eq!(reg.stack, 0x01FF);
+
+        assert_eq!(step(&mut reg, &mut bus), 6);
+        assert_eq!(reg.program_counter(), 0x01FFFF);
+        step(&mut reg, &mut bus);
+        assert_eq!(reg.program_counter(), 0x010000);
+    }
+
+    #[test]
+    fn stack() {
+        let mut bus = setup(&[
+            0xF4, 0x34, 0x12, // PEA $1234
+            0xA3, 0x01,       // LDA $01,S
+            0xB3, 0x01,       // LDA ($01,S),Y
+            0x62, 0x00, 0x01, // PER $0100
+            0xD4, 0x10,       // PEI ($10)
+            0x0B,             // PHD
+            0x2B,             // PLD
+        ]);
+        load(&mut bus, 0x001236, &[0xCD, 0xAB]);
+        load(&mut bus, 0x000010, &[0x78, 0x56]);
+        let mut reg = Registers { y: 0x0002, ..native() };
+
+        assert_eq!(step(&mut reg, &mut bus), 5);
+        assert_eq!(step(&mut reg, &mut bus), 5);
+        assert_eq!(reg.a, 0x1234);
+        assert_eq!(step(&mut reg, &mut bus), 8);
+        assert_eq!(reg.a, 0xABCD);
+        assert_eq!(step(&mut reg, &mut bus), 6);
+        assert_eq!((bus.get_long(0x01FD), bus.get_long(0x01FC)), (0x03, 0x0A));
+        assert_eq!(step(&mut reg, &mut bus), 6);
+        assert_eq!((bus.get_long(0x01FB), bus.get_long(0x01FA)), (0x56, 0x78));
+        assert_eq!(step(&mut reg, &mut bus), 4);
+        assert_eq!(step(&mut reg, &mut bus), 5);
+        assert!(reg.status.flag(StatusRegister::ZERO));
+        assert_eq!(reg.stack, 0x01F9);
+    }
+
+    #[test]
+    fn block_move() {
+        let mut bus = setup(&[
+            0x54, 0x7E, 0x01, // MVN $01,$7E
+            0xEA,             // NOP
+        ]);
+        load(&mut bus, 0x011000, &[0x11, 0x22, 0x33]);
+        let mut reg = Registers { a: 0x0002, x: 0x1000, y: 0x2000, ..native() };
+
+        // Each byte is an instruction of its own, repeating until A runs out
+        for _ in 0..3 {
+            assert_eq!(reg.pc, 0x0200);
+            assert_eq!(step(&mut reg, &mut bus), 7);
+        }
+        assert_eq!(reg.pc, 0x0203);
+        assert_eq!((reg.a, reg.x, reg.y, reg.data_bank), (0xFFFF, 0x1003, 0x2003, 0x7E));
+        assert_eq!(bus.get_long(0x7E2000), 0x11);
+        assert_eq!(bus.get_long(0x7E2002), 0x33);
+
+        // MVP works down from the end
+        let mut bus = setup(&[0x44, 0x7E, 0x01]); // MVP $01,$7E
+        load(&mut bus, 0x011000, &[0x11, 0x22, 0x33]);
+        let mut reg = Registers { a: 0x0002, x: 0x1002, y: 0x2002, ..native() };
+        for _ in 0..3 {
+            step(&mut reg, &mut bus);
+        }
+        assert_eq!((reg.x, reg.y), (0x0FFF, 0x1FFF));
+        assert_eq!(bus.get_long(0x7E2000), 0x11);
+        assert_eq!(bus.get_long(0x7E2002), 0x33);
+    }
+
+    #[test]
+    fn decimal() {
+        let mut bus = setup(&[
+            0x69, 0x21, 0x43, // ADC #$4321
+            0x69, 0x45, 0x44, // ADC #$4445
+            0xE9, 0x01, 0x00, // SBC #$0001
+        ]);
+        let mut reg = Registers { a: 0x1234, ..native() };
+        reg.status.set_flag(StatusRegister::DECIMAL_MODE);
+
+        step(&mut reg, &mut bus);
+        assert_eq!(reg.a, 0x5555);
+        assert!(!reg.status.flag(StatusRegister::CARRY));
+
+        step(&mut reg, &mut bus);
+        assert_eq!(reg.a, 0x0000);
+        assert!(reg.status.flag(StatusRegister::CARRY));
+        assert!(reg.status.flag(StatusRegister::ZERO));
+
+        step(&mut reg, &mut bus);
+        assert_eq!(reg.a, 0x9999);
+        assert!(!reg.status.flag(StatusRegister::CARRY));
+        assert!(reg.status.flag(StatusRegister::NEGATIVE));
+    }
+
+    #[test]
+    fn branches() {
+        let mut bus = setup(&[0x80, 0xFC]); // BRA *-2
+        load(&mut bus, 0x0001FE, &[0x82, 0x00, 0x10]); // BRL *+$1003
+        let mut reg = emulation();
+
+        // Crossing a page costs a cycle in emulation mode only
+        assert_eq!(step(&mut reg, &mut bus), 4);
+        assert_eq!(reg.pc, 0x01FE);
+        assert_eq!(step(&mut reg, &mut bus), 4);
+        assert_eq!(reg.pc, 0x1201);
+
+        let mut reg = native();
+        assert_eq!(step(&mut reg, &mut bus), 3);
+    }
+
+    #[test]
+    fn indexed_jumps() {
+        let mut bus = setup(&[0xFC, 0x00, 0x80]); // JSR ($8000,X)
+        load(&mut bus, 0x018002, &[0x34, 0x12]);
+        let mut reg = Registers { x: 0x0002, ..native() };
+        reg.program_bank = 0x01;
+        load(&mut bus, 0x010200, &[0xFC, 0x00, 0x80]);
+
+        // The pointer is read from the program bank
+        assert_eq!(step(&mut reg, &mut bus), 8);
+        assert_eq!(reg.program_counter(), 0x011234);
+        assert_eq!((bus.get_long(0x01FF), bus.get_long(0x01FE)), (0x02, 0x02));
+    }
+
+    #[test]
+    fn brk() {
+        let mut bus = setup(&[0x00, 0xEA]); // BRK
+        load(&mut bus, 0x00FFE6, &[0x00, 0x40]);
+        load(&mut bus, 0x00FFFE, &[0x00, 0x50]);
+        load(&mut bus, 0x014000, &[0x40]);  // RTI
+
+        let mut reg = Registers { program_bank: 0x01, ..native() };
+        load(&mut bus, 0x010200, &[0x00, 0xEA]);
+        reg.status.set_flag(StatusRegister::DECIMAL_MODE);
+        assert_eq!(step(&mut reg, &mut bus), 8);
+        assert_eq!(reg.program_counter(), 0x004000);
+        assert_eq!(bus.get_long(0x01FF), 0x01);
+        assert!(reg.status.flag(StatusRegister::IRQ_DISABLE));
+        assert!(!reg.status.flag(StatusRegister::DECIMAL_MODE));
+
+        load(&mut bus, 0x004000, &[0x40]);
+        assert_eq!(step(&mut reg, &mut bus), 7);
+        assert_eq!(reg.program_counter(), 0x010202);
+        assert!(reg.status.flag(StatusRegister::DECIMAL_MODE));
+
+        // Emulation mode has no program bank to push, and BRK shares the IRQ vector
+        let mut reg = emulation();
+        assert_eq!(step(&mut reg, &mut bus), 7);
+        assert_eq!(reg.pc, 0x5000);
+        assert_eq!(bus.get_long(0x01FD) & StatusRegister::BRK_COMMAND.bits(), StatusRegister::BRK_COMMAND.bits());
+    }
+
+    #[test]
+    fn wai_stp() {
+        let mut bus = setup(&[0xCB, 0xDB]);
+        let mut reg = native();
+
+        assert_eq!(execute(&mut reg, &mut bus), (0xCB, InstructionState::Waiting, 3));
+        assert_eq!(execute(&mut reg, &mut bus), (0xDB, InstructionState::Jammed, 3));
+    }
+}
@@ -1,29 +1,242 @@
+use std::fmt;
+use std::ops::BitOr;
+
+/// One or more of P's bits, as named by the StatusRegister constants. Combine them with `|`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Flags(u8);
+
+impl Flags {
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// P, the processor status register. Only six of its bits are flags the CPU keeps: B and bit 5 don't exist in
+/// the register itself, and only appear in the copy of P pushed on the stack. The register keeps them clear, and
+/// for_push and from_pull apply the hardware's rules as P goes on and off the stack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct StatusRegister {
-    pub flags: u8,
+    bits: u8,
 }
 
 impl StatusRegister {
-    pub const CARRY: u8 =        0b00000001;
-    pub const ZERO: u8 =         0b00000010;
-    pub const IRQ_DISABLE: u8 =  0b00000100;
-    pub const DECIMAL_MODE: u8 = 0b00001000;
-    pub const BRK_COMMAND: u8 =  0b00010000;
-    pub const OVERFLOW: u8 =     0b01000000;
-    pub const NEGATIVE: u8 =     0b10000000;
+    pub const CARRY: Flags =        Flags(0b00000001);
+    pub const ZERO: Flags =         Flags(0b00000010);
+    pub const IRQ_DISABLE: Flags =  Flags(0b00000100);
+    pub const DECIMAL_MODE: Flags = Flags(0b00001000);
+    /// Only on the stack: set when P was pushed by PHP or BRK, and clear when pushed by IRQ or NMI
+    pub const BRK_COMMAND: Flags =  Flags(0b00010000);
+    /// Only on the stack, where it's always set
+    pub const UNUSED: Flags =       Flags(0b00100000);
+    pub const OVERFLOW: Flags =     Flags(0b01000000);
+    pub const NEGATIVE: Flags =     Flags(0b10000000);
+
+    // The 65816 in native mode uses the B and unused bits to select 8-bit (set) or 16-bit (clear) registers
+    pub const INDEX_SELECT: Flags = Self::BRK_COMMAND;
+    pub const MEMORY_SELECT: Flags = Self::UNUSED;
+
+    /// P loaded with a byte as it stands, without the stack's rules for B and bit 5.
+    pub fn new(bits: u8) -> Self {
+        Self { bits }
+    }
+
+    /// P as it stands in the register. Traces that show P as a byte want for_push(false) instead, which is how
+    /// the hardware presents it.
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    pub fn flag(&self, flag: Flags) -> bool {
+        self.bits & flag.0 == flag.0
+    }
+
+    pub fn set_flag(&mut self, flag: Flags) {
+        self.bits |= flag.0;
+    }
+
+    pub fn clear_flag(&mut self, flag: Flags) {
+        self.bits &= !flag.0;
+    }
+
+    /// Set a flag if the condition holds, and clear it if it doesn't.
+    pub fn update_flag(&mut self, flag: Flags, set: bool) {
+        if set {
+            self.set_flag(flag);
+        } else {
+            self.clear_flag(flag);
+        }
+    }
+
+    pub fn carry(&self) -> bool {
+        self.flag(Self::CARRY)
+    }
+
+    pub fn set_carry(&mut self, set: bool) {
+        self.update_flag(Self::CARRY, set);
+    }
+
+    pub fn zero(&self) -> bool {
+        self.flag(Self::ZERO)
+    }
+
+    pub fn set_zero(&mut self, set: bool) {
+        self.update_flag(Self::ZERO, set);
+    }
+
+    pub fn irq_disable(&self) -> bool {
+        self.flag(Self::IRQ_DISABLE)
+    }
+
+    pub fn set_irq_disable(&mut self, set: bool) {
+        self.update_flag(Self::IRQ_DISABLE, set);
+    }
 
-    pub fn new(flags: u8) -> Self {
-        Self { flags }
+    pub fn decimal_mode(&self) -> bool {
+        self.flag(Self::DECIMAL_MODE)
     }
 
-    pub fn flag(&self, flag: u8) -> bool {
-        self.flags & flag == flag
+    pub fn set_decimal_mode(&mut self, set: bool) {
+        self.update_flag(Self::DECIMAL_MODE, set);
     }
 
-    pub fn set_flag(&mut self, flag: u8) {
-        self.flags |= flag;
+    pub fn overflow(&self) -> bool {
+        self.flag(Self::OVERFLOW)
+    }
+
+    pub fn set_overflow(&mut self, set: bool) {
+        self.update_flag(Self::OVERFLOW, set);
+    }
+
+    pub fn negative(&self) -> bool {
+        self.flag(Self::NEGATIVE)
+    }
+
+    pub fn set_negative(&mut self, set: bool) {
+        self.update_flag(Self::NEGATIVE, set);
+    }
+
+    /// Set N and Z from a result, as almost every instruction that produces one does.
+    pub fn set_zero_negative(&mut self, value: u8) {
+        self.set_zero(value == 0);
+        self.set_negative(value & 0b10000000 != 0);
+    }
+
+    /// Set C, N and Z from comparing a register with a value, as CMP, CPX and CPY do.
+    pub fn set_compare(&mut self, register: u8, value: u8) {
+        self.set_carry(register >= value);
+        self.set_zero_negative(register.wrapping_sub(value));
+    }
+
+    /// Set C and V from a binary addition of a and value, given the 9-bit total it came to.
+    pub fn set_carry_overflow(&mut self, a: u8, value: u8, total: u16) {
+        let result = total as u8;
+        self.set_carry(total > 0xFF);
+        self.set_overflow((a ^ result) & (value ^ result) & 0b10000000 != 0);
+    }
+
+    /// Set N and V from bits 7 and 6 of the value, and Z from ANDing it with A, as BIT does.
+    pub fn set_bit_test(&mut self, a: u8, value: u8) {
+        self.set_zero(a & value == 0);
+        self.set_negative(value & 0b10000000 != 0);
+        self.set_overflow(value & 0b01000000 != 0);
+    }
+
+    /// P as it's pushed on the stack. Bit 5 is always set, and B is set by PHP and BRK but not by IRQ or NMI.
+    pub fn for_push(&self, break_command: bool) -> u8 {
+        match break_command {
+            true => self.bits | (Self::BRK_COMMAND | Self::UNUSED).0,
+            false => (self.bits | Self::UNUSED.0) & !Self::BRK_COMMAND.0,
+        }
+    }
+
+    /// P as it's pulled off the stack by PLP and RTI, which ignore the B and unused bits.
+    pub fn from_pull(value: u8) -> Self {
+        Self::new(value & !(Self::BRK_COMMAND | Self::UNUSED).0)
+    }
+}
+
+/// P in the `NV-BDIZC` style traces use: a flag's letter is upper case when it's set and lower case when it's
+/// clear. Bit 5 isn't a flag, so it's always shown as `-`.
+impl fmt::Display for StatusRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (bit, letter) in "NV-BDIZC".chars().enumerate() {
+            let set = self.bits & (0b10000000 >> bit) != 0;
+            let shown = match letter {
+                '-' => '-',
+                _ if set => letter,
+                _ => letter.to_ascii_lowercase(),
+            };
+            write!(f, "{}", shown)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::status_register::StatusRegister;
+
+    #[test]
+    fn flags() {
+        let mut status = StatusRegister::new(0);
+        status.set_carry(true);
+        status.set_decimal_mode(true);
+        status.set_negative(true);
+        assert_eq!(status.bits(), 0b10001001);
+        assert!(status.carry() && status.decimal_mode() && status.negative());
+        assert!(!status.zero() && !status.irq_disable() && !status.overflow());
+
+        status.set_carry(false);
+        status.update_flag(StatusRegister::OVERFLOW | StatusRegister::ZERO, true);
+        assert_eq!(status.bits(), 0b11001010);
+    }
+
+    #[test]
+    fn results() {
+        let mut status = StatusRegister::new(0);
+        status.set_zero_negative(0x00);
+        assert_eq!(status.bits(), StatusRegister::ZERO.bits());
+        status.set_zero_negative(0x80);
+        assert_eq!(status.bits(), StatusRegister::NEGATIVE.bits());
+
+        status.set_compare(0x10, 0x20);
+        assert_eq!(status.bits(), StatusRegister::NEGATIVE.bits());
+        status.set_compare(0x20, 0x20);
+        assert_eq!(status.bits(), (StatusRegister::ZERO | StatusRegister::CARRY).bits());
+
+        // $50 + $50 overflows into the sign bit, $FF + $01 carries out
+        status.set_carry_overflow(0x50, 0x50, 0x00A0);
+        assert!(status.overflow() && !status.carry());
+        status.set_carry_overflow(0xFF, 0x01, 0x0100);
+        assert!(!status.overflow() && status.carry());
+
+        status.set_bit_test(0x01, 0xC0);
+        assert!(status.zero() && status.negative() && status.overflow());
+    }
+
+    #[test]
+    fn push_and_pull() {
+        let status = StatusRegister::new((StatusRegister::CARRY | StatusRegister::NEGATIVE).bits());
+        assert_eq!(status.for_push(true), 0b10110001);
+        assert_eq!(status.for_push(false), 0b10100001);
+
+        // B and bit 5 never make it into the register
+        assert_eq!(StatusRegister::from_pull(0xFF).bits(), 0b11001111);
+        assert_eq!(StatusRegister::from_pull(status.for_push(true)), status);
     }
 
-    pub fn clear_flag(&mut self, flag: u8) {
-        self.flags &= flag ^ 0b11111111;
+    #[test]
+    fn display() {
+        assert_eq!(StatusRegister::new(0x00).to_string(), "nv-bdizc");
+        assert_eq!(StatusRegister::new(0xFF).to_string(), "NV-BDIZC");
+        assert_eq!(StatusRegister::new(0b10000101).to_string(), "Nv-bdIzC");
     }
 }
@@ -0,0 +1,53 @@
+/// The members of the 6502 family CPU6502 can run as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variant {
+    /// The original NMOS 6502, undocumented opcodes, bugs and all.
+    Mos6502,
+    /// The WDC W65C02S. The CMOS part adds instructions and addressing modes, fixes the JMP ($xxFF) bug, sets N
+    /// and Z properly in decimal mode, and turns every undocumented opcode into a NOP.
+    Wdc65C02,
+    /// The NES's Ricoh 2A03, an NMOS 6502 with the decimal adjustment cut out. D can still be set and cleared,
+    /// but ADC, SBC and the undocumented opcodes built on them always work in binary.
+    Ricoh2A03,
+    /// The Atari 2600's 6507, an NMOS 6502 in a smaller package with only 13 address lines. Everything it
+    /// reads or writes lands in $0000-$1FFF.
+    Mos6507,
+    /// The C64's 6510, an NMOS 6502 with an I/O port built in. The data direction register is at $0000 and the
+    /// port itself is at $0001, in front of whatever the bus has there.
+    Mos6510,
+}
+
+impl Variant {
+    pub fn cmos(&self) -> bool {
+        matches!(self, Variant::Wdc65C02)
+    }
+
+    /// Whether ADC and SBC pay any attention to D.
+    pub fn decimal_mode(&self) -> bool {
+        !matches!(self, Variant::Ricoh2A03)
+    }
+
+    /// The address lines the variant has, as a mask over the addresses it works out.
+    pub fn address_mask(&self) -> u16 {
+        match self {
+            Variant::Mos6507 => 0x1FFF,
+            _ => 0xFFFF,
+        }
+    }
+
+    /// Whether the variant has the 6510's I/O port at $0000/$0001.
+    pub fn io_port(&self) -> bool {
+        matches!(self, Variant::Mos6510)
+    }
+
+    /// Whether JMP ($xxFF) reads the high byte of its target from $xx00, as the NMOS parts do. They don't carry
+    /// into the high byte of the pointer, and the 65C02 does.
+    pub fn jmp_indirect_wraps(&self) -> bool {
+        !self.cmos()
+    }
+
+    /// Whether the variant sees the bus exactly as the CPU addresses it, with nothing masked or in the way.
+    pub fn direct_bus(&self) -> bool {
+        self.address_mask() == 0xFFFF && !self.io_port()
+    }
+}
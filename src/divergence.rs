@@ -0,0 +1,510 @@
+use crate::cpu::status_register::StatusRegister;
+use crate::trace::BusAccess;
+
+/*
+ * Finding where two traces of the same program part ways. A trace can be in any of these formats, which are told
+ * apart by their first line:
+ *
+ * - the emulator's own, with a line a cycle (see trace.rs):
+ *   CYC:12 R C5F7 86 SYNC PC:C5F7 A:00 X:00 Y:00 P:26 SP:FD
+ * - nestest.log's, with a line an instruction:
+ *   C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12
+ * - a bus capture as CSV, with a line a cycle: the cycle, the address and data in hex, R or W (or the level of the
+ *   R/W pin, 1 for a read), and optionally the level of SYNC. A header line is skipped.
+ *   12,C5F7,86,R,1
+ *
+ * The traces are lined up by instruction, using the lines that start one, or cycle by cycle, and compared on
+ * whatever both of them record. Cycle counts are compared from the start of each trace, so traces that started
+ * counting in different places still line up.
+ */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    R6502,
+    Nestest,
+    Csv,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alignment {
+    Instruction,
+    Cycle,
+}
+
+/// A line of a trace, with whatever it records.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Record {
+    /// The line of the trace it came from, counting from 1
+    pub line: usize,
+    pub text: String,
+    pub cycle: Option<usize>,
+    /// Whether the line starts an instruction, if the trace says
+    pub sync: Option<bool>,
+    pub pc: Option<u16>,
+    pub a: Option<u8>,
+    pub x: Option<u8>,
+    pub y: Option<u8>,
+    pub p: Option<u8>,
+    pub sp: Option<u8>,
+    pub access: Option<BusAccess>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trace {
+    pub format: Format,
+    pub records: Vec<Record>,
+}
+
+/// Something two lined up records disagree on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub field: &'static str,
+    pub left: String,
+    pub right: String,
+}
+
+/// Where two traces first disagree: the position in each once they're lined up, and how they differ there.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub index: usize,
+    pub differences: Vec<Difference>,
+}
+
+impl Trace {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim_end()))
+            .filter(|(_, line)| !line.is_empty())
+            .peekable();
+        let format = match lines.peek() {
+            Some((number, line)) => {
+                detect(line).ok_or_else(|| format!("line {number}: can't tell what format {line:?} is in"))?
+            },
+            None => return Err("the trace is empty".to_string()),
+        };
+
+        let mut records = vec![];
+        for (number, line) in lines {
+            let record = match format {
+                Format::R6502 => parse_r6502(line),
+                Format::Nestest => parse_nestest(line),
+                // A header is anything before the first line that starts with a cycle count
+                Format::Csv if records.is_empty() && !line.starts_with(|c: char| c.is_ascii_digit()) => continue,
+                Format::Csv => parse_csv(line),
+            };
+            let record = record.map_err(|error| format!("line {number}: {error}"))?;
+            records.push(Record { line: number, text: line.to_string(), ..record });
+        }
+        Ok(Self { format, records })
+    }
+
+    /// The records that line up with the other trace's, one an instruction or one a cycle.
+    fn aligned(&self, alignment: Alignment) -> Result<Vec<&Record>, String> {
+        match (alignment, self.format) {
+            (Alignment::Cycle, Format::Nestest) => Err("a nestest trace can only be lined up by instruction".into()),
+            (Alignment::Cycle, _) => Ok(self.records.iter().collect()),
+            (Alignment::Instruction, _) if self.records.iter().all(|record| record.sync.is_none()) => {
+                Err("a bus capture without SYNC can only be lined up by cycle".into())
+            },
+            (Alignment::Instruction, _) => Ok(self.records.iter().filter(|record| record.sync == Some(true)).collect()),
+        }
+    }
+}
+
+fn detect(line: &str) -> Option<Format> {
+    let bytes = line.as_bytes();
+    if bytes.len() > 6 && bytes[..4].iter().all(u8::is_ascii_hexdigit) && &bytes[4..6] == b"  " {
+        Some(Format::Nestest)
+    } else if line.starts_with("CYC:") {
+        Some(Format::R6502)
+    } else if line.contains(',') {
+        Some(Format::Csv)
+    } else {
+        None
+    }
+}
+
+fn hex_u8(value: &str) -> Result<u8, String> {
+    u8::from_str_radix(value, 16).map_err(|_| format!("{value:?} isn't a hex byte"))
+}
+
+fn hex_u16(value: &str) -> Result<u16, String> {
+    let digits = value.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("{value:?} isn't a hex address"))
+}
+
+fn decimal(value: &str) -> Result<usize, String> {
+    value.parse().map_err(|_| format!("{value:?} isn't a cycle count"))
+}
+
+/// Fill in a record from `name:value` fields, ignoring any it doesn't know and any without a value.
+fn parse_fields<'a>(record: &mut Record, fields: impl Iterator<Item = &'a str>) -> Result<(), String> {
+    for (name, value) in fields.filter_map(|field| field.split_once(':')) {
+        match (name, value) {
+            (_, "") => {},
+            ("CYC", value) => record.cycle = Some(decimal(value)?),
+            ("PC", value) => record.pc = Some(hex_u16(value)?),
+            ("A", value) => record.a = Some(hex_u8(value)?),
+            ("X", value) => record.x = Some(hex_u8(value)?),
+            ("Y", value) => record.y = Some(hex_u8(value)?),
+            ("P", value) => record.p = Some(hex_u8(value)?),
+            ("SP", value) => record.sp = Some(hex_u8(value)?),
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+fn parse_r6502(line: &str) -> Result<Record, String> {
+    let mut fields = line.split_whitespace().peekable();
+    let mut record = Record::default();
+    parse_fields(&mut record, fields.next().into_iter())?;
+
+    let access = (fields.next(), fields.next(), fields.next());
+    record.access = match access {
+        (Some("-"), Some(_), Some(_)) => None,
+        (Some(direction @ ("R" | "W")), Some(address), Some(value)) => {
+            Some(BusAccess { address: hex_u16(address)?, value: hex_u8(value)?, write: direction == "W" })
+        },
+        _ => return Err("expected a bus access after the cycle".to_string()),
+    };
+    record.sync = Some(fields.next_if_eq(&"SYNC").is_some());
+    parse_fields(&mut record, fields)?;
+    Ok(record)
+}
+
+fn parse_nestest(line: &str) -> Result<Record, String> {
+    let pc = line.get(..4).ok_or("expected a PC at the start of the line")?;
+    let mut record = Record { pc: Some(hex_u16(pc)?), sync: Some(true), ..Record::default() };
+    // The registers come after the disassembly, which never has a colon in it
+    let registers = line.find(" A:").ok_or("expected registers after the disassembly")?;
+    parse_fields(&mut record, line[registers..].split_whitespace())?;
+    Ok(record)
+}
+
+fn parse_csv(line: &str) -> Result<Record, String> {
+    let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+    let [cycle, address, value, direction, rest @ ..] = columns.as_slice() else {
+        return Err("expected cycle, address, data and R/W".to_string());
+    };
+    let write = match *direction {
+        "R" | "r" | "1" => false,
+        "W" | "w" | "0" => true,
+        _ => return Err(format!("{direction:?} isn't R or W")),
+    };
+    let sync = match rest.first() {
+        Some(&"1") => Some(true),
+        Some(&"0") => Some(false),
+        Some(sync) => return Err(format!("{sync:?} isn't a level for SYNC")),
+        None => None,
+    };
+    Ok(Record {
+        cycle: Some(decimal(cycle)?),
+        sync,
+        // When SYNC is up the CPU is fetching the opcode at PC
+        pc: if sync == Some(true) { Some(hex_u16(address)?) } else { None },
+        access: Some(BusAccess { address: hex_u16(address)?, value: hex_u8(value)?, write }),
+        ..Record::default()
+    })
+}
+
+fn show_access(access: &BusAccess) -> String {
+    format!("{} {:04X} {:02X}", if access.write { 'W' } else { 'R' }, access.address, access.value)
+}
+
+fn show_p(p: u8) -> String {
+    format!("{p:02X} ({})", StatusRegister::new(p))
+}
+
+/// What two lined up records disagree on, counting cycles from each trace's first record.
+fn compare(left: (&Record, usize), right: (&Record, usize)) -> Vec<Difference> {
+    let (left, left_start) = left;
+    let (right, right_start) = right;
+    let mut differences = vec![];
+    let mut check = |field, left: Option<String>, right: Option<String>| {
+        if let (Some(left), Some(right)) = (left, right) {
+            if left != right {
+                differences.push(Difference { field, left, right });
+            }
+        }
+    };
+
+    let cycles = |record: &Record, start| record.cycle.map(|cycle| cycle.wrapping_sub(start).to_string());
+    check("cycles in", cycles(left, left_start), cycles(right, right_start));
+    check("PC", left.pc.map(|pc| format!("{pc:04X}")), right.pc.map(|pc| format!("{pc:04X}")));
+    for (field, left, right) in [("A", left.a, right.a), ("X", left.x, right.x), ("Y", left.y, right.y)] {
+        check(field, left.map(|value| format!("{value:02X}")), right.map(|value| format!("{value:02X}")));
+    }
+    check("P", left.p.map(show_p), right.p.map(show_p));
+    check("SP", left.sp.map(|sp| format!("{sp:02X}")), right.sp.map(|sp| format!("{sp:02X}")));
+    check("bus", left.access.as_ref().map(show_access), right.access.as_ref().map(show_access));
+    differences
+}
+
+/// The first place two traces disagree once they're lined up, if they do. One running out before the other does
+/// counts.
+pub fn find(left: &Trace, right: &Trace, alignment: Alignment) -> Result<Option<Divergence>, String> {
+    let left = left.aligned(alignment).map_err(|error| format!("left: {error}"))?;
+    let right = right.aligned(alignment).map_err(|error| format!("right: {error}"))?;
+    let start = |records: &[&Record]| records.first().and_then(|record| record.cycle).unwrap_or(0);
+    let (left_start, right_start) = (start(&left), start(&right));
+
+    for (index, (l, r)) in left.iter().zip(&right).enumerate() {
+        let differences = compare((l, left_start), (r, right_start));
+        if !differences.is_empty() {
+            return Ok(Some(Divergence { index, differences }));
+        }
+    }
+    if left.len() != right.len() {
+        let unit = match alignment {
+            Alignment::Instruction => "instructions",
+            Alignment::Cycle => "cycles",
+        };
+        return Ok(Some(Divergence {
+            index: left.len().min(right.len()),
+            differences: vec![Difference {
+                field: "length",
+                left: format!("{} {unit}", left.len()),
+                right: format!("{} {unit}", right.len()),
+            }],
+        }));
+    }
+    Ok(None)
+}
+
+/// Describe a divergence, with the lines of each trace around it and what differs.
+pub fn report(left: &Trace, right: &Trace, alignment: Alignment, divergence: &Divergence, context: usize) -> String {
+    let unit = match alignment {
+        Alignment::Instruction => "instruction",
+        Alignment::Cycle => "cycle",
+    };
+    let mut report = format!("First divergence at {unit} {}\n", divergence.index + 1);
+
+    for (side, trace) in [("left", left), ("right", right)] {
+        report.push_str(&format!("\n{side}:\n"));
+        // Both traces lined up when the divergence was found, so they still do
+        let records = trace.aligned(alignment).unwrap_or_default();
+        let first = divergence.index.saturating_sub(context);
+        for (index, record) in records.iter().enumerate().skip(first).take(divergence.index + context + 1 - first) {
+            let marker = if index == divergence.index { '>' } else { ' ' };
+            report.push_str(&format!("{marker} {:>6} | {}\n", record.line, record.text));
+        }
+        if records.len() <= divergence.index {
+            report.push_str(&format!("> {:>6} | <end of trace>\n", ""));
+        }
+    }
+
+    report.push_str("\ndifferences (left vs right):\n");
+    for difference in &divergence.differences {
+        report.push_str(&format!("  {}: {} vs {}\n", difference.field, difference.left, difference.right));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::cpu_6502::CPU6502;
+    use crate::cpu::status_register::StatusRegister;
+    use crate::cpu::variant::Variant;
+    use crate::divergence::{find, report, Alignment, Difference, Divergence, Format, Record, Trace};
+    use crate::trace::{load_nes, nestest, BusAccess, TracingBus};
+
+    /// LDX #$00, then a loop that counts X up and stores it in $10
+    fn program() -> Vec<u8> {
+        let mut image = b"NES\x1a\x01\x01\x00\x00".to_vec();
+        image.resize(16 + 0x4000, 0);
+        image[16..16 + 7].copy_from_slice(&[0xA2, 0x00, 0xE8, 0x86, 0x10, 0xD0, 0xFB]);
+        image
+    }
+
+    /// The emulator's own trace of the program, over as many cycles as it takes to start the given number of
+    /// instructions, started the way nestest is
+    fn r6502_trace(instructions: usize) -> String {
+        let mut bus = TracingBus::new(load_nes(&program()).unwrap());
+        let mut cpu = CPU6502::with_variant(Variant::Ricoh2A03);
+        cpu.registers.pc = 0xC000;
+        cpu.registers.stack = 0xFD;
+        cpu.registers.status = StatusRegister::from_pull(0x24);
+
+        let mut lines = vec![];
+        let mut started = 0;
+        for cycle in 7.. {
+            started += cpu.at_boundary() as usize;
+            if started > instructions {
+                break;
+            }
+            lines.push(bus.tick(&mut cpu, cycle).1);
+        }
+        lines.join("\n")
+    }
+
+    /// The bus accesses in a trace of the emulator's own, as a CSV capture
+    fn csv(trace: &Trace) -> String {
+        let mut csv = "cycle,address,data,rw,sync\n".to_string();
+        for record in &trace.records {
+            let access = record.access.unwrap();
+            let direction = if access.write { 'W' } else { 'R' };
+            let sync = record.sync.unwrap() as u8;
+            csv.push_str(&format!("{},{:04X},{:02X},{direction},{sync}\n", record.cycle.unwrap(), access.address,
+                                  access.value));
+        }
+        csv
+    }
+
+    #[test]
+    fn formats() {
+        let trace = Trace::parse("CYC:12 R C5F7 86 SYNC PC:C5F7 A:01 X:02 Y:03 P:26 SP:FD\n").unwrap();
+        assert_eq!(trace.format, Format::R6502);
+        assert_eq!(
+            trace.records,
+            [Record {
+                line: 1,
+                text: "CYC:12 R C5F7 86 SYNC PC:C5F7 A:01 X:02 Y:03 P:26 SP:FD".to_string(),
+                cycle: Some(12),
+                sync: Some(true),
+                pc: Some(0xC5F7),
+                a: Some(0x01),
+                x: Some(0x02),
+                y: Some(0x03),
+                p: Some(0x26),
+                sp: Some(0xFD),
+                access: Some(BusAccess { address: 0xC5F7, value: 0x86, write: false }),
+            }]
+        );
+        let trace = Trace::parse("CYC:13 - ---- --      PC:C5F8 A:01 X:02 Y:03 P:26 SP:FD").unwrap();
+        assert_eq!((trace.records[0].sync, trace.records[0].access), (Some(false), None));
+
+        let line = "C5F7  86 00     STX $00 = 00                    A:01 X:02 Y:03 P:26 SP:FD PPU:  0, 36 CYC:12";
+        let trace = Trace::parse(line).unwrap();
+        assert_eq!(trace.format, Format::Nestest);
+        let record = &trace.records[0];
+        assert_eq!(
+            (record.cycle, record.sync, record.pc, record.a, record.x, record.y, record.p, record.sp, record.access),
+            (Some(12), Some(true), Some(0xC5F7), Some(1), Some(2), Some(3), Some(0x26), Some(0xFD), None)
+        );
+
+        let trace = Trace::parse("cycle,address,data,rw\n\n12, $C5F7, 86, 1\n13,0x0010,05,W\n").unwrap();
+        assert_eq!(trace.format, Format::Csv);
+        let records = trace.records.iter().map(|record| (record.line, record.cycle, record.sync, record.access));
+        assert_eq!(
+            records.collect::<Vec<_>>(),
+            [
+                (3, Some(12), None, Some(BusAccess { address: 0xC5F7, value: 0x86, write: false })),
+                (4, Some(13), None, Some(BusAccess { address: 0x0010, value: 0x05, write: true })),
+            ]
+        );
+
+        assert_eq!(Trace::parse("\n\n"), Err("the trace is empty".to_string()));
+        assert_eq!(Trace::parse("hello"), Err("line 1: can't tell what format \"hello\" is in".to_string()));
+        assert_eq!(Trace::parse("1,2,3,R\n2,2,3,X"), Err("line 2: \"X\" isn't R or W".to_string()));
+        assert_eq!(Trace::parse("CYC:1 Q"), Err("line 1: expected a bus access after the cycle".to_string()));
+        let pc = Err("line 2: expected a PC at the start of the line".to_string());
+        assert_eq!(Trace::parse(&format!("{line}\nab")), pc);
+        assert_eq!(Trace::parse(&format!("{line}\nC5Fé")), pc);
+    }
+
+    #[test]
+    fn same_program() {
+        let own = Trace::parse(&r6502_trace(20)).unwrap();
+        let nestest = Trace::parse(&nestest(&program(), 20).unwrap().join("\n")).unwrap();
+        let capture = Trace::parse(&csv(&own)).unwrap();
+
+        assert_eq!(find(&own, &nestest, Alignment::Instruction), Ok(None));
+        assert_eq!(find(&nestest, &own, Alignment::Instruction), Ok(None));
+        assert_eq!(find(&own, &capture, Alignment::Instruction), Ok(None));
+        assert_eq!(find(&own, &capture, Alignment::Cycle), Ok(None));
+    }
+
+    #[test]
+    fn registers() {
+        let own = Trace::parse(&r6502_trace(20)).unwrap();
+        // Have the other emulator get INX wrong the third time round
+        let mut lines = nestest(&program(), 20).unwrap();
+        lines[8] = lines[8].replace("X:03", "X:04").replace("P:24", "P:A6");
+        let nestest = Trace::parse(&lines.join("\n")).unwrap();
+
+        let divergence = find(&own, &nestest, Alignment::Instruction).unwrap().unwrap();
+        assert_eq!(divergence.index, 8);
+        assert_eq!(
+            divergence.differences,
+            [
+                Difference { field: "X", left: "03".to_string(), right: "04".to_string() },
+                Difference { field: "P", left: "24 (nv-bdIzc)".to_string(), right: "A6 (Nv-bdIZc)".to_string() },
+            ]
+        );
+        let report = report(&own, &nestest, Alignment::Instruction, &divergence, 1);
+        let nestest_lines: Vec<&str> = report.lines().skip_while(|line| *line != "right:").skip(1).take(3).collect();
+        assert!(report.starts_with("First divergence at instruction 9\n"));
+        assert_eq!(nestest_lines, [
+            format!("       8 | {}", lines[7]),
+            format!(">      9 | {}", lines[8]),
+            format!("      10 | {}", lines[9]),
+        ]);
+        assert!(report.ends_with("\
+differences (left vs right):
+  X: 03 vs 04
+  P: 24 (nv-bdIzc) vs A6 (Nv-bdIZc)
+"));
+    }
+
+    #[test]
+    fn cycles() {
+        let own = Trace::parse(&r6502_trace(20)).unwrap();
+        // The capture saw the third store write something else, a cycle late
+        let csv = csv(&own).replacen(",0010,03,W,", ",0010,FF,W,", 1);
+        let mut capture = Trace::parse(&csv).unwrap();
+        let store = capture.records.iter().position(|record| record.access.unwrap().value == 0xFF).unwrap();
+        for record in &mut capture.records[store..] {
+            record.cycle = record.cycle.map(|cycle| cycle + 1);
+        }
+
+        let divergence = find(&own, &capture, Alignment::Cycle).unwrap().unwrap();
+        assert_eq!(divergence.index, store);
+        assert_eq!(
+            divergence.differences,
+            [
+                Difference {
+                    field: "cycles in",
+                    left: store.to_string(),
+                    right: (store + 1).to_string(),
+                },
+                Difference { field: "bus", left: "W 0010 03".to_string(), right: "W 0010 FF".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn lengths() {
+        let own = Trace::parse(&r6502_trace(20)).unwrap();
+        let nestest = Trace::parse(&nestest(&program(), 12).unwrap().join("\n")).unwrap();
+        let divergence = find(&own, &nestest, Alignment::Instruction).unwrap().unwrap();
+        assert_eq!(
+            divergence,
+            Divergence {
+                index: 12,
+                differences: vec![Difference {
+                    field: "length",
+                    left: "20 instructions".to_string(),
+                    right: "12 instructions".to_string(),
+                }],
+            }
+        );
+        assert!(report(&own, &nestest, Alignment::Instruction, &divergence, 1).contains(">        | <end of trace>\n"));
+    }
+
+    #[test]
+    fn alignments() {
+        let nestest = Trace::parse(&nestest(&program(), 2).unwrap().join("\n")).unwrap();
+        let capture = Trace::parse("0,C000,A2,R\n1,C001,00,R").unwrap();
+        assert_eq!(
+            find(&nestest, &capture, Alignment::Cycle),
+            Err("left: a nestest trace can only be lined up by instruction".to_string())
+        );
+        assert_eq!(
+            find(&nestest, &capture, Alignment::Instruction),
+            Err("right: a bus capture without SYNC can only be lined up by cycle".to_string())
+        );
+    }
+}
@@ -4,3 +4,20 @@ pub trait IODevice {
     fn put(&mut self, addr: u16, value: u8);
     fn put_hl(&mut self, high: u8, low: u8, value: u8);
 }
+
+/// A bus with the 65816's 24-bit addresses, where the top eight bits pick the bank. Anything with 16-bit
+/// addresses works as one, seeing the same 64k in every bank.
+pub trait LongIODevice {
+    fn get_long(&self, addr: u32) -> u8;
+    fn put_long(&mut self, addr: u32, value: u8);
+}
+
+impl<T: IODevice + ?Sized> LongIODevice for T {
+    fn get_long(&self, addr: u32) -> u8 {
+        self.get(addr as u16)
+    }
+
+    fn put_long(&mut self, addr: u32, value: u8) {
+        self.put(addr as u16, value);
+    }
+}
@@ -1,29 +1,91 @@
 pub mod address_decoder;
+pub mod bench;
 pub mod cpu;
 pub mod clock;
+pub mod divergence;
 pub mod io_device;
 pub mod memory;
+pub mod trace;
+
+#[cfg(test)]
+mod single_step;
+#[cfg(test)]
+mod test_suites;
 
 use crate::address_decoder::AddressDecoder;
 use crate::clock::Clock;
-use crate::cpu::cpu_6502::CPU6502;
-use crate::io_device::IODevice;
+use crate::cpu::cpu_6502::{CPU6502, ExecutionMode, ExecutionState};
 use crate::memory::ram::RAM;
 use crate::memory::rom::ROM;
+use crate::trace::TracingBus;
 
-fn print_state(cpu: &CPU6502, address_line: &AddressDecoder) {
-    println!("Status register: {:#010b}", cpu.registers.status.flags);
-    println!("Accumulator: {:#04x}", cpu.registers.a);
-    println!("Data bus: {:#04x}", cpu.registers.data);
-    println!("ADH/ADL: {:#04x} {:#04x}", cpu.registers.adh, cpu.registers.adl);
-    println!("Contents of 0x6100 to 0x6102: {:#04x} {:#04x} {:#04x}",
-             address_line.get(0x6100),
-             address_line.get(0x6101),
-             address_line.get(0x6102)
-    );
+/// `r6502 diff <left> <right> [--cycle] [--context N]` finds where two traces part ways, lining them up by
+/// instruction unless told to go cycle by cycle. Returns whether they do, or what was wrong with the arguments or
+/// the traces.
+fn diff(args: &[String]) -> Result<bool, String> {
+    let usage = "usage: r6502 diff <left> <right> [--cycle] [--context N]";
+    let read = |path: Option<&String>| {
+        let path = path.ok_or(usage)?;
+        let text = std::fs::read_to_string(path).map_err(|error| format!("error reading {path}: {error}"))?;
+        divergence::Trace::parse(&text).map_err(|error| format!("{path}: {error}"))
+    };
+    let (left, right) = (read(args.get(2))?, read(args.get(3))?);
+    let alignment = match args.iter().any(|arg| arg == "--cycle") {
+        true => divergence::Alignment::Cycle,
+        false => divergence::Alignment::Instruction,
+    };
+    let context = match args.iter().position(|arg| arg == "--context") {
+        Some(index) => args.get(index + 1).and_then(|context| context.parse().ok()).ok_or(usage)?,
+        None => 3,
+    };
+
+    match divergence::find(&left, &right, alignment)? {
+        Some(found) => {
+            print!("{}", divergence::report(&left, &right, alignment, &found, context));
+            Ok(true)
+        },
+        None => {
+            println!("The traces agree");
+            Ok(false)
+        },
+    }
 }
 
 fn main() {
+    // `r6502 bench [cycles] [--functional]` measures how fast the core runs instead
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("bench") {
+        let cycles = args.get(2).and_then(|cycles| cycles.parse().ok()).unwrap_or(100_000_000);
+        let mode = match args.iter().any(|arg| arg == "--functional") {
+            true => ExecutionMode::Functional,
+            false => ExecutionMode::CycleAccurate,
+        };
+        bench::run(cycles, mode);
+        return;
+    }
+
+    // It exits with 1 if the traces part ways, and 2 if it couldn't compare them
+    if args.get(1).map(String::as_str) == Some("diff") {
+        match diff(&args) {
+            Ok(false) => return,
+            Ok(true) => std::process::exit(1),
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(2);
+            },
+        }
+    }
+
+    // `r6502 nestest <rom> [lines]` prints the trace of nestest.nes in automation mode, in the format of nestest.log
+    if args.get(1).map(String::as_str) == Some("nestest") {
+        let image = std::fs::read(args.get(2).expect("usage: r6502 nestest <rom> [lines]")).expect("error reading rom");
+        let lines = args.get(3).and_then(|lines| lines.parse().ok()).unwrap_or(usize::MAX);
+        for line in trace::nestest(&image, lines).expect("error loading rom") {
+            println!("{line}");
+        }
+        return;
+    }
+
     let clock = Clock::new(1000000.0);
     let mut address_line = AddressDecoder::new();
     let mut cpu = CPU6502::new();
@@ -35,16 +97,29 @@ fn main() {
         ),
     );
     
+    // The ROM calls the program with a JSR $0000, so that when it returns it lands on a JAM and stops.
+    // Everything else is filled with JAMs too.
+    let mut rom = [0x02; 0x8000];
+    rom[0x0000..0x0003].copy_from_slice(&[0x20, 0x00, 0x00]);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
     address_line.add_device(
         0x8000..=0xFFFF,
-        Box::new(ROM::<0x8000>::new(Some([255; 0x8000]))),
+        Box::new(ROM::<0x8000>::new(Some(rom))),
     );
 
-    // Go until we reach an opcode we don't know how to run
+    cpu.reset();
+
+    // Go until the CPU stops, printing a line of the trace a cycle
+    let mut bus = TracingBus::new(address_line);
+    let mut cycle = 0;
     clock.start(|| {
-        println!("\n=== Clock Cycle ===");
-        let cont = cpu.tick(&mut address_line);
-        print_state(&cpu, &address_line);
-        cont
+        let (state, line) = bus.tick(&mut cpu, cycle);
+        println!("{line}");
+        cycle += 1;
+        if state != ExecutionState::Running {
+            eprintln!("Stopped: {state:?}");
+        }
+        state == ExecutionState::Running
     });
 }
@@ -26,13 +26,17 @@ impl <const N: usize> Memory<N> {
     pub fn set_from_file(&mut self, filename: &str) -> Result<(), Box<dyn std::error::Error + 'static>> {
         let contents = fs::read(filename)?;
 
-        // TODO: is there a way to do this in chunks, or with something like memcpy?
-        for i in 0..contents.len() {
-            self.contents[i] = contents[i];
-        }
+        let len = contents.len().min(N);
+        self.contents[..len].copy_from_slice(&contents[..len]);
         
         Ok(())
     }
+
+    /// Index into the contents buffer for an address. Buffers smaller than the address space are mirrored
+    /// across it, rather than panicking on addresses past the end.
+    pub fn index(addr: u16) -> usize {
+        addr as usize % N
+    }
 }
 
 /// Convert an address represented by seperate high and low u8 values into a u16 value.
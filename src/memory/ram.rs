@@ -21,20 +21,20 @@ impl <const N: usize> RAM<N> {
 
 impl <const N: usize> IODevice for RAM<N> {
     fn get(&self, addr: u16) -> u8 {
-        self.memory.contents[addr as usize]
+        self.memory.contents[Memory::<N>::index(addr)]
     }
 
     fn get_hl(&self, high: u8, low: u8) -> u8 {
-        self.memory.contents[hl_to_addr(high, low) as usize]
+        self.memory.contents[Memory::<N>::index(hl_to_addr(high, low))]
 
     }
 
     fn put(&mut self, addr: u16, value: u8) {
-        self.memory.contents[addr as usize] = value;
+        self.memory.contents[Memory::<N>::index(addr)] = value;
     }
 
     fn put_hl(&mut self, high: u8, low: u8, value: u8) {
-        self.memory.contents[hl_to_addr(high, low) as usize] = value;
+        self.memory.contents[Memory::<N>::index(hl_to_addr(high, low))] = value;
     }
 }
 
@@ -96,4 +96,12 @@ mod tests {
         ram.put_hl(0xFF, 0xFF, 255);
         assert_eq!(ram.get(0xFFFF), 255);
     }
+
+    #[test]
+    fn mirrored() {
+        let mut ram = RAM::<0x0800>::new(None);
+        ram.put(0x0801, 255);
+        assert_eq!(ram.get(0x0001), 255);
+        assert_eq!(ram.get_hl(0xF8, 0x01), 255);
+    }
 }
@@ -21,11 +21,11 @@ impl <const N: usize> ROM<N> {
 
 impl <const N: usize> IODevice for ROM<N> {
     fn get(&self, addr: u16) -> u8 {
-        self.memory.contents[addr as usize]
+        self.memory.contents[Memory::<N>::index(addr)]
     }
 
     fn get_hl(&self, high: u8, low: u8) -> u8 {
-        self.memory.contents[hl_to_addr(high, low) as usize]
+        self.memory.contents[Memory::<N>::index(hl_to_addr(high, low))]
 
     }
 
@@ -88,4 +88,11 @@ mod tests {
         rom.put_hl(0xFF, 0xFF, 255);
         assert_eq!(rom.get(0xFFFF), 0);
     }
+
+    #[test]
+    fn mirrored() {
+        let rom = ROM::<0x1000>::new(Some([255; 0x1000]));
+        assert_eq!(rom.get(0x1000), 255);
+        assert_eq!(rom.get_hl(0xFF, 0xFF), 255);
+    }
 }
@@ -0,0 +1,435 @@
+/*
+ * The SingleStepTests (formerly ProcessorTests) cases for the 6502 family, run against the core a cycle at a time.
+ * Each opcode has a file of JSON cases, each giving the registers and RAM before and after the instruction, and
+ * every bus access it makes along the way:
+ *
+ * { "name": "a9 42 00", "initial": { "pc": 512, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[512, 169], ...] },
+ *   "final": { ... }, "cycles": [[512, 169, "read"], [513, 66, "read"]] }
+ *
+ * The cases come from https://github.com/SingleStepTests/65x02, and go in test_bin/single_step with a directory
+ * per variant, named as they are there, and a file per opcode: test_bin/single_step/6502/a9.json. Only the first
+ * CASES_PER_OPCODE cases in each file are run, so a subset can be vendored. Every opcode needs its file, and one
+ * that's missing fails the test.
+ */
+
+use crate::cpu::cpu_6502::CPU6502;
+use crate::cpu::opcode_info;
+use crate::cpu::status_register::StatusRegister;
+use crate::cpu::variant::Variant;
+use crate::io_device::IODevice;
+use crate::memory::ram::RAM;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+
+const CASES_PER_OPCODE: usize = 100;
+
+/// The variants the cases cover, with the directories they're kept in. The 6507 and 6510 are left out: the
+/// cases use the whole 64k, and the 6510's I/O port would answer at $0000 and $0001.
+const VARIANTS: [(Variant, &str); 3] = [
+    (Variant::Mos6502, "6502"),
+    (Variant::Wdc65C02, "wdc65c02"),
+    (Variant::Ricoh2A03, "nes6502"),
+];
+
+// JSON, as much of it as the cases use
+
+#[derive(Clone, Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { bytes: text.as_bytes(), position: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        match parser.position == parser.bytes.len() {
+            true => Ok(value),
+            false => Err(parser.error("trailing characters")),
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<&Json, String> {
+        match self {
+            Json::Object(fields) => fields.get(key).ok_or(format!("missing {key}")),
+            _ => Err(format!("looking up {key} in something that isn't an object")),
+        }
+    }
+
+    fn number(&self) -> Result<i64, String> {
+        match self {
+            Json::Number(number) => Ok(*number),
+            _ => Err(format!("expected a number, found {self:?}")),
+        }
+    }
+
+    fn array(&self) -> Result<&[Json], String> {
+        match self {
+            Json::Array(items) => Ok(items),
+            _ => Err(format!("expected an array, found {self:?}")),
+        }
+    }
+
+    fn string(&self) -> Result<&str, String> {
+        match self {
+            Json::String(string) => Ok(string),
+            _ => Err(format!("expected a string, found {self:?}")),
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, what: &str) -> String {
+        format!("{what} at byte {}", self.position)
+    }
+
+    fn whitespace(&mut self) {
+        while self.bytes.get(self.position).is_some_and(|byte| byte.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.whitespace();
+        self.bytes.get(self.position).copied()
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), String> {
+        match self.bytes[self.position..].starts_with(literal.as_bytes()) {
+            true => {
+                self.position += literal.len();
+                Ok(())
+            },
+            false => Err(self.error(&format!("expected {literal}"))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.expect("true").map(|_| Json::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some(b'n') => self.expect("null").map(|_| Json::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        let mut fields = BTreeMap::new();
+        self.expect("{")?;
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.whitespace();
+            let key = self.string()?;
+            self.whitespace();
+            self.expect(":")?;
+            fields.insert(key, self.value()?);
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(fields));
+                },
+                _ => return Err(self.error("expected , or }")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        let mut items = Vec::new();
+        self.expect("[")?;
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(items));
+                },
+                _ => return Err(self.error("expected , or ]")),
+            }
+        }
+    }
+
+    /// Strings, with the simple escapes. The cases only use strings for names and access directions.
+    fn string(&mut self) -> Result<String, String> {
+        self.expect("\"")?;
+        let mut string = String::new();
+        loop {
+            let Some(&byte) = self.bytes.get(self.position) else {
+                return Err(self.error("unterminated string"));
+            };
+            self.position += 1;
+            match byte {
+                b'"' => return Ok(string),
+                b'\\' => {
+                    let escaped = self.bytes.get(self.position).copied();
+                    self.position += 1;
+                    match escaped {
+                        Some(b'n') => string.push('\n'),
+                        Some(b't') => string.push('\t'),
+                        Some(byte @ (b'"' | b'\\' | b'/')) => string.push(byte as char),
+                        _ => return Err(self.error("unsupported escape")),
+                    }
+                },
+                _ => string.push(byte as char),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        if self.bytes[self.position] == b'-' {
+            self.position += 1;
+        }
+        while self.bytes.get(self.position).is_some_and(u8::is_ascii_digit) {
+            self.position += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.position]).map_err(|error| error.to_string())?;
+        text.parse().map(Json::Number).map_err(|_| self.error("expected an integer"))
+    }
+}
+
+// The cases
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Access {
+    address: u16,
+    value: u8,
+    direction: Direction,
+}
+
+#[derive(Debug)]
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+impl State {
+    fn from_json(json: &Json) -> Result<State, String> {
+        let byte = |key| json.get(key).and_then(Json::number).map(|number| number as u8);
+        let ram = json.get("ram")?.array()?.iter().map(|entry| {
+            let entry = entry.array()?;
+            match entry {
+                [address, value] => Ok((address.number()? as u16, value.number()? as u8)),
+                _ => Err(format!("expected [address, value], found {entry:?}")),
+            }
+        });
+        Ok(State {
+            pc: json.get("pc")?.number()? as u16,
+            s: byte("s")?,
+            a: byte("a")?,
+            x: byte("x")?,
+            y: byte("y")?,
+            p: byte("p")?,
+            ram: ram.collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+struct Case {
+    name: String,
+    initial: State,
+    last: State,
+    cycles: Vec<Access>,
+}
+
+impl Case {
+    fn from_json(json: &Json) -> Result<Case, String> {
+        let cycles = json.get("cycles")?.array()?.iter().map(|cycle| match cycle.array()? {
+            [address, value, direction] => Ok(Access {
+                address: address.number()? as u16,
+                value: value.number()? as u8,
+                direction: match direction.string()? {
+                    "read" => Direction::Read,
+                    "write" => Direction::Write,
+                    other => return Err(format!("unknown direction {other}")),
+                },
+            }),
+            cycle => Err(format!("expected [address, value, direction], found {cycle:?}")),
+        });
+        Ok(Case {
+            name: json.get("name")?.string()?.to_string(),
+            initial: State::from_json(json.get("initial")?)?,
+            last: State::from_json(json.get("final")?)?,
+            cycles: cycles.collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// 64k of RAM that keeps a log of every access made through it.
+struct RecordingBus {
+    ram: RAM<0x10000>,
+    log: RefCell<Vec<Access>>,
+}
+
+impl IODevice for RecordingBus {
+    fn get(&self, addr: u16) -> u8 {
+        let value = self.ram.get(addr);
+        self.log.borrow_mut().push(Access { address: addr, value, direction: Direction::Read });
+        value
+    }
+
+    fn get_hl(&self, high: u8, low: u8) -> u8 {
+        self.get(u16::from_le_bytes([low, high]))
+    }
+
+    fn put(&mut self, addr: u16, value: u8) {
+        self.log.borrow_mut().push(Access { address: addr, value, direction: Direction::Write });
+        self.ram.put(addr, value);
+    }
+
+    fn put_hl(&mut self, high: u8, low: u8, value: u8) {
+        self.put(u16::from_le_bytes([low, high]), value);
+    }
+}
+
+/// Run a case on a variant a tick at a time, returning what differed from what it expected if anything did.
+fn run(case: &Case, variant: Variant) -> Result<(), String> {
+    let mut ram = RAM::<0x10000>::new(None);
+    for (address, value) in &case.initial.ram {
+        ram.put(*address, *value);
+    }
+    let mut bus = RecordingBus { ram, log: RefCell::new(Vec::new()) };
+
+    let mut cpu = CPU6502::with_variant(variant);
+    let initial = &case.initial;
+    cpu.registers.pc = initial.pc;
+    cpu.registers.stack = initial.s;
+    (cpu.registers.a, cpu.registers.x, cpu.registers.y) = (initial.a, initial.x, initial.y);
+    cpu.registers.status = StatusRegister::from_pull(initial.p);
+
+    for _ in 0..case.cycles.len() {
+        cpu.tick(&mut bus);
+    }
+
+    let log = bus.log.borrow();
+    for (cycle, expected) in case.cycles.iter().enumerate() {
+        if log.get(cycle) != Some(expected) {
+            return Err(format!("{}: cycle {cycle} should be {expected:?}, was {:?}", case.name, log.get(cycle)));
+        }
+    }
+    if !cpu.at_boundary() {
+        return Err(format!("{}: still running after {} cycles", case.name, case.cycles.len()));
+    }
+
+    // P is compared without B and bit 5, which only exist on the stack
+    let last = &case.last;
+    let reg = &cpu.registers;
+    let expected = (last.pc, last.s, last.a, last.x, last.y, StatusRegister::from_pull(last.p).bits());
+    let actual = (reg.pc, reg.stack, reg.a, reg.x, reg.y, reg.status.bits());
+    if expected != actual {
+        return Err(format!("{}: (PC, S, A, X, Y, P) should be {expected:02x?}, was {actual:02x?}", case.name));
+    }
+    for (address, value) in &last.ram {
+        let actual = bus.ram.get(*address);
+        if actual != *value {
+            return Err(format!("{}: {address:#06x} should be {value:#04x}, was {actual:#04x}", case.name));
+        }
+    }
+    Ok(())
+}
+
+/// Run the cases in a file, returning how many ran along with every one that failed.
+fn run_file(json: &str, variant: Variant) -> Result<(usize, Vec<String>), String> {
+    let cases = Json::parse(json)?;
+    let cases = cases.array()?;
+    let mut failures = Vec::new();
+    for case in cases.iter().take(CASES_PER_OPCODE) {
+        if let Err(failure) = run(&Case::from_json(case)?, variant) {
+            failures.push(failure);
+        }
+    }
+    Ok((cases.len().min(CASES_PER_OPCODE), failures))
+}
+
+#[test]
+fn parse() {
+    let json = Json::parse(r#"{ "a": [1, -2, "three\""], "b": { "c": true, "d": null } }"#).unwrap();
+    assert_eq!(json.get("a").unwrap().array().unwrap()[1], Json::Number(-2));
+    assert_eq!(json.get("a").unwrap().array().unwrap()[2], Json::String("three\"".to_string()));
+    assert_eq!(json.get("b").unwrap().get("c").unwrap(), &Json::Bool(true));
+    assert!(Json::parse("[1, 2").is_err());
+    assert!(Json::parse("[1] 2").is_err());
+}
+
+#[test]
+fn case() {
+    // LDA $1234,X crossing into $1300, so it reads $1200 first
+    let lda = r#"[{
+        "name": "bd ff 12",
+        "initial": { "pc": 512, "s": 253, "a": 0, "x": 1, "y": 0, "p": 36,
+                     "ram": [[512, 189], [513, 255], [514, 18], [4608, 17], [4864, 128]] },
+        "final": { "pc": 515, "s": 253, "a": 128, "x": 1, "y": 0, "p": 164,
+                   "ram": [[512, 189], [513, 255], [514, 18], [4608, 17], [4864, 128]] },
+        "cycles": [[512, 189, "read"], [513, 255, "read"], [514, 18, "read"], [4608, 17, "read"], [4864, 128, "read"]]
+    }]"#;
+    assert_eq!(run_file(lda, Variant::Mos6502), Ok((1, vec![])));
+
+    // The 65C02 rereads the operand instead of reading from the wrong page
+    let (ran, failures) = run_file(lda, Variant::Wdc65C02).unwrap();
+    assert_eq!(ran, 1);
+    assert_eq!(failures, [
+        "bd ff 12: cycle 3 should be Access { address: 4608, value: 17, direction: Read }, \
+         was Some(Access { address: 514, value: 18, direction: Read })",
+    ]);
+}
+
+#[test]
+fn single_step_tests() {
+    let mut failures = Vec::new();
+    for (variant, directory) in VARIANTS {
+        for opcode in 0..=0xff {
+            // JAM, STP and WAI never finish, so there's nothing to compare
+            if matches!(opcode_info::lookup(opcode, variant).mnemonic, "JAM" | "STP" | "WAI") {
+                continue;
+            }
+            let path = format!("test_bin/single_step/{directory}/{opcode:02x}.json");
+            let json = match fs::read_to_string(&path) {
+                Ok(json) => json,
+                Err(error) => {
+                    failures.push(format!("{path} can't be read ({error})"));
+                    continue;
+                },
+            };
+            let (cases, failed) = run_file(&json, variant).unwrap_or_else(|error| panic!("{path}: {error}"));
+            if cases == 0 {
+                failures.push(format!("{path} has no cases"));
+            }
+            failures.extend(failed.into_iter().map(|failure| format!("{path}: {failure}")));
+        }
+    }
+    assert!(failures.is_empty(), "{} failures:\n{}", failures.len(), failures.join("\n"));
+}
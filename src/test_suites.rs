@@ -0,0 +1,162 @@
+/*
+ * Klaus Dormann's 6502 test suites, run against the core. They're assembled from
+ * https://github.com/Klaus2m5/6502_65C02_functional_tests with each listing's default options, and the binaries
+ * go in test_bin. The addresses below are those the default options give; anything assembled differently needs
+ * them checked against its listing.
+ *
+ * Each suite runs until the CPU traps in a jump or branch to itself. A suite that traps anywhere other than where
+ * it should has failed, and the test it was on is reported along with the state of the CPU. A suite whose binary
+ * isn't in test_bin fails too.
+ */
+
+use crate::address_decoder::AddressDecoder;
+use crate::cpu::cpu_6502::{CPU6502, ExecutionState};
+use crate::io_device::IODevice;
+use crate::memory::ram::RAM;
+use std::fs;
+
+/// How a suite shows it has passed.
+enum Passed {
+    /// It traps at this address.
+    At(u16),
+    /// It stops with this byte clear.
+    Clear(u16),
+}
+
+struct Suite {
+    file: &'static str,
+    /// Where the image is loaded. The images run from where they're loaded.
+    load: u16,
+    start: u16,
+    passed: Passed,
+    /// The byte the suite keeps the number of the test it's on in
+    test_case: u16,
+    /// The feedback register that drives IRQ (bit 0) and NMI (bit 1), for suites that need it
+    interrupt_port: Option<u16>,
+}
+
+/// More than any of the suites takes to run, so one that never traps still fails.
+const CYCLE_LIMIT: usize = 200_000_000;
+
+/// Run a suite from its binary in test_bin, returning a description of how it failed if it did.
+fn run(suite: &Suite) -> Result<(), String> {
+    let path = format!("test_bin/{}", suite.file);
+    match fs::read(&path) {
+        Ok(image) => run_image(suite, &image),
+        Err(error) => Err(format!("{path} can't be read ({error}): assemble it with the default options")),
+    }
+}
+
+fn run_image(suite: &Suite, image: &[u8]) -> Result<(), String> {
+    let mut bus = AddressDecoder::new();
+    bus.add_device(0x0000..=0xFFFF, Box::new(RAM::<0x10000>::new(None)));
+    for (offset, byte) in image.iter().enumerate() {
+        bus.put(suite.load.wrapping_add(offset as u16), *byte);
+    }
+
+    let mut cpu = CPU6502::new();
+    cpu.registers.pc = suite.start;
+
+    let mut cycles = 0;
+    let trapped = loop {
+        let step = cpu.step(&mut bus);
+        if let Some(port) = suite.interrupt_port {
+            let lines = bus.get(port);
+            cpu.set_irq(lines & 0b01 != 0);
+            cpu.set_nmi(lines & 0b10 != 0);
+        }
+
+        match step {
+            Ok(step) if step.interrupt.is_none() && cpu.registers.pc == step.address => break step.address,
+            Ok(step) => cycles += step.cycles,
+            Err(ExecutionState::Halted { pc, .. }) => break pc,
+            Err(state) => return Err(describe(suite, &cpu, &bus, &format!("stopped with {state:?}"))),
+        }
+        if cycles > CYCLE_LIMIT {
+            return Err(describe(suite, &cpu, &bus, "never trapped"));
+        }
+    };
+
+    match suite.passed {
+        Passed::At(address) if trapped == address => Ok(()),
+        Passed::Clear(address) if bus.get(address) == 0 => Ok(()),
+        _ => Err(describe(suite, &cpu, &bus, &format!("trapped at {trapped:#06x}"))),
+    }
+}
+
+fn describe(suite: &Suite, cpu: &CPU6502, bus: &AddressDecoder, what: &str) -> String {
+    let reg = &cpu.registers;
+    format!(
+        "{} {what} on test {:#04x}: PC {:#06x} A {:#04x} X {:#04x} Y {:#04x} S {:#04x} P {}",
+        suite.file,
+        bus.get(suite.test_case),
+        reg.pc,
+        reg.a,
+        reg.x,
+        reg.y,
+        reg.stack,
+        reg.status,
+    )
+}
+
+#[test]
+fn trap() {
+    // LDA #$05, STA $0200, JMP $0405
+    let image = [0xa9, 0x05, 0x8d, 0x00, 0x02, 0x4c, 0x05, 0x04];
+    let mut suite = Suite {
+        file: "trap.bin",
+        load: 0x0400,
+        start: 0x0400,
+        passed: Passed::At(0x0405),
+        test_case: 0x0200,
+        interrupt_port: None,
+    };
+    assert_eq!(run_image(&suite, &image), Ok(()));
+
+    suite.passed = Passed::At(0x1234);
+    assert_eq!(
+        run_image(&suite, &image),
+        Err("trap.bin trapped at 0x0405 on test 0x05: PC 0x0405 A 0x05 X 0x00 Y 0x00 S 0x00 P nv-bdizc".to_string())
+    );
+}
+
+#[test]
+fn functional_test() {
+    let suite = Suite {
+        file: "6502_functional_test.bin",
+        load: 0x0000,
+        start: 0x0400,
+        passed: Passed::At(0x3469),
+        test_case: 0x0200,
+        interrupt_port: None,
+    };
+    run(&suite).unwrap();
+}
+
+#[test]
+fn decimal_test() {
+    // The decimal test keeps a 1 in ERROR until every combination of operands and carry has checked out. It
+    // doesn't number its tests, so the first operand stands in for one.
+    let suite = Suite {
+        file: "6502_decimal_test.bin",
+        load: 0x0200,
+        start: 0x0200,
+        passed: Passed::Clear(0x000B),
+        test_case: 0x0000,
+        interrupt_port: None,
+    };
+    run(&suite).unwrap();
+}
+
+#[test]
+fn interrupt_test() {
+    let suite = Suite {
+        file: "6502_interrupt_test.bin",
+        load: 0x0000,
+        start: 0x0400,
+        passed: Passed::At(0x06F5),
+        test_case: 0x0200,
+        interrupt_port: Some(0xBFFC),
+    };
+    run(&suite).unwrap();
+}
@@ -0,0 +1,435 @@
+use crate::address_decoder::AddressDecoder;
+use crate::cpu::cpu_6502::{CPU6502, ExecutionState, Registers};
+use crate::cpu::opcode_info::{self, AddressingMode};
+use crate::cpu::status_register::StatusRegister;
+use crate::cpu::variant::Variant;
+use crate::io_device::IODevice;
+use crate::memory::ram::RAM;
+use crate::memory::rom::ROM;
+use std::cell::Cell;
+
+/*
+ * Execution traces in the format of nestest.log, which most 6502 emulators can write and compare against:
+ *
+ * C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+ *
+ * Each line is written before the instruction at PC runs: its address and bytes, its disassembly with the
+ * addresses and values it's about to use, the registers, the PPU position and the cycles run so far. There's no
+ * PPU here, so its position is worked out from the cycles the way it runs on the NES, three dots to a cycle with
+ * rendering off.
+ */
+
+/// The bytes of the instruction at an address, as many as it takes up.
+pub fn instruction_bytes(address: u16, bus: &dyn IODevice, variant: Variant) -> Vec<u8> {
+    let length = opcode_info::lookup(bus.get(address), variant).length;
+    (0..length as u16).map(|offset| bus.get(address.wrapping_add(offset))).collect()
+}
+
+/// Disassemble the instruction at an address the way nestest.log does, with the addresses it works out from the
+/// registers and the values it finds there. Undocumented opcodes are marked with a `*`.
+pub fn disassemble(address: u16, reg: &Registers, bus: &dyn IODevice, variant: Variant) -> String {
+    let bytes = instruction_bytes(address, bus, variant);
+    let info = opcode_info::lookup(bytes[0], variant);
+    let operand = bytes.get(1).copied().unwrap_or(0);
+    let word = u16::from_le_bytes([operand, bytes.get(2).copied().unwrap_or(0)]);
+    let zero_page_word = |pointer: u8| {
+        u16::from_le_bytes([bus.get(pointer as u16), bus.get(pointer.wrapping_add(1) as u16)])
+    };
+    let next = address.wrapping_add(info.length as u16);
+
+    let operand = match info.mode {
+        AddressingMode::Implied => String::new(),
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::Immediate => format!("#${operand:02X}"),
+        AddressingMode::ZeroPage => format!("${operand:02X} = {:02X}", bus.get(operand as u16)),
+        AddressingMode::ZeroPageX => {
+            let address = operand.wrapping_add(reg.x);
+            format!("${operand:02X},X @ {address:02X} = {:02X}", bus.get(address as u16))
+        },
+        AddressingMode::ZeroPageY => {
+            let address = operand.wrapping_add(reg.y);
+            format!("${operand:02X},Y @ {address:02X} = {:02X}", bus.get(address as u16))
+        },
+        AddressingMode::Absolute if matches!(info.mnemonic, "JMP" | "JSR") => format!("${word:04X}"),
+        AddressingMode::Absolute => format!("${word:04X} = {:02X}", bus.get(word)),
+        AddressingMode::AbsoluteX => {
+            let address = word.wrapping_add(reg.x as u16);
+            format!("${word:04X},X @ {address:04X} = {:02X}", bus.get(address))
+        },
+        AddressingMode::AbsoluteY => {
+            let address = word.wrapping_add(reg.y as u16);
+            format!("${word:04X},Y @ {address:04X} = {:02X}", bus.get(address))
+        },
+        AddressingMode::Indirect => {
+            let [low, high] = word.to_le_bytes();
+            let high = match variant.jmp_indirect_wraps() {
+                true => u16::from_le_bytes([low.wrapping_add(1), high]),
+                false => word.wrapping_add(1),
+            };
+            format!("(${word:04X}) = {:04X}", u16::from_le_bytes([bus.get(word), bus.get(high)]))
+        },
+        AddressingMode::IndexedIndirect => {
+            let pointer = operand.wrapping_add(reg.x);
+            let address = zero_page_word(pointer);
+            format!("(${operand:02X},X) @ {pointer:02X} = {address:04X} = {:02X}", bus.get(address))
+        },
+        AddressingMode::IndirectIndexed => {
+            let base = zero_page_word(operand);
+            let address = base.wrapping_add(reg.y as u16);
+            format!("(${operand:02X}),Y = {base:04X} @ {address:04X} = {:02X}", bus.get(address))
+        },
+        AddressingMode::ZeroPageIndirect => {
+            let address = zero_page_word(operand);
+            format!("(${operand:02X}) = {address:04X} = {:02X}", bus.get(address))
+        },
+        AddressingMode::AbsoluteIndexedIndirect => format!("(${word:04X},X)"),
+        AddressingMode::Relative => format!("${:04X}", next.wrapping_add(operand as i8 as u16)),
+        AddressingMode::ZeroPageRelative => {
+            let offset = bytes[2];
+            format!("${operand:02X},${:04X}", next.wrapping_add(offset as i8 as u16))
+        },
+    };
+
+    // nestest.log calls ISC by its other name
+    let mnemonic = match info.mnemonic {
+        "ISC" => "ISB",
+        mnemonic => mnemonic,
+    };
+    let marker = if info.legal { ' ' } else { '*' };
+    match operand.is_empty() {
+        true => format!("{marker}{mnemonic}"),
+        false => format!("{marker}{mnemonic} {operand}"),
+    }
+}
+
+/// The nestest.log line for the instruction the CPU is about to run, given the cycles it has run so far.
+pub fn nestest_line(cpu: &CPU6502, bus: &dyn IODevice, cycles: usize) -> String {
+    let reg = &cpu.registers;
+    let bytes = instruction_bytes(reg.pc, bus, cpu.variant());
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+    let dots = cycles * 3;
+    format!(
+        "{:04X}  {:<9}{:<33}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:3},{:3} CYC:{}",
+        reg.pc,
+        bytes.join(" "),
+        disassemble(reg.pc, reg, bus, cpu.variant()),
+        reg.a,
+        reg.x,
+        reg.y,
+        reg.status.for_push(false),
+        reg.stack,
+        dots / 341 % 262,
+        dots % 341,
+        cycles,
+    )
+}
+
+/*
+ * The emulator's own trace has a line for every cycle: the read or write the CPU made on the bus, SYNC if it was
+ * an opcode fetch, and the registers going into the cycle, so that a SYNC line has them the way nestest.log does:
+ *
+ * CYC:12 R C5F7 86 SYNC PC:C5F7 A:00 X:00 Y:00 P:26 SP:FD
+ *
+ * A cycle that doesn't touch the bus, because the CPU is jammed or waiting, shows `- ---- --` for the access.
+ */
+
+/// A read or write on the bus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusAccess {
+    pub address: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+/// A bus that notes the access made through it, for the per-cycle trace.
+pub struct TracingBus<T: IODevice> {
+    pub bus: T,
+    access: Cell<Option<BusAccess>>,
+}
+
+impl<T: IODevice> TracingBus<T> {
+    pub fn new(bus: T) -> Self {
+        Self { bus, access: Cell::new(None) }
+    }
+
+    /// Run a cycle, returning how it left the CPU along with its line of the trace.
+    pub fn tick(&mut self, cpu: &mut CPU6502, cycle: usize) -> (ExecutionState, String) {
+        let sync = cpu.at_boundary();
+        let registers = cpu.registers;
+        self.access.set(None);
+        let state = cpu.tick(self);
+        (state, cycle_line(cycle, self.access.get(), sync, &registers))
+    }
+}
+
+impl<T: IODevice> IODevice for TracingBus<T> {
+    fn get(&self, addr: u16) -> u8 {
+        let value = self.bus.get(addr);
+        self.access.set(Some(BusAccess { address: addr, value, write: false }));
+        value
+    }
+
+    fn get_hl(&self, high: u8, low: u8) -> u8 {
+        self.get(u16::from_le_bytes([low, high]))
+    }
+
+    fn put(&mut self, addr: u16, value: u8) {
+        self.access.set(Some(BusAccess { address: addr, value, write: true }));
+        self.bus.put(addr, value);
+    }
+
+    fn put_hl(&mut self, high: u8, low: u8, value: u8) {
+        self.put(u16::from_le_bytes([low, high]), value);
+    }
+}
+
+/// The line of the per-cycle trace for a cycle, given the registers going into it.
+pub fn cycle_line(cycle: usize, access: Option<BusAccess>, sync: bool, reg: &Registers) -> String {
+    let access = match access {
+        Some(BusAccess { address, value, write }) => {
+            format!("{} {address:04X} {value:02X}", if write { 'W' } else { 'R' })
+        },
+        None => "- ---- --".to_string(),
+    };
+    format!(
+        "CYC:{cycle} {access} {:<4} PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+        if sync { "SYNC" } else { "" },
+        reg.pc,
+        reg.a,
+        reg.x,
+        reg.y,
+        reg.status.for_push(false),
+        reg.stack,
+    )
+}
+
+/*
+ * nestest.nes runs every official and unofficial opcode the NES uses and checks the results. Started at $C000
+ * instead of through its reset vector it runs in automation mode, with no PPU needed, and nestest.log is the
+ * trace it gives on a known good emulator.
+ */
+
+/// The PPU and APU registers, which read back $FF in nestest.log.
+struct OpenBus;
+
+impl IODevice for OpenBus {
+    fn get(&self, _addr: u16) -> u8 {
+        0xFF
+    }
+
+    fn get_hl(&self, _high: u8, _low: u8) -> u8 {
+        0xFF
+    }
+
+    fn put(&mut self, _addr: u16, _value: u8) {}
+
+    fn put_hl(&mut self, _high: u8, _low: u8, _value: u8) {}
+}
+
+/// Map an iNES image with no mapper (NROM) the way the NES does: 2k of RAM mirrored up to $1FFF, the
+/// registers from $2000 to $401F, and the PRG ROM at $8000, mirrored up to $FFFF if it's only 16k.
+pub fn load_nes(image: &[u8]) -> Result<AddressDecoder, String> {
+    let header = image.get(..16).ok_or("too short for an iNES header")?;
+    if &header[..4] != b"NES\x1a" {
+        return Err("not an iNES image".to_string());
+    }
+    let mapper = (header[6] >> 4) | (header[7] & 0xF0);
+    if mapper != 0 {
+        return Err(format!("mapper {mapper} isn't supported"));
+    }
+    // A 512 byte trainer can sit between the header and PRG ROM
+    let start = if header[6] & 0x04 != 0 { 16 + 512 } else { 16 };
+    let prg = image.get(start..start + header[4] as usize * 0x4000).ok_or("PRG ROM is cut short")?;
+
+    let mut bus = AddressDecoder::new();
+    bus.add_device(0x0000..=0x1FFF, Box::new(RAM::<0x800>::new(None)));
+    bus.add_device(0x2000..=0x401F, Box::new(OpenBus));
+    match header[4] {
+        1 => bus.add_device(0x8000..=0xFFFF, Box::new(ROM::<0x4000>::new(prg.try_into().ok()))),
+        2 => bus.add_device(0x8000..=0xFFFF, Box::new(ROM::<0x8000>::new(prg.try_into().ok()))),
+        banks => return Err(format!("{banks} banks of PRG ROM is more than NROM has")),
+    }
+    Ok(bus)
+}
+
+/// Run nestest.nes in automation mode, returning up to the given number of lines of its trace. The trace ends
+/// early if the CPU stops.
+pub fn nestest(image: &[u8], lines: usize) -> Result<Vec<String>, String> {
+    let mut bus = load_nes(image)?;
+    let mut cpu = CPU6502::with_variant(Variant::Ricoh2A03);
+    cpu.registers.pc = 0xC000;
+    cpu.registers.stack = 0xFD;
+    cpu.registers.status = StatusRegister::from_pull(0x24);
+
+    // The reset sequence has already run by the time nestest.log starts
+    let mut cycles = 7;
+    let mut trace = vec![];
+    while trace.len() < lines {
+        trace.push(nestest_line(&cpu, &bus, cycles));
+        match cpu.step(&mut bus) {
+            Ok(step) => cycles += step.cycles,
+            Err(_) => break,
+        }
+    }
+    Ok(trace)
+}
+
+/// The index of the first line where two traces differ, including where one ends before the other does.
+pub fn first_divergence<A: AsRef<str>, B: AsRef<str>>(ours: &[A], theirs: &[B]) -> Option<usize> {
+    let differs = ours.iter().zip(theirs).position(|(ours, theirs)| ours.as_ref() != theirs.as_ref());
+    match differs {
+        Some(index) => Some(index),
+        None if ours.len() != theirs.len() => Some(ours.len().min(theirs.len())),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::cpu_6502::CPU6502;
+    use crate::cpu::variant::Variant;
+    use crate::io_device::IODevice;
+    use crate::trace::{disassemble, first_divergence, load_nes, nestest, TracingBus};
+    use std::fs;
+
+    /// An NROM image with one bank of PRG ROM holding the given code.
+    fn image(code: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut image = b"NES\x1a\x01\x01\x00\x00".to_vec();
+        image.resize(16 + 0x4000, 0);
+        for (address, bytes) in code {
+            let start = 16 + (*address as usize & 0x3FFF);
+            image[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+        image
+    }
+
+    #[test]
+    fn nestest_lines() {
+        // The start of nestest.log
+        let image = image(&[
+            (0xC000, &[0x4C, 0xF5, 0xC5]),
+            (0xC5F5, &[0xA2, 0x00, 0x86, 0x00, 0x86, 0x10, 0x86, 0x11, 0x20, 0x2D, 0xC7]),
+            (0xC72D, &[0xEA]),
+        ]);
+        assert_eq!(
+            nestest(&image, 7).unwrap(),
+            [
+                "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+                "C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 30 CYC:10",
+                "C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12",
+                "C5F9  86 10     STX $10 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 45 CYC:15",
+                "C5FB  86 11     STX $11 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 54 CYC:18",
+                "C5FD  20 2D C7  JSR $C72D                       A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 63 CYC:21",
+                "C72D  EA        NOP                             A:00 X:00 Y:00 P:26 SP:FB PPU:  0, 81 CYC:27",
+            ]
+        );
+    }
+
+    #[test]
+    fn nestest_annotations() {
+        // LDX #$02, LDY #$01, then each addressing mode against values set up in RAM, and a couple of
+        // undocumented opcodes. JMP ($02FF) takes its high byte from $0200, as the 2A03 does.
+        let image = image(&[
+            (0xC000, &[
+                0xA2, 0x02, 0xA0, 0x01,
+                0xB5, 0x10,
+                0xBD, 0x00, 0x02,
+                0xA1, 0x20,
+                0xB1, 0x22,
+                0x6C, 0xFF, 0x02,
+                0xC7, 0x10,
+                0x04, 0x10,
+            ]),
+        ]);
+        let mut bus = load_nes(&image).unwrap();
+        let values = [(0x12, 0x34), (0x23, 0x03), (0x0202, 0x56), (0x0301, 0x78), (0x02FF, 0x34), (0x0200, 0x12)];
+        for (address, value) in values {
+            bus.put(address, value);
+        }
+        let mut cpu = CPU6502::with_variant(Variant::Ricoh2A03);
+        cpu.registers.pc = 0xC000;
+        let mut disassembly = vec![];
+        for _ in 0..9 {
+            disassembly.push(disassemble(cpu.registers.pc, &cpu.registers, &bus, cpu.variant()));
+            // Step over the JMP rather than take it
+            match cpu.registers.pc {
+                0xC00D => cpu.registers.pc = 0xC010,
+                _ => cpu.step(&mut bus).map(|_| ()).unwrap(),
+            }
+        }
+        assert_eq!(
+            disassembly,
+            [
+                " LDX #$02",
+                " LDY #$01",
+                " LDA $10,X @ 12 = 34",
+                " LDA $0200,X @ 0202 = 56",
+                " LDA ($20,X) @ 22 = 0300 = 00",
+                " LDA ($22),Y = 0300 @ 0301 = 78",
+                " JMP ($02FF) = 1234",
+                "*DCP $10 = 00",
+                "*NOP $10 = FF",
+            ]
+        );
+    }
+
+    #[test]
+    fn cycle_lines() {
+        // LDA #$05, STA $10
+        let image = image(&[(0xC000, &[0xA9, 0x05, 0x85, 0x10])]);
+        let mut bus = TracingBus::new(load_nes(&image).unwrap());
+        let mut cpu = CPU6502::with_variant(Variant::Ricoh2A03);
+        cpu.registers.pc = 0xC000;
+        let lines: Vec<String> = (0..6).map(|cycle| bus.tick(&mut cpu, cycle).1).collect();
+        assert_eq!(
+            lines,
+            [
+                "CYC:0 R C000 A9 SYNC PC:C000 A:00 X:00 Y:00 P:20 SP:00",
+                "CYC:1 R C001 05      PC:C001 A:00 X:00 Y:00 P:20 SP:00",
+                "CYC:2 R C002 85 SYNC PC:C002 A:05 X:00 Y:00 P:20 SP:00",
+                "CYC:3 R C003 10      PC:C003 A:05 X:00 Y:00 P:20 SP:00",
+                "CYC:4 W 0010 05      PC:C004 A:05 X:00 Y:00 P:20 SP:00",
+                "CYC:5 R C004 00 SYNC PC:C004 A:05 X:00 Y:00 P:20 SP:00",
+            ]
+        );
+    }
+
+    #[test]
+    fn divergence() {
+        assert_eq!(first_divergence(&["a", "b"], &["a", "b"]), None);
+        assert_eq!(first_divergence(&["a", "b"], &["a", "c"]), Some(1));
+        assert_eq!(first_divergence(&["a"], &["a", "b"]), Some(1));
+        assert_eq!(first_divergence(&["a", "b"], &["a"]), Some(1));
+    }
+
+    #[test]
+    fn nes_images() {
+        assert_eq!(load_nes(b"NES").err(), Some("too short for an iNES header".to_string()));
+        assert_eq!(load_nes(&[0; 16]).err(), Some("not an iNES image".to_string()));
+        let mut mapped = image(&[]);
+        mapped[6] = 0x10;
+        assert_eq!(load_nes(&mapped).err(), Some("mapper 1 isn't supported".to_string()));
+        mapped.truncate(0x100);
+        mapped[6] = 0;
+        assert_eq!(load_nes(&mapped).err(), Some("PRG ROM is cut short".to_string()));
+    }
+
+    /// nestest.nes and nestest.log from https://www.qmtpro.com/~nes/misc/ go in test_bin. The trace is compared
+    /// with the log line by line, and the first line that differs is reported.
+    #[test]
+    fn nestest_log() {
+        let image = fs::read("test_bin/nestest.nes").unwrap_or_else(|error| panic!("test_bin/nestest.nes: {error}"));
+        let log = fs::read_to_string("test_bin/nestest.log")
+            .unwrap_or_else(|error| panic!("test_bin/nestest.log: {error}"));
+        let log: Vec<&str> = log.lines().map(str::trim_end).collect();
+        let trace = nestest(&image, log.len()).unwrap();
+        if let Some(line) = first_divergence(&trace, &log) {
+            panic!(
+                "nestest diverges from nestest.log at line {}\n  expected: {}\n  got:      {}",
+                line + 1,
+                log.get(line).unwrap_or(&"<end of log>"),
+                trace.get(line).map_or("<end of trace>", String::as_str),
+            );
+        }
+    }
+}